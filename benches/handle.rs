@@ -0,0 +1,37 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use httpserve::{Config, FileServer};
+use hyper::{Body, Request};
+
+fn handle(c: &mut Criterion) {
+    let sizes = [("small", 1024), ("large", 1024 * 1024)];
+    let config = Config {
+        virtual_files: sizes
+            .iter()
+            .map(|(name, size)| (format!("/{}.bin", name), vec![b'x'; *size]))
+            .collect(),
+        ..Config::default()
+    };
+    let file_server = FileServer::new(&config).expect("Unable to load the files");
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("Unable to build Tokio runtime");
+
+    let mut group = c.benchmark_group("handle");
+    for (name, size) in sizes.iter() {
+        let path = format!("/{}.bin", name);
+        group.throughput(Throughput::Bytes(*size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &path, |b, path| {
+            b.iter(|| {
+                runtime.block_on(async {
+                    let req = Request::get(path.as_str()).body(Body::empty()).unwrap();
+                    let response = file_server.handle(req).await.unwrap();
+                    hyper::body::to_bytes(response.into_body()).await.unwrap()
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, handle);
+criterion_main!(benches);
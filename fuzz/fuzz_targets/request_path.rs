@@ -0,0 +1,48 @@
+//! Request paths must never panic, and never lead outside of the served directory: the path to
+//! look up only consists of plain segments below the root. Run with
+//! `cargo fuzz run request_path`.
+
+#![no_main]
+
+use httpserve::path::request_path;
+use libfuzzer_sys::fuzz_target;
+use std::path::{Component, Path};
+
+fuzz_target!(|data: &[u8]| {
+    // Hyper only hands out request paths which are valid UTF-8
+    let raw = match std::str::from_utf8(data) {
+        Ok(raw) => raw,
+        Err(_) => return,
+    };
+    for strip_trailing_dot in [false, true] {
+        let path = match request_path(raw, strip_trailing_dot) {
+            Some(path) => path,
+            None => continue,
+        };
+        assert!(path.starts_with('/'), "{:?} became {:?}", raw, path);
+        assert!(!path.contains('\0'), "{:?} became {:?}", raw, path);
+        let segments: Vec<&str> = path[1..].split('/').collect();
+        for (i, segment) in segments.iter().enumerate() {
+            // Only a directory path ends in an empty segment
+            let is_last = i == segments.len() - 1;
+            assert!(
+                !matches!(*segment, "." | "..") && (is_last || !segment.is_empty()),
+                "{:?} became {:?}",
+                raw,
+                path
+            );
+        }
+        // This is how files are looked up on disk with `--no-cache` and `--writable`
+        let root = Path::new("/srv/www");
+        let joined = root.join(&path[1..]);
+        assert!(
+            joined.starts_with(root)
+                && joined
+                    .components()
+                    .all(|c| matches!(c, Component::RootDir | Component::Normal(_))),
+            "{:?} leads to {}",
+            raw,
+            joined.display()
+        );
+    }
+});
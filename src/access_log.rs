@@ -0,0 +1,237 @@
+//! Custom access log lines for `--access-log-format`, with directives in the style of Apache's
+//! `LogFormat`.
+
+use hyper::header::HeaderName;
+use hyper::{HeaderMap, Method, StatusCode, Uri, Version};
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// The address of the client, which the connection layer adds to the extensions of requests.
+#[derive(Clone, Copy)]
+pub struct RemoteAddr(pub SocketAddr);
+
+enum Segment {
+    Literal(String),
+    /// `%h`
+    RemoteHost,
+    /// `%m`
+    Method,
+    /// `%U`
+    Path,
+    /// `%q`, including the `?`
+    Query,
+    /// `%H`
+    Protocol,
+    /// `%r`
+    RequestLine,
+    /// `%s`
+    Status,
+    /// `%b`, which is `-` for no bytes
+    Bytes,
+    /// `%B`
+    BytesOrZero,
+    /// `%D`
+    Micros,
+    /// `%T`
+    Seconds,
+    /// `%{NAME}i`
+    RequestHeader(HeaderName),
+    /// `%{NAME}o`
+    ResponseHeader(HeaderName),
+}
+
+/// A parsed access log template, rendered once per request.
+pub struct AccessLogFormat {
+    segments: Vec<Segment>,
+}
+
+/// Everything about a request and its response which a log line can contain.
+pub struct Entry<'a> {
+    pub remote: Option<SocketAddr>,
+    pub method: &'a Method,
+    pub uri: &'a Uri,
+    pub version: Version,
+    pub request_headers: &'a HeaderMap,
+    pub status: StatusCode,
+    pub response_headers: &'a HeaderMap,
+    pub bytes: u64,
+    pub duration: Duration,
+}
+
+impl AccessLogFormat {
+    /// Parse a template like `%h "%r" %s %b %D`. Unknown directives are an error.
+    pub fn parse(template: &str) -> Result<AccessLogFormat, String> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                literal.push(c);
+                continue;
+            }
+            let segment = match chars.next() {
+                Some('%') => {
+                    literal.push('%');
+                    continue;
+                }
+                Some('h') => Segment::RemoteHost,
+                Some('m') => Segment::Method,
+                Some('U') => Segment::Path,
+                Some('q') => Segment::Query,
+                Some('H') => Segment::Protocol,
+                Some('r') => Segment::RequestLine,
+                Some('s') => Segment::Status,
+                Some('b') => Segment::Bytes,
+                Some('B') => Segment::BytesOrZero,
+                Some('D') => Segment::Micros,
+                Some('T') => Segment::Seconds,
+                Some('{') => {
+                    let name: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                    let name = HeaderName::from_bytes(name.as_bytes())
+                        .map_err(|_| format!("invalid header name in %{{{}}}", name))?;
+                    match chars.next() {
+                        Some('i') => Segment::RequestHeader(name),
+                        Some('o') => Segment::ResponseHeader(name),
+                        other => return Err(unknown(&format!("{{{}}}", name), other)),
+                    }
+                }
+                other => return Err(unknown("", other)),
+            };
+            if !literal.is_empty() {
+                segments.push(Segment::Literal(std::mem::take(&mut literal)));
+            }
+            segments.push(segment);
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(AccessLogFormat { segments })
+    }
+
+    /// The log line for `entry`. Values which aren't known, such as missing headers, are `-`.
+    pub fn render(&self, entry: &Entry) -> String {
+        let mut line = String::new();
+        for segment in &self.segments {
+            // Writing to a `String` can't fail
+            let _ = match segment {
+                Segment::Literal(literal) => write!(line, "{}", literal),
+                Segment::RemoteHost => match entry.remote {
+                    Some(remote) => write!(line, "{}", remote.ip()),
+                    None => write!(line, "-"),
+                },
+                Segment::Method => write!(line, "{}", entry.method),
+                Segment::Path => write!(line, "{}", entry.uri.path()),
+                Segment::Query => match entry.uri.query() {
+                    Some(query) => write!(line, "?{}", query),
+                    None => Ok(()),
+                },
+                Segment::Protocol => write!(line, "{:?}", entry.version),
+                Segment::RequestLine => {
+                    write!(line, "{} {} {:?}", entry.method, entry.uri, entry.version)
+                }
+                Segment::Status => write!(line, "{}", entry.status.as_u16()),
+                Segment::Bytes if entry.bytes == 0 => write!(line, "-"),
+                Segment::Bytes | Segment::BytesOrZero => write!(line, "{}", entry.bytes),
+                Segment::Micros => write!(line, "{}", entry.duration.as_micros()),
+                Segment::Seconds => write!(line, "{}", entry.duration.as_secs()),
+                Segment::RequestHeader(name) => {
+                    write_header(&mut line, entry.request_headers, name)
+                }
+                Segment::ResponseHeader(name) => {
+                    write_header(&mut line, entry.response_headers, name)
+                }
+            };
+        }
+        line
+    }
+
+    /// Whether any directive refers to request headers, which have to be kept around until the
+    /// response is logged.
+    pub fn uses_request_headers(&self) -> bool {
+        self.segments
+            .iter()
+            .any(|segment| matches!(segment, Segment::RequestHeader(_)))
+    }
+}
+
+fn unknown(prefix: &str, directive: Option<char>) -> String {
+    match directive {
+        Some(c) => format!("unknown directive %{}{}", prefix, c),
+        None => format!("incomplete directive %{} at the end", prefix),
+    }
+}
+
+fn write_header(line: &mut String, headers: &HeaderMap, name: &HeaderName) -> std::fmt::Result {
+    match headers.get(name).and_then(|value| value.to_str().ok()) {
+        Some(value) => write!(line, "{}", value),
+        None => write!(line, "-"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::{HeaderValue, CONTENT_TYPE, USER_AGENT};
+
+    fn render(template: &str, bytes: u64, uri: &str) -> String {
+        let mut request_headers = HeaderMap::new();
+        request_headers.insert(USER_AGENT, HeaderValue::from_static("curl/8.0"));
+        let mut response_headers = HeaderMap::new();
+        response_headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/html"));
+        let entry = Entry {
+            remote: Some("192.0.2.1:4711".parse().unwrap()),
+            method: &Method::GET,
+            uri: &uri.parse().unwrap(),
+            version: Version::HTTP_11,
+            request_headers: &request_headers,
+            status: StatusCode::NOT_FOUND,
+            response_headers: &response_headers,
+            bytes,
+            duration: Duration::from_micros(2_500_000),
+        };
+        AccessLogFormat::parse(template).unwrap().render(&entry)
+    }
+
+    #[test]
+    fn renders_every_directive() {
+        assert_eq!(
+            render(r#"%h "%r" %s %b %B %D %T %m %U%q %H"#, 512, "/a?b=c"),
+            r#"192.0.2.1 "GET /a?b=c HTTP/1.1" 404 512 512 2500000 2 GET /a?b=c HTTP/1.1"#
+        );
+        assert_eq!(render("%b %B %U%q", 0, "/a"), "- 0 /a");
+        assert_eq!(render("100%% %s%%", 0, "/"), "100% 404%");
+    }
+
+    #[test]
+    fn renders_headers_or_a_dash() {
+        assert_eq!(
+            render(
+                "%{User-Agent}i %{content-type}o %{X-Req}i %{User-Agent}o",
+                0,
+                "/"
+            ),
+            "curl/8.0 text/html - -"
+        );
+        assert!(AccessLogFormat::parse("%{X-Req}i")
+            .unwrap()
+            .uses_request_headers());
+        assert!(!AccessLogFormat::parse("%{X-Req}o %h")
+            .unwrap()
+            .uses_request_headers());
+    }
+
+    #[test]
+    fn rejects_unknown_and_incomplete_directives() {
+        let error = |template| AccessLogFormat::parse(template).err().unwrap();
+        assert_eq!(error("%h %x"), "unknown directive %x");
+        assert_eq!(error("%{X-Req}z"), "unknown directive %{x-req}z");
+        assert_eq!(error("%s %"), "incomplete directive % at the end");
+        assert_eq!(
+            error("%{X-Req}"),
+            "incomplete directive %{x-req} at the end"
+        );
+        assert_eq!(error("%{X Req}i"), "invalid header name in %{X Req}");
+        assert_eq!(error("%{}i"), "invalid header name in %{}");
+    }
+}
@@ -0,0 +1,160 @@
+//! A small API for operators on a port of its own, for `--admin-port`: `GET /cache/stats`
+//! summarizes the cache and `POST /cache/clear` throws it away and loads the files again, for the
+//! default host and every virtual host alike.
+
+use crate::auth::BasicAuth;
+use crate::vhost::VirtualHosts;
+use crate::Config;
+use hyper::header::{ALLOW, CACHE_CONTROL, CONTENT_TYPE, WWW_AUTHENTICATE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::{error, info, warn};
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::TcpListener;
+use std::sync::Arc;
+
+/// The admin API, for the files of all virtual hosts.
+pub struct Admin {
+    vhosts: Arc<VirtualHosts>,
+    stdin_tar: bool,
+    auth: BasicAuth,
+}
+
+impl Admin {
+    /// The admin API for `vhosts`, which were created from `config`. With `config.admin_auth`,
+    /// every request must carry those credentials.
+    pub fn new(vhosts: Arc<VirtualHosts>, config: &Config) -> Admin {
+        let credentials: Vec<(String, String)> = config
+            .admin_auth
+            .iter()
+            .map(|credentials| (String::from("/"), credentials.clone()))
+            .collect();
+        Admin {
+            vhosts,
+            stdin_tar: config.stdin_tar,
+            auth: BasicAuth::new(&credentials, false),
+        }
+    }
+
+    /// Serve the API on the bound `listener` until `shutdown` completes, then finish the requests
+    /// in progress.
+    pub async fn serve(self, listener: TcpListener, shutdown: impl Future<Output = ()>) {
+        let admin = Arc::new(self);
+        let make_svc = make_service_fn(move |_| {
+            let admin = Arc::clone(&admin);
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let admin = Arc::clone(&admin);
+                    async move { Ok::<_, Infallible>(admin.handle(req).await) }
+                }))
+            }
+        });
+        let addr = listener.local_addr();
+        let server = match Server::from_tcp(listener) {
+            Ok(builder) => builder.serve(make_svc).with_graceful_shutdown(shutdown),
+            Err(e) => {
+                error!("Unable to serve the admin API: {}", e);
+                return;
+            }
+        };
+        if let Ok(addr) = addr {
+            info!("Serving the admin API on {}", addr);
+        }
+        if let Err(e) = server.await {
+            error!("admin server error: {}", e);
+        }
+    }
+
+    async fn handle(&self, req: Request<Body>) -> Response<Body> {
+        let response = if !self.auth.is_authorized(req.uri().path(), req.headers()) {
+            Response::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .header(
+                    WWW_AUTHENTICATE,
+                    "Basic realm=\"httpserve admin\", charset=\"UTF-8\"",
+                )
+                .body(Body::empty())
+                .expect("Unable to create `http::Response`")
+        } else {
+            match (req.method(), req.uri().path()) {
+                (&Method::GET, "/cache/stats") => self.stats(),
+                (&Method::POST, "/cache/clear") => self.clear().await,
+                (_, "/cache/stats") => method_not_allowed("GET"),
+                (_, "/cache/clear") => method_not_allowed("POST"),
+                _ => Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(Body::empty())
+                    .expect("Unable to create `http::Response`"),
+            }
+        };
+        info!(
+            "admin: {} {} {}",
+            req.method(),
+            req.uri(),
+            response.status().as_u16()
+        );
+        response
+    }
+
+    fn stats(&self) -> Response<Body> {
+        let (files, bytes) =
+            self.vhosts
+                .servers()
+                .iter()
+                .fold((0, 0), |(files, bytes), (_, file_server)| {
+                    let (more_files, more_bytes) = file_server.cache_stats();
+                    (files + more_files, bytes + more_bytes)
+                });
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, "application/json")
+            .header(CACHE_CONTROL, "no-store")
+            .body(Body::from(format!(
+                "{{\"files\":{},\"bytes\":{}}}",
+                files, bytes
+            )))
+            .expect("Unable to create `http::Response`")
+    }
+
+    /// Load the files of every host again, replacing each cache once its files are loaded.
+    /// Requests are served from the previous cache in the meantime.
+    async fn clear(&self) -> Response<Body> {
+        if self.stdin_tar {
+            warn!("Not reloading the files, as the archive on standard input was read already");
+            return Response::builder()
+                .status(StatusCode::CONFLICT)
+                .body(Body::empty())
+                .expect("Unable to create `http::Response`");
+        }
+        let mut failed = false;
+        for (config, file_server) in self.vhosts.servers() {
+            let file_server = Arc::clone(file_server);
+            let config = config.clone();
+            let loaded = tokio::task::spawn_blocking(move || file_server.load(&config)).await;
+            if let Ok(Err(e)) = &loaded {
+                warn!(
+                    "Failed to reload the files, keeping the previous cache: {}",
+                    e
+                );
+            }
+            // The other hosts are still reloaded
+            failed |= !matches!(loaded, Ok(Ok(())));
+        }
+        if failed {
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::empty())
+                .expect("Unable to create `http::Response`");
+        }
+        self.stats()
+    }
+}
+
+fn method_not_allowed(allow: &'static str) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::METHOD_NOT_ALLOWED)
+        .header(ALLOW, allow)
+        .body(Body::empty())
+        .expect("Unable to create `http::Response`")
+}
@@ -0,0 +1,287 @@
+//! Serving the files of a zip or (optionally gzip-compressed) tar archive instead of a directory.
+
+use flate2::read::GzDecoder;
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Seek, SeekFrom};
+use std::path::{Component, Path};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::SourceFile;
+
+/// Returns true if the file name has an extension that we know how to unpack.
+pub fn has_archive_extension(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    [".zip", ".tar", ".tar.gz", ".tgz"]
+        .iter()
+        .any(|ext| name.ends_with(ext))
+}
+
+/// Unpack all files in the archive at `path` into a map from request path to file. The
+/// archive format is determined from the leading bytes of the file rather than its extension, so
+/// that `--archive` also works for archives with unusual names.
+pub fn load(path: &Path) -> io::Result<HashMap<String, SourceFile>> {
+    let mut file = File::open(path)?;
+    let mut magic = [0u8; 4];
+    let read = file.read(&mut magic)?;
+    file.seek(SeekFrom::Start(0))?;
+
+    if read >= 4 && magic == *b"PK\x03\x04" {
+        load_zip(file)
+    } else if read >= 2 && magic[..2] == [0x1f, 0x8b] {
+        load_tar(GzDecoder::new(file))
+    } else {
+        load_tar(file)
+    }
+}
+
+fn load_zip(file: File) -> io::Result<HashMap<String, SourceFile>> {
+    let mut cache = HashMap::new();
+    let mut archive = zip::ZipArchive::new(file)?;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        if entry.is_dir() {
+            continue;
+        }
+        let key = match normalize_entry_path(Path::new(entry.name())) {
+            Some(key) => key,
+            None => {
+                warn!("Skipping archive entry with unsafe path {}", entry.name());
+                continue;
+            }
+        };
+        let mut content = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut content)?;
+        debug!("Loaded {} bytes from {}", content.len(), key);
+        let modified = Some(zip_time(entry.last_modified()));
+        cache.insert(key, SourceFile { content, modified });
+    }
+    Ok(cache)
+}
+
+/// Unpack a tar archive, which may be compressed with gzip, as it is streamed to standard input,
+/// e.g. by `tar -c site/ | httpserve --stdin-tar`. A zip archive can't be unpacked as a stream.
+pub fn load_stdin() -> io::Result<HashMap<String, SourceFile>> {
+    if io::stdin().is_terminal() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "it's a terminal rather than a pipe",
+        ));
+    }
+    let mut stdin = BufReader::new(io::stdin().lock());
+    let magic = stdin.fill_buf()?;
+    if magic.starts_with(b"PK") {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "zip archives can't be read as a stream, only tar archives",
+        ))
+    } else if magic.starts_with(&[0x1f, 0x8b]) {
+        load_tar(GzDecoder::new(stdin))
+    } else {
+        load_tar(stdin)
+    }
+}
+
+fn load_tar<R: Read>(reader: R) -> io::Result<HashMap<String, SourceFile>> {
+    let mut cache = HashMap::new();
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let entry_path = entry.path()?;
+        let key = match normalize_entry_path(&entry_path) {
+            Some(key) => key,
+            None => {
+                warn!(
+                    "Skipping archive entry with unsafe path {}",
+                    entry_path.display()
+                );
+                continue;
+            }
+        };
+        let modified = entry
+            .header()
+            .mtime()
+            .ok()
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content)?;
+        debug!("Loaded {} bytes from {}", content.len(), key);
+        cache.insert(key, SourceFile { content, modified });
+    }
+    Ok(cache)
+}
+
+/// Turn a path inside an archive (e.g. `./site/index.html`) into a request path
+/// (`/site/index.html`). Entries which would escape the archive root are rejected.
+fn normalize_entry_path(path: &Path) -> Option<String> {
+    let mut key = String::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => {
+                key.push('/');
+                key.push_str(part.to_str()?);
+            }
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    if key.is_empty() {
+        return None;
+    }
+    Some(key)
+}
+
+/// Zip entries store their modification time as a local date and time without a timezone. We
+/// interpret it as UTC, which is good enough for deriving ETags.
+fn zip_time(time: zip::DateTime) -> SystemTime {
+    // Days since the epoch for the given civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let (month, day) = (time.month() as i64, time.day() as i64);
+    let year = time.year() as i64 - if month <= 2 { 1 } else { 0 };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    let seconds =
+        days * 86400 + time.hour() as i64 * 3600 + time.minute() as i64 * 60 + time.second() as i64;
+    UNIX_EPOCH + Duration::from_secs(seconds.max(0) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    /// A tar archive of files, or of directories for entries without content. The names are
+    /// written as they are, as `tar::Header::set_path` refuses unsafe ones.
+    fn tar(entries: &[(&str, Option<&str>)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, content) in entries {
+            let mut header = tar::Header::new_old();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_mode(0o644);
+            header.set_mtime(1_600_000_000);
+            header.set_entry_type(match content {
+                Some(_) => tar::EntryType::Regular,
+                None => tar::EntryType::Directory,
+            });
+            let content = content.unwrap_or_default();
+            header.set_size(content.len() as u64);
+            header.set_cksum();
+            builder.append(&header, content.as_bytes()).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    /// Load `archive` from a file without an archive extension, so only its content tells the
+    /// format.
+    fn load_archive(name: &str, archive: &[u8]) -> HashMap<String, String> {
+        let path = std::env::temp_dir().join(format!(
+            "httpserve-archive-{}-{}.bin",
+            std::process::id(),
+            name
+        ));
+        std::fs::write(&path, archive).unwrap();
+        let loaded = load(&path);
+        let _ = std::fs::remove_file(&path);
+        loaded
+            .unwrap()
+            .into_iter()
+            .map(|(key, file)| (key, String::from_utf8(file.content).unwrap()))
+            .collect()
+    }
+
+    fn files(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, content)| (key.to_string(), content.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn turns_entry_paths_into_request_paths() {
+        let key = |path: &str| normalize_entry_path(Path::new(path));
+        assert_eq!(key("index.html").as_deref(), Some("/index.html"));
+        assert_eq!(
+            key("./site/index.html").as_deref(),
+            Some("/site/index.html")
+        );
+        assert_eq!(key("site/./a//b.txt").as_deref(), Some("/site/a/b.txt"));
+        assert_eq!(key("."), None);
+        assert_eq!(key("./"), None);
+    }
+
+    #[test]
+    fn rejects_entries_outside_of_the_archive_root() {
+        let key = |path: &str| normalize_entry_path(Path::new(path));
+        assert_eq!(key("../evil.txt"), None);
+        assert_eq!(key("site/../../evil.txt"), None);
+        assert_eq!(key("site/../index.html"), None);
+        assert_eq!(key("/etc/passwd"), None);
+    }
+
+    #[test]
+    fn recognizes_archive_extensions() {
+        for name in ["site.zip", "site.tar", "site.tar.gz", "SITE.TGZ"] {
+            assert!(has_archive_extension(Path::new(name)), "{}", name);
+        }
+        for name in ["site", "site.gz", "zip", "site.tar.bz2"] {
+            assert!(!has_archive_extension(Path::new(name)), "{}", name);
+        }
+    }
+
+    #[test]
+    fn loads_tar_archives_skipping_directories_and_unsafe_entries() {
+        let archive = tar(&[
+            ("./", None),
+            ("./site/", None),
+            ("./site/index.html", Some("<h1>Home</h1>")),
+            ("about.txt", Some("About us")),
+            ("../evil.txt", Some("Evil")),
+            ("/etc/passwd", Some("root")),
+        ]);
+        let expected = files(&[
+            ("/site/index.html", "<h1>Home</h1>"),
+            ("/about.txt", "About us"),
+        ]);
+        assert_eq!(load_archive("tar", &archive), expected);
+
+        let mut gzip = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(&archive).unwrap();
+        assert_eq!(load_archive("tar-gz", &gzip.finish().unwrap()), expected);
+    }
+
+    #[test]
+    fn loads_zip_archives_skipping_directories_and_unsafe_entries() {
+        let mut zip = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        let options = zip::write::FileOptions::default();
+        zip.add_directory("site/", options).unwrap();
+        for (name, content) in [
+            ("site/index.html", "<h1>Home</h1>"),
+            ("./about.txt", "About us"),
+            ("../evil.txt", "Evil"),
+        ] {
+            zip.start_file(name, options).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        let archive = zip.finish().unwrap().into_inner();
+        assert_eq!(
+            load_archive("zip", &archive),
+            files(&[
+                ("/site/index.html", "<h1>Home</h1>"),
+                ("/about.txt", "About us"),
+            ])
+        );
+    }
+}
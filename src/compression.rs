@@ -0,0 +1,166 @@
+//! Content codings for responses: gzip compression for `--compress`, and which codings a
+//! request's `Accept-Encoding` allows.
+
+use bytes::Bytes;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use hyper::body::HttpBody;
+use hyper::header::ACCEPT_ENCODING;
+use hyper::{Body, HeaderMap};
+use std::io::{Read, Write};
+
+/// Content codings in which files can be sent, other than as they are.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// Zstandard, only ever sent from precompressed `.zst` files
+    Zstd,
+    Gzip,
+}
+
+impl Encoding {
+    /// The name of the coding, as in `Accept-Encoding` and `Content-Encoding`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Zstd => "zstd",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Encoding> {
+        match name {
+            "zstd" => Some(Encoding::Zstd),
+            "gzip" => Some(Encoding::Gzip),
+            _ => None,
+        }
+    }
+}
+
+/// Settings for producing gzip-compressed variants of responses.
+#[derive(Clone)]
+pub struct Compression {
+    pub enabled: bool,
+    /// Responses smaller than this are not worth compressing.
+    pub min_size: usize,
+    /// The gzip compression level, from 1 (fastest) to 9 (smallest).
+    pub gzip_level: u32,
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression {
+            enabled: false,
+            min_size: 1024,
+            gzip_level: 6,
+        }
+    }
+}
+
+impl Compression {
+    /// Compress `content`, unless compression is disabled, `content` is too small, its content
+    /// type is already compressed or compression doesn't make it any smaller.
+    pub fn gzip(&self, content_type: &str, content: &[u8]) -> Option<Bytes> {
+        if !self.enabled || content.len() < self.min_size || !is_compressible(content_type) {
+            return None;
+        }
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::new(self.gzip_level));
+        encoder
+            .write_all(content)
+            .and_then(|_| encoder.finish())
+            .ok()
+            .filter(|compressed| compressed.len() < content.len())
+            .map(Bytes::from)
+    }
+
+    /// Whether a generated response is worth compressing, given its size if it is known up front.
+    pub fn should_compress(&self, content_type: &str, size: Option<u64>) -> bool {
+        self.enabled
+            && is_compressible(content_type)
+            && size.is_none_or(|size| size >= self.min_size as u64)
+    }
+
+    /// Compress `body` while it is being sent, rather than buffering all of it first.
+    pub fn gzip_stream(&self, mut body: Body) -> Body {
+        let (mut sender, compressed) = Body::channel();
+        let level = flate2::Compression::new(self.gzip_level);
+        tokio::spawn(async move {
+            let mut encoder = GzEncoder::new(Vec::new(), level);
+            while let Some(chunk) = body.data().await {
+                let written = chunk
+                    .map_err(std::io::Error::other)
+                    .and_then(|chunk| encoder.write_all(&chunk));
+                if written.is_err() {
+                    sender.abort();
+                    return;
+                }
+                let output = std::mem::take(encoder.get_mut());
+                // The encoder buffers small chunks, so there may be nothing to send yet
+                if !output.is_empty() && sender.send_data(Bytes::from(output)).await.is_err() {
+                    return;
+                }
+            }
+            match encoder.finish() {
+                Ok(output) => {
+                    let _ = sender.send_data(Bytes::from(output)).await;
+                }
+                Err(_) => sender.abort(),
+            }
+        });
+        compressed
+    }
+}
+
+/// Content types which are worth compressing. Images, video and archives are already compressed.
+fn is_compressible(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or("").trim();
+    essence.starts_with("text/")
+        || matches!(
+            essence,
+            "application/json"
+                | "application/manifest+json"
+                | "application/wasm"
+                | "application/xml"
+                | "image/svg+xml"
+                | "image/x-icon"
+        )
+}
+
+/// Whether the `Accept-Encoding` header of a request allows a gzip-encoded response.
+pub fn accepts_gzip(headers: &HeaderMap) -> bool {
+    accepts(headers, Encoding::Gzip)
+}
+
+/// Whether the `Accept-Encoding` header of a request allows a response with `encoding`. An entry
+/// naming the encoding takes precedence over `*`, so `gzip;q=0, *` refuses gzip (RFC 9110,
+/// section 12.5.3).
+pub fn accepts(headers: &HeaderMap, encoding: Encoding) -> bool {
+    let mut named = None;
+    let mut wildcard = None;
+    for coding in headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+    {
+        let mut parts = coding.split(';');
+        let name = parts.next().unwrap_or("").trim();
+        let quality = parts
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if name.eq_ignore_ascii_case(encoding.name())
+            || (encoding == Encoding::Gzip && name.eq_ignore_ascii_case("x-gzip"))
+        {
+            named = Some(named.map_or(quality, |q: f32| q.max(quality)));
+        } else if name == "*" {
+            wildcard = Some(quality);
+        }
+    }
+    named.or(wildcard).is_some_and(|quality| quality > 0.0)
+}
+
+/// Decompress gzip-compressed `content`, such as that of a precompressed `.gz` file.
+pub fn gunzip(content: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    GzDecoder::new(content).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
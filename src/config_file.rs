@@ -0,0 +1,168 @@
+//! Configuration files for `--config`, which use the keys and format of `--print-config`: one
+//! `key = value` per line, where the value is `true` or `false`, a number, a quoted string or a
+//! list of quoted strings, and `#` starts a comment. They're turned into command line arguments,
+//! so they're checked in exactly the same way.
+
+/// The value of a key in a configuration file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    /// For flags without a value, like `compress`
+    Flag(bool),
+    /// For options with a single value, like `port`
+    Single(String),
+    /// For options which may be given several times, like `mime`
+    List(Vec<String>),
+}
+
+/// Parse the `key = value` lines of a configuration file, in order.
+pub fn parse(text: &str) -> Result<Vec<(String, Value)>, String> {
+    let mut entries = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected key = value", number + 1))?;
+        let key = key.trim();
+        if key.is_empty()
+            || !key
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+        {
+            return Err(format!("line {}: invalid key {}", number + 1, key));
+        }
+        if key == "config" || key == "print-config" {
+            return Err(format!(
+                "line {}: {} can only be given on the command line",
+                number + 1,
+                key
+            ));
+        }
+        let value = parse_value(value.trim()).map_err(|e| format!("line {}: {}", number + 1, e))?;
+        entries.push((key.to_string(), value));
+    }
+    Ok(entries)
+}
+
+/// The line without a comment, which starts at a `#` outside of quotes.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_value(value: &str) -> Result<Value, String> {
+    match value {
+        "true" => return Ok(Value::Flag(true)),
+        "false" => return Ok(Value::Flag(false)),
+        _ => {}
+    }
+    if let Some(items) = value.strip_prefix('[') {
+        let mut items = items
+            .strip_suffix(']')
+            .ok_or_else(|| String::from("missing ] at the end of the list"))?
+            .trim();
+        let mut values = Vec::new();
+        while !items.is_empty() {
+            let (item, rest) = string(items)?;
+            values.push(item);
+            items = rest.trim_start();
+            items = match items.strip_prefix(',') {
+                Some(rest) => rest.trim_start(),
+                None if items.is_empty() => items,
+                None => return Err(String::from("expected , between the values of the list")),
+            };
+        }
+        return Ok(Value::List(values));
+    }
+    if value.starts_with('"') {
+        return match string(value)? {
+            (value, "") => Ok(Value::Single(value)),
+            _ => Err(String::from("unexpected characters after the string")),
+        };
+    }
+    if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit() || b == b'.') {
+        return Ok(Value::Single(value.to_string()));
+    }
+    Err(format!("invalid value {}, strings must be quoted", value))
+}
+
+/// The quoted string at the start of `value`, and what follows it.
+fn string(value: &str) -> Result<(String, &str), String> {
+    let rest = value
+        .strip_prefix('"')
+        .ok_or_else(|| format!("expected a quoted string at {}", value))?;
+    let mut unquoted = String::new();
+    let mut chars = rest.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((unquoted, &rest[i + 1..])),
+            '\\' => match chars.next() {
+                Some((_, c @ ('"' | '\\'))) => unquoted.push(c),
+                Some((_, 'n')) => unquoted.push('\n'),
+                Some((_, 't')) => unquoted.push('\t'),
+                _ => return Err(String::from("invalid escape sequence in string")),
+            },
+            c => unquoted.push(c),
+        }
+    }
+    Err(String::from("missing \" at the end of the string"))
+}
+
+/// Merge the entries of several files, in order. A later value of a flag or single-valued option
+/// replaces an earlier one, while lists are appended to each other.
+pub fn merge(files: Vec<Vec<(String, Value)>>) -> Vec<(String, Value)> {
+    let mut merged: Vec<(String, Value)> = Vec::new();
+    for (key, value) in files.into_iter().flatten() {
+        match (
+            merged.iter_mut().find(|(merged_key, _)| *merged_key == key),
+            value,
+        ) {
+            (Some((_, Value::List(values))), Value::List(more)) => values.extend(more),
+            (Some((_, merged_value)), value) => *merged_value = value,
+            (None, value) => merged.push((key, value)),
+        }
+    }
+    merged
+}
+
+/// The command line arguments for `entries`, to go before those given on the command line. Flags
+/// and single-valued options for which `given` is true are left out, as the command line takes
+/// precedence. `dir` is the positional directory and `fallback-dirs` the `--dir` options.
+pub fn to_args(entries: &[(String, Value)], given: impl Fn(&str) -> bool) -> Vec<String> {
+    let mut args = Vec::new();
+    for (key, value) in entries {
+        let flag = match key.as_str() {
+            "fallback-dirs" => String::from("--dir"),
+            key => format!("--{}", key),
+        };
+        match value {
+            Value::List(values) => {
+                for value in values {
+                    args.push(flag.clone());
+                    args.push(value.clone());
+                }
+            }
+            _ if given(key) => {}
+            Value::Flag(true) => args.push(flag),
+            Value::Flag(false) => {}
+            Value::Single(value) if key == "dir" => args.push(value.clone()),
+            Value::Single(value) => {
+                args.push(flag);
+                args.push(value.clone());
+            }
+        }
+    }
+    args
+}
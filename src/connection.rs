@@ -0,0 +1,234 @@
+//! Checks on incoming connections before hyper handles them: dropping connections which don't
+//! send a request in time, for `--connection-idle-timeout`, and answering requests for HTTP
+//! versions we don't speak with `505 HTTP Version Not Supported`.
+
+use hyper::server::accept::Accept;
+use hyper::server::conn::{AddrIncoming, AddrStream};
+use log::{info, warn};
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{sleep, Sleep};
+
+/// How much of the start of a connection is inspected for the request line.
+const REQUEST_LINE_LIMIT: usize = 1024;
+
+const VERSION_NOT_SUPPORTED: &[u8] = b"HTTP/1.1 505 HTTP Version Not Supported\r\n\
+    content-length: 0\r\n\
+    connection: close\r\n\r\n";
+
+/// Accepts connections like `AddrIncoming`, wrapping each of them in a `Connection`.
+pub struct Incoming {
+    incoming: AddrIncoming,
+    idle_timeout: Option<Duration>,
+}
+
+impl Incoming {
+    /// Wrap `incoming`. Without an `idle_timeout`, connections are never dropped for being idle.
+    pub fn new(incoming: AddrIncoming, idle_timeout: Option<Duration>) -> Incoming {
+        Incoming {
+            incoming,
+            idle_timeout,
+        }
+    }
+}
+
+impl Accept for Incoming {
+    type Conn = Connection;
+    type Error = io::Error;
+
+    fn poll_accept(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Connection, io::Error>>> {
+        let this = self.get_mut();
+        let timeout = this.idle_timeout;
+        Pin::new(&mut this.incoming)
+            .poll_accept(cx)
+            .map_ok(|stream| Connection {
+                stream,
+                deadline: timeout.map(|timeout| (Box::pin(sleep(timeout)), timeout)),
+                requested: Arc::new(AtomicBool::new(false)),
+                start: Start::Inspecting(Vec::new()),
+            })
+    }
+}
+
+/// What has become of the first bytes of a connection.
+enum Start {
+    /// Read until the request line is complete, but not yet passed on to hyper
+    Inspecting(Vec<u8>),
+    /// Inspected and found fine, but still to be passed on to hyper
+    Replaying(Vec<u8>),
+    /// Passed on to hyper, which reads from the stream directly from now on
+    Passed,
+    /// Answered with `505`, of which the given number of bytes were written
+    Rejected(usize),
+}
+
+/// An accepted connection. It fails to read once its idle deadline has passed, unless a request
+/// was received before, so hyper closes it. Requests for unsupported HTTP versions are answered
+/// here without hyper ever seeing them.
+pub struct Connection {
+    stream: AddrStream,
+    /// The deadline along with the timeout it is based on, until a request is received
+    deadline: Option<(Pin<Box<Sleep>>, Duration)>,
+    requested: Arc<AtomicBool>,
+    start: Start,
+}
+
+impl Connection {
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.stream.remote_addr()
+    }
+
+    /// The handle through which the service marks that the connection has sent a request.
+    pub fn activity(&self) -> Activity {
+        Activity(Arc::clone(&self.requested))
+    }
+
+    /// Read until the request line is complete, then decide whether hyper gets to handle it.
+    fn poll_inspect(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while let Start::Inspecting(buffer) = &mut self.start {
+            let mut chunk = [0; REQUEST_LINE_LIMIT];
+            let mut chunk = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut self.stream).poll_read(cx, &mut chunk))?;
+            let eof = chunk.filled().is_empty();
+            buffer.extend_from_slice(chunk.filled());
+            let line_end = buffer.windows(2).position(|w| w == b"\r\n");
+            if line_end.is_none() && !eof && buffer.len() < REQUEST_LINE_LIMIT {
+                continue;
+            }
+            let buffer = std::mem::take(buffer);
+            let unsupported = line_end
+                .and_then(|end| std::str::from_utf8(&buffer[..end]).ok())
+                .and_then(unsupported_version);
+            self.start = match unsupported {
+                Some((method, target, version)) => {
+                    warn!("{} {} 505 ({} is not supported)", method, target, version);
+                    Start::Rejected(0)
+                }
+                None => Start::Replaying(buffer),
+            };
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// The method, target and version of a request line for an HTTP version other than 1.x, except
+/// for the preface of HTTP/2 with prior knowledge, which hyper handles. Lines which don't look
+/// like a request line at all are left to hyper as well, which rejects them.
+fn unsupported_version(line: &str) -> Option<(&str, &str, &str)> {
+    if line == "PRI * HTTP/2.0" {
+        return None;
+    }
+    let mut parts = line.split(' ');
+    let (method, target, version) = (parts.next()?, parts.next()?, parts.next()?);
+    let major = version.strip_prefix("HTTP/")?.split('.').next()?;
+    if parts.next().is_some() || major.is_empty() || !major.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    if major == "1" {
+        None
+    } else {
+        Some((method, target, version))
+    }
+}
+
+/// Marks a connection as no longer idle.
+#[derive(Clone)]
+pub struct Activity(Arc<AtomicBool>);
+
+impl Activity {
+    /// Record that a complete request (head) was received, which stops the timeout.
+    pub fn request_received(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if let Some((deadline, timeout)) = &mut this.deadline {
+            if this.requested.load(Ordering::Relaxed) {
+                this.deadline = None;
+            } else if deadline.as_mut().poll(cx).is_ready() {
+                info!(
+                    "Dropping connection from {} without a request after {:?}",
+                    this.stream.remote_addr(),
+                    timeout
+                );
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "No request received in time",
+                )));
+            }
+        }
+        ready!(this.poll_inspect(cx))?;
+        match &mut this.start {
+            Start::Inspecting(_) => unreachable!("Inspection is complete"),
+            Start::Replaying(buffer) => {
+                let n = buffer.len().min(buf.remaining());
+                buf.put_slice(&buffer[..n]);
+                buffer.drain(..n);
+                if buffer.is_empty() {
+                    this.start = Start::Passed;
+                }
+                Poll::Ready(Ok(()))
+            }
+            Start::Passed => Pin::new(&mut this.stream).poll_read(cx, buf),
+            // Hyper sees the end of the connection once the response is written
+            Start::Rejected(written) => {
+                while *written < VERSION_NOT_SUPPORTED.len() {
+                    let n = ready!(Pin::new(&mut this.stream)
+                        .poll_write(cx, &VERSION_NOT_SUPPORTED[*written..]))?;
+                    if n == 0 {
+                        return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+                    }
+                    *written += n;
+                }
+                Poll::Ready(Ok(()))
+            }
+        }
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
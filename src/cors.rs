@@ -0,0 +1,138 @@
+//! Cross-origin resource sharing, for `--cors-allow-origin`: the `Access-Control-Allow-Origin`
+//! header on responses to allowed origins, and the responses to their preflight requests, which
+//! browsers send before cross-origin requests that aren't simple, such as those with custom headers.
+
+use crate::error::Error;
+use hyper::header::{
+    HeaderValue, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+    ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS,
+    ACCESS_CONTROL_REQUEST_METHOD, ORIGIN, VARY,
+};
+use hyper::{Body, HeaderMap, Method, Response, StatusCode};
+
+pub struct Cors {
+    /// The allowed origins, or `*` for any
+    origins: Vec<String>,
+    methods: Option<HeaderValue>,
+    headers: Option<HeaderValue>,
+    reflect_headers: bool,
+    max_age: Option<u64>,
+}
+
+impl Cors {
+    pub fn new(
+        origins: &[String],
+        methods: &[String],
+        headers: &[String],
+        reflect_headers: bool,
+        max_age: Option<u64>,
+    ) -> Result<Cors, Error> {
+        let join = |values: &[String], name: &'static str| {
+            let joined = values.join(", ");
+            match HeaderValue::from_str(&joined) {
+                _ if values.is_empty() => Ok(None),
+                Ok(value) => Ok(Some(value)),
+                Err(_) => Err(Error::Header {
+                    name,
+                    value: joined,
+                }),
+            }
+        };
+        Ok(Cors {
+            origins: origins.to_vec(),
+            methods: join(methods, "Access-Control-Allow-Methods")?,
+            headers: join(headers, "Access-Control-Allow-Headers")?,
+            reflect_headers,
+            max_age,
+        })
+    }
+
+    /// The `Access-Control-Allow-Origin` for a request with this `Origin`, if it's allowed: `*`,
+    /// or the origin itself.
+    fn allow_origin(&self, origin: Option<&HeaderValue>) -> Option<HeaderValue> {
+        if self.origins.iter().any(|origin| origin == "*") {
+            return Some(HeaderValue::from_static("*"));
+        }
+        let origin = origin?;
+        let allowed = origin
+            .to_str()
+            .is_ok_and(|origin| self.origins.iter().any(|o| o.eq_ignore_ascii_case(origin)));
+        Some(origin.clone()).filter(|_| allowed)
+    }
+
+    /// Add `Access-Control-Allow-Origin` to the response to a request with this `Origin`, if it's
+    /// allowed.
+    pub fn apply(&self, origin: Option<&HeaderValue>, response: &mut Response<Body>) {
+        if self.origins.is_empty() {
+            return;
+        }
+        let allow_origin = self.allow_origin(origin);
+        let response_headers = response.headers_mut();
+        if allow_origin.as_ref().is_none_or(|value| value != "*") {
+            // The header differs between origins, even if it's left out for this one
+            response_headers.append(VARY, HeaderValue::from_static("Origin"));
+        }
+        if let Some(value) = allow_origin {
+            response_headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, value);
+        }
+    }
+
+    /// The response to a preflight request with these headers from an allowed origin, with the
+    /// configured methods, or `default_methods` if there are none.
+    pub fn preflight(
+        &self,
+        method: &Method,
+        headers: &HeaderMap,
+        default_methods: impl FnOnce() -> String,
+    ) -> Option<Response<Body>> {
+        if method != Method::OPTIONS || !headers.contains_key(ACCESS_CONTROL_REQUEST_METHOD) {
+            return None;
+        }
+        let allow_origin = self.allow_origin(headers.get(ORIGIN))?;
+        let mut builder = Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .header(ACCESS_CONTROL_ALLOW_ORIGIN, &allow_origin)
+            .header(
+                ACCESS_CONTROL_ALLOW_METHODS,
+                match &self.methods {
+                    Some(methods) => methods.clone(),
+                    None => HeaderValue::from_str(&default_methods())
+                        .expect("Unable to create `Access-Control-Allow-Methods` header"),
+                },
+            );
+        let mut vary = Vec::new();
+        if allow_origin != "*" {
+            vary.push("Origin");
+        }
+        let allow_headers = if self.reflect_headers {
+            vary.push("Access-Control-Request-Headers");
+            headers.get(ACCESS_CONTROL_REQUEST_HEADERS).cloned()
+        } else {
+            self.headers.clone()
+        };
+        if let Some(allow_headers) = allow_headers {
+            builder = builder.header(ACCESS_CONTROL_ALLOW_HEADERS, allow_headers);
+        }
+        if let Some(max_age) = self.max_age {
+            builder = builder.header(ACCESS_CONTROL_MAX_AGE, max_age);
+        }
+        if !vary.is_empty() {
+            builder = builder.header(VARY, vary.join(", "));
+        }
+        Some(
+            builder
+                .body(Body::empty())
+                .expect("Unable to create `http::Response`"),
+        )
+    }
+}
+
+/// Split a comma-separated list like `GET, POST` into its values.
+pub fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(String::from)
+        .collect()
+}
@@ -0,0 +1,112 @@
+//! `Content-Disposition` headers for `--attachment`, which make browsers download files rather
+//! than display them.
+
+use crate::error::Error;
+use crate::glob::PathGlob;
+
+/// The files which browsers should download rather than display, as set with `--attachment`.
+pub struct Attachments {
+    patterns: Vec<PathGlob>,
+}
+
+impl Attachments {
+    pub fn new(patterns: &[String]) -> Result<Attachments, Error> {
+        Ok(Attachments {
+            patterns: patterns
+                .iter()
+                .map(|p| PathGlob::new(p))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// The `Content-Disposition` header for the file at `path`, if it is an attachment.
+    pub fn content_disposition(&self, path: &str) -> Option<String> {
+        if self.patterns.iter().any(|p| p.is_match(path)) {
+            Some(attachment(path.rsplit('/').next().unwrap_or(path)))
+        } else {
+            None
+        }
+    }
+}
+
+/// Format the header value as described in RFC 6266. Non-ASCII file names are passed as an RFC
+/// 5987 encoded `filename*` parameter, with an ASCII approximation for older clients.
+fn attachment(file_name: &str) -> String {
+    let fallback: String = file_name
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => format!("\\{}", c),
+            c if c.is_ascii() && !c.is_ascii_control() => c.to_string(),
+            _ => String::from("_"),
+        })
+        .collect();
+    if file_name.is_ascii() {
+        return format!("attachment; filename=\"{}\"", fallback);
+    }
+    let mut encoded = String::new();
+    for byte in file_name.bytes() {
+        if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback, encoded
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_ascii_file_names() {
+        assert_eq!(
+            attachment("report.pdf"),
+            "attachment; filename=\"report.pdf\""
+        );
+        assert_eq!(
+            attachment("say \"hi\" \\ bye.txt"),
+            r#"attachment; filename="say \"hi\" \\ bye.txt""#
+        );
+        assert_eq!(attachment("tab\there"), "attachment; filename=\"tab_here\"");
+    }
+
+    #[test]
+    fn encodes_non_ascii_file_names() {
+        assert_eq!(
+            attachment("résumé.pdf"),
+            "attachment; filename=\"r_sum_.pdf\"; filename*=UTF-8''r%C3%A9sum%C3%A9.pdf"
+        );
+        assert_eq!(
+            attachment("naïve \"quote\".txt"),
+            "attachment; filename=\"na_ve \\\"quote\\\".txt\"; \
+             filename*=UTF-8''na%C3%AFve%20%22quote%22.txt"
+        );
+        assert_eq!(
+            attachment("日本.csv"),
+            "attachment; filename=\"__.csv\"; filename*=UTF-8''%E6%97%A5%E6%9C%AC.csv"
+        );
+    }
+
+    #[test]
+    fn applies_to_matching_files_only() {
+        let attachments =
+            Attachments::new(&[String::from("*.csv"), String::from("/downloads/**")]).unwrap();
+        assert_eq!(
+            attachments
+                .content_disposition("/data/export.csv")
+                .as_deref(),
+            Some("attachment; filename=\"export.csv\"")
+        );
+        assert_eq!(
+            attachments
+                .content_disposition("/downloads/tools/setup.exe")
+                .as_deref(),
+            Some("attachment; filename=\"setup.exe\"")
+        );
+        assert_eq!(attachments.content_disposition("/index.html"), None);
+    }
+}
@@ -0,0 +1,29 @@
+//! Files compiled into the binary with the `embed` feature. The directory is selected with the
+//! `HTTPSERVE_EMBED_DIR` environment variable at build time.
+
+use include_dir::{include_dir, Dir};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::error::Error;
+use crate::{cache_key, SourceFile};
+
+static EMBEDDED: Dir = include_dir!("$HTTPSERVE_EMBED_DIR");
+
+/// Load all embedded files into a map from request path to file.
+pub fn load() -> Result<HashMap<String, SourceFile>, Error> {
+    let mut cache = HashMap::new();
+    let mut to_visit = vec![&EMBEDDED];
+    while let Some(dir) = to_visit.pop() {
+        to_visit.extend(dir.dirs());
+        for file in dir.files() {
+            let key = cache_key(Path::new(""), file.path())?;
+            let file = SourceFile {
+                content: file.contents().to_vec(),
+                modified: file.metadata().map(|m| m.modified()),
+            };
+            cache.insert(key, file);
+        }
+    }
+    Ok(cache)
+}
@@ -0,0 +1,77 @@
+//! The errors which keep `httpserve` from starting or stop it: options which can't be parsed,
+//! files which can't be read, addresses which can't be bound and a server which fails. They're
+//! down to the configuration or the environment rather than bugs, so they're reported as a
+//! message instead of a panic.
+
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+
+#[derive(thiserror::Error)]
+pub enum Error {
+    /// An option, or a file given as one (such as `--redirects`), which can't be parsed
+    #[error("{0}")]
+    Config(String),
+    /// A file, directory or socket which can't be used
+    #[error("{context}: {source}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+    /// The address to serve `what` (such as the admin API) on, which can't be bound
+    #[error("{}", bind_message(.what, *.addr, .source))]
+    Bind {
+        what: &'static str,
+        addr: SocketAddr,
+        #[source]
+        source: io::Error,
+    },
+    /// The value of an option which isn't valid in the header `name`
+    #[error("Invalid {name} header {value:?}")]
+    Header { name: &'static str, value: String },
+    /// A failure of the server while serving, such as being unable to accept connections
+    #[error("Server error: {0}")]
+    Server(#[source] hyper::Error),
+}
+
+impl Error {
+    /// Wrap an `io::Error` with what was being done, for `map_err`.
+    pub fn io(context: impl Into<String>) -> impl FnOnce(io::Error) -> Error {
+        let context = context.into();
+        move |source| Error::Io { context, source }
+    }
+}
+
+/// Like `Display`, so that `main` returning an `Error` prints the message.
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Explain why `addr` can't be bound, with a hint for the usual causes.
+fn bind_message(what: &str, addr: SocketAddr, e: &io::Error) -> String {
+    match e.kind() {
+        io::ErrorKind::AddrInUse => format!(
+            "Address already in use: {}. Another process is listening on port {}; stop it or \
+             serve the {} on another port",
+            addr,
+            addr.port(),
+            what
+        ),
+        io::ErrorKind::PermissionDenied if addr.port() < 1024 => format!(
+            "Permission denied: {}. Ports below 1024 are privileged; serve the {} on a port \
+             from 1024 up, or grant the capability to bind privileged ports (e.g. \
+             CAP_NET_BIND_SERVICE on Linux)",
+            addr, what
+        ),
+        io::ErrorKind::PermissionDenied => format!("Permission denied: {}: {}", addr, e),
+        io::ErrorKind::AddrNotAvailable => format!(
+            "Address not available: {}. {} isn't an address of this machine",
+            addr,
+            addr.ip()
+        ),
+        _ => format!("Unable to serve the {} on {}: {}", what, addr, e),
+    }
+}
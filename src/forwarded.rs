@@ -0,0 +1,229 @@
+//! The headers with which proxies describe the original request, for `--proxy-headers`: the
+//! standard `Forwarded` header (RFC 7239) and the older `X-Forwarded-For`, `X-Forwarded-Host` and
+//! `X-Forwarded-Proto`. Only the first element of each is used, which describes the client as
+//! seen by the first proxy, so the proxies must not pass on these headers as the client sent them.
+
+use hyper::header::FORWARDED;
+use hyper::HeaderMap;
+use std::net::{IpAddr, SocketAddr};
+
+/// Which headers to take the client address, host and scheme from.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ProxyHeaders {
+    /// Neither, using the connection and `Host` header instead
+    Ignore,
+    /// `Forwarded`, or the `X-Forwarded-*` headers if it doesn't have the directive
+    Forwarded,
+    /// The `X-Forwarded-*` headers, or `Forwarded` if they're missing
+    XForwarded,
+}
+
+impl ProxyHeaders {
+    pub fn name(self) -> &'static str {
+        match self {
+            ProxyHeaders::Ignore => "none",
+            ProxyHeaders::Forwarded => "forwarded",
+            ProxyHeaders::XForwarded => "x-forwarded",
+        }
+    }
+
+    /// The address of the client, from `for=` or `X-Forwarded-For`. Obfuscated and unknown
+    /// addresses are ignored, as are obfuscated ports, which become 0.
+    pub fn client(self, headers: &HeaderMap) -> Option<SocketAddr> {
+        self.find(headers, "for", "x-forwarded-for")
+            .and_then(|node| parse_node(&node))
+    }
+
+    /// The `Host` the client sent, from `host=` or `X-Forwarded-Host`.
+    pub fn host(self, headers: &HeaderMap) -> Option<String> {
+        self.find(headers, "host", "x-forwarded-host")
+    }
+
+    /// The scheme the client used, from `proto=` or `X-Forwarded-Proto`.
+    pub fn proto(self, headers: &HeaderMap) -> Option<String> {
+        self.find(headers, "proto", "x-forwarded-proto")
+    }
+
+    fn find(self, headers: &HeaderMap, directive: &str, header: &str) -> Option<String> {
+        let standard = || forwarded(headers, directive);
+        let legacy = || {
+            let value = headers.get(header)?.to_str().ok()?;
+            let first = value.split(',').next().unwrap_or_default().trim();
+            Some(first.to_string()).filter(|first| !first.is_empty())
+        };
+        match self {
+            ProxyHeaders::Ignore => None,
+            ProxyHeaders::Forwarded => standard().or_else(legacy),
+            ProxyHeaders::XForwarded => legacy().or_else(standard),
+        }
+    }
+}
+
+/// The value of `directive` in the first element of the `Forwarded` header, unquoted.
+pub fn forwarded(headers: &HeaderMap, directive: &str) -> Option<String> {
+    let value = headers.get(FORWARDED)?.to_str().ok()?;
+    let element = split_unquoted(value, ',').next()?;
+    split_unquoted(element, ';').find_map(|pair| {
+        let (name, value) = pair.trim().split_once('=')?;
+        if name.trim().eq_ignore_ascii_case(directive) {
+            Some(unquote(value.trim()))
+        } else {
+            None
+        }
+    })
+}
+
+/// The parts of `value` between the separators which aren't in a quoted string.
+fn split_unquoted(value: &str, separator: char) -> impl Iterator<Item = &str> {
+    let mut quoted = false;
+    let mut escaped = false;
+    value.split(move |c| {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == separator && !quoted => return true,
+            _ => {}
+        }
+        false
+    })
+}
+
+fn unquote(value: &str) -> String {
+    match value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    {
+        Some(quoted) => {
+            let mut unquoted = String::with_capacity(quoted.len());
+            let mut chars = quoted.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => unquoted.extend(chars.next()),
+                    c => unquoted.push(c),
+                }
+            }
+            unquoted
+        }
+        None => value.to_string(),
+    }
+}
+
+/// An address like `192.0.2.1`, `192.0.2.1:4711`, `2001:db8::1` or `[2001:db8::1]:4711`.
+fn parse_node(node: &str) -> Option<SocketAddr> {
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(SocketAddr::new(ip, 0));
+    }
+    let (ip, port) = match node.strip_prefix('[') {
+        Some(bracketed) => {
+            let (ip, rest) = bracketed.split_once(']')?;
+            (ip, rest.strip_prefix(':'))
+        }
+        None => {
+            let (ip, port) = node.rsplit_once(':')?;
+            (ip, Some(port))
+        }
+    };
+    let ip = ip.parse::<IpAddr>().ok()?;
+    let port = port.and_then(|port| port.parse().ok()).unwrap_or(0);
+    Some(SocketAddr::new(ip, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::{HeaderName, HeaderValue};
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for &(name, value) in pairs {
+            headers.append(
+                HeaderName::from_static(name),
+                HeaderValue::from_static(value),
+            );
+        }
+        headers
+    }
+
+    fn client(pairs: &[(&'static str, &'static str)]) -> Option<SocketAddr> {
+        ProxyHeaders::Forwarded.client(&headers(pairs))
+    }
+
+    #[test]
+    fn takes_the_client_from_the_first_element() {
+        assert_eq!(
+            client(&[(
+                "forwarded",
+                "for=192.0.2.60;proto=http;by=203.0.113.43, for=198.51.100.17"
+            )]),
+            Some("192.0.2.60:0".parse().unwrap())
+        );
+        assert_eq!(
+            client(&[("forwarded", "For=\"192.0.2.60:4711\"")]),
+            Some("192.0.2.60:4711".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn parses_quoted_ipv6_clients() {
+        assert_eq!(
+            client(&[("forwarded", "for=\"[2001:db8::1]:4711\"")]),
+            Some("[2001:db8::1]:4711".parse().unwrap())
+        );
+        assert_eq!(
+            client(&[("forwarded", "for=\"[2001:db8::1]\";proto=https")]),
+            Some("[2001:db8::1]:0".parse().unwrap())
+        );
+        assert_eq!(
+            client(&[("forwarded", "for=\"[2001:db8::1]:_port\"")]),
+            Some("[2001:db8::1]:0".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn ignores_obfuscated_and_unknown_clients() {
+        assert_eq!(client(&[("forwarded", "for=_hidden")]), None);
+        assert_eq!(client(&[("forwarded", "for=unknown")]), None);
+        assert_eq!(client(&[("forwarded", "for=\"[2001:db8::1\"")]), None);
+    }
+
+    #[test]
+    fn keeps_separators_in_quoted_values() {
+        let headers = headers(&[(
+            "forwarded",
+            "host=\"a;b,c.example\";proto=https, host=other.example",
+        )]);
+        assert_eq!(
+            forwarded(&headers, "host").as_deref(),
+            Some("a;b,c.example")
+        );
+        assert_eq!(forwarded(&headers, "proto").as_deref(), Some("https"));
+        assert_eq!(
+            unquote(r#""a \"quoted\" \\ value""#),
+            r#"a "quoted" \ value"#
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_other_headers() {
+        let both = headers(&[
+            ("forwarded", "for=192.0.2.60"),
+            ("x-forwarded-for", "2001:db8::1, 10.0.0.1"),
+            ("x-forwarded-proto", "https"),
+        ]);
+        assert_eq!(
+            ProxyHeaders::Forwarded.client(&both),
+            Some("192.0.2.60:0".parse().unwrap())
+        );
+        assert_eq!(
+            ProxyHeaders::XForwarded.client(&both),
+            Some("[2001:db8::1]:0".parse().unwrap())
+        );
+        assert_eq!(
+            ProxyHeaders::Forwarded.proto(&both).as_deref(),
+            Some("https")
+        );
+        assert_eq!(ProxyHeaders::XForwarded.host(&both), None);
+        assert_eq!(ProxyHeaders::Ignore.client(&both), None);
+    }
+}
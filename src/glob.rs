@@ -0,0 +1,29 @@
+use crate::error::Error;
+use globset::{Glob, GlobMatcher};
+
+/// A glob pattern for request paths. Patterns containing a `/` are matched against the whole
+/// request path, others only against the file name, so `*.csv` matches CSV files anywhere.
+pub struct PathGlob {
+    matcher: GlobMatcher,
+    whole_path: bool,
+}
+
+impl PathGlob {
+    pub fn new(pattern: &str) -> Result<PathGlob, Error> {
+        Ok(PathGlob {
+            matcher: Glob::new(pattern)
+                .map_err(|e| Error::Config(format!("Invalid pattern {}: {}", pattern, e)))?
+                .compile_matcher(),
+            whole_path: pattern.contains('/'),
+        })
+    }
+
+    pub fn is_match(&self, path: &str) -> bool {
+        if self.whole_path {
+            self.matcher.is_match(path)
+        } else {
+            self.matcher
+                .is_match(path.rsplit('/').next().unwrap_or(path))
+        }
+    }
+}
@@ -0,0 +1,302 @@
+//! Reformatting of JSON files for `--json-reformat`. Only the whitespace between tokens changes:
+//! keys keep their order and strings and numbers are copied exactly as written.
+
+/// How to lay out the JSON.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum JsonFormat {
+    /// Without any whitespace between tokens
+    Minify,
+    /// With each member and element on a line of its own, indented by two spaces
+    Pretty,
+}
+
+/// Documents nested deeper than this are rejected rather than risk running out of stack.
+const MAX_DEPTH: usize = 128;
+
+/// Whether the content type is a JSON one: `application/json` or a `+json` type.
+pub fn is_json(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+    essence == "application/json" || essence.ends_with("+json")
+}
+
+/// Parse `content` and lay it out in `format`, or describe why it isn't valid JSON.
+pub fn reformat(content: &[u8], format: JsonFormat) -> Result<Vec<u8>, String> {
+    let text = std::str::from_utf8(content).map_err(|_| String::from("not valid UTF-8"))?;
+    let mut reformatter = Reformatter {
+        input: text.as_bytes(),
+        position: 0,
+        output: Vec::with_capacity(content.len()),
+        format,
+    };
+    reformatter.skip_whitespace();
+    reformatter.value(0)?;
+    reformatter.skip_whitespace();
+    if reformatter.position < reformatter.input.len() {
+        return Err(reformatter.unexpected());
+    }
+    if format == JsonFormat::Pretty {
+        reformatter.output.push(b'\n');
+    }
+    Ok(reformatter.output)
+}
+
+struct Reformatter<'a> {
+    input: &'a [u8],
+    position: usize,
+    output: Vec<u8>,
+    format: JsonFormat,
+}
+
+impl Reformatter<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.position += 1;
+        }
+    }
+
+    fn unexpected(&self) -> String {
+        match self.peek() {
+            Some(b) if b.is_ascii_graphic() => {
+                format!("unexpected '{}' at byte {}", b as char, self.position)
+            }
+            Some(b) => format!("unexpected byte 0x{:02x} at byte {}", b, self.position),
+            None => String::from("unexpected end of input"),
+        }
+    }
+
+    fn expect(&mut self, b: u8) -> Result<(), String> {
+        if self.peek() == Some(b) {
+            self.position += 1;
+            self.output.push(b);
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    /// Start a new line at `depth`, when pretty-printing.
+    fn newline(&mut self, depth: usize) {
+        if self.format == JsonFormat::Pretty {
+            self.output.push(b'\n');
+            self.output.resize(self.output.len() + 2 * depth, b' ');
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<(), String> {
+        match self.peek() {
+            Some(b'{') => self.container(depth, b'}'),
+            Some(b'[') => self.container(depth, b']'),
+            Some(b'"') => self.string(),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(b't') => self.literal("true"),
+            Some(b'f') => self.literal("false"),
+            Some(b'n') => self.literal("null"),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    /// An object or array, which ends with `close`.
+    fn container(&mut self, depth: usize, close: u8) -> Result<(), String> {
+        if depth == MAX_DEPTH {
+            return Err(format!("nested deeper than {} levels", MAX_DEPTH));
+        }
+        self.output.push(self.input[self.position]);
+        self.position += 1;
+        self.skip_whitespace();
+        if self.peek() == Some(close) {
+            return self.expect(close);
+        }
+        loop {
+            self.newline(depth + 1);
+            if close == b'}' {
+                self.string()?;
+                self.skip_whitespace();
+                self.expect(b':')?;
+                if self.format == JsonFormat::Pretty {
+                    self.output.push(b' ');
+                }
+                self.skip_whitespace();
+            }
+            self.value(depth + 1)?;
+            self.skip_whitespace();
+            if self.peek() == Some(b',') {
+                self.expect(b',')?;
+                self.skip_whitespace();
+            } else {
+                self.newline(depth);
+                return self.expect(close);
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<(), String> {
+        let start = self.position;
+        if self.peek() != Some(b'"') {
+            return Err(self.unexpected());
+        }
+        self.position += 1;
+        loop {
+            match self.peek() {
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    self.position += 1;
+                    match self.peek() {
+                        Some(b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't') => {
+                            self.position += 1;
+                        }
+                        Some(b'u') => {
+                            self.position += 1;
+                            for _ in 0..4 {
+                                match self.peek() {
+                                    Some(b) if b.is_ascii_hexdigit() => self.position += 1,
+                                    _ => return Err(self.unexpected()),
+                                }
+                            }
+                        }
+                        _ => return Err(self.unexpected()),
+                    }
+                }
+                Some(0x00..=0x1f) | None => return Err(self.unexpected()),
+                Some(_) => self.position += 1,
+            }
+        }
+        self.position += 1;
+        self.output
+            .extend_from_slice(&self.input[start..self.position]);
+        Ok(())
+    }
+
+    fn number(&mut self) -> Result<(), String> {
+        let start = self.position;
+        if self.peek() == Some(b'-') {
+            self.position += 1;
+        }
+        match self.peek() {
+            Some(b'0') => self.position += 1,
+            Some(b'1'..=b'9') => self.digits(),
+            _ => return Err(self.unexpected()),
+        }
+        if self.peek() == Some(b'.') {
+            self.position += 1;
+            self.required_digits()?;
+        }
+        if let Some(b'e' | b'E') = self.peek() {
+            self.position += 1;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.position += 1;
+            }
+            self.required_digits()?;
+        }
+        self.output
+            .extend_from_slice(&self.input[start..self.position]);
+        Ok(())
+    }
+
+    fn digits(&mut self) {
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.position += 1;
+        }
+    }
+
+    fn required_digits(&mut self) -> Result<(), String> {
+        match self.peek() {
+            Some(b'0'..=b'9') => {
+                self.digits();
+                Ok(())
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn literal(&mut self, literal: &str) -> Result<(), String> {
+        if self.input[self.position..].starts_with(literal.as_bytes()) {
+            self.position += literal.len();
+            self.output.extend_from_slice(literal.as_bytes());
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minify(content: &str) -> Result<String, String> {
+        reformat(content.as_bytes(), JsonFormat::Minify).map(|out| String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn recognizes_json_content_types() {
+        assert!(is_json("application/json"));
+        assert!(is_json("application/json; charset=utf-8"));
+        assert!(is_json("application/ld+json"));
+        assert!(!is_json("text/json"));
+        assert!(!is_json("application/jsonl"));
+    }
+
+    #[test]
+    fn minifies_without_changing_tokens() {
+        assert_eq!(
+            minify(" { \"b\" : [ 1 , -2.50e+3, true, null ],\n\t\"a\": \"x \\\" \\u00e9\" } ")
+                .as_deref(),
+            Ok(r#"{"b":[1,-2.50e+3,true,null],"a":"x \" \u00e9"}"#)
+        );
+        assert_eq!(minify("[ ]").as_deref(), Ok("[]"));
+        assert_eq!(minify(" 0 ").as_deref(), Ok("0"));
+    }
+
+    #[test]
+    fn pretty_prints_with_two_spaces() {
+        let pretty = reformat(br#"{"a":[1,{}],"b":{"c":null}}"#, JsonFormat::Pretty).unwrap();
+        assert_eq!(
+            String::from_utf8(pretty).unwrap(),
+            "{\n  \"a\": [\n    1,\n    {}\n  ],\n  \"b\": {\n    \"c\": null\n  }\n}\n"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_documents() {
+        for content in [
+            "",
+            "[1,]",
+            "{\"a\" 1}",
+            "{1:2}",
+            "01",
+            "1.",
+            "-",
+            "1e",
+            "\"\\x\"",
+            "\"\\u12\"",
+            "\"tab\tinside\"",
+            "\"unterminated",
+            "nul",
+            "[1] [2]",
+        ] {
+            assert!(minify(content).is_err(), "{:?}", content);
+        }
+        assert_eq!(
+            reformat(b"\"\xff\"", JsonFormat::Minify),
+            Err(String::from("not valid UTF-8"))
+        );
+        assert_eq!(
+            minify("[1 2]"),
+            Err(String::from("unexpected '2' at byte 3"))
+        );
+    }
+
+    #[test]
+    fn limits_nesting() {
+        let nested = |depth| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(minify(&nested(MAX_DEPTH)).is_ok());
+        assert_eq!(
+            minify(&nested(MAX_DEPTH + 1)),
+            Err(String::from("nested deeper than 128 levels"))
+        );
+    }
+}
@@ -0,0 +1,83 @@
+//! Content negotiation on `Accept-Language`, for `--negotiate-language`. Language variants of an
+//! HTML file carry the language tag before the extension, e.g. `/index.de.html` is the German
+//! variant of `/index.html`.
+
+use hyper::header::ACCEPT_LANGUAGE;
+use hyper::HeaderMap;
+
+/// If `path` is a language variant, return the path of the file it is a variant of along with
+/// its language tag.
+pub fn variant_of(path: &str) -> Option<(String, String)> {
+    let (dir, file_name) = path.rsplit_once('/')?;
+    let (stem, extension) = file_name.rsplit_once('.')?;
+    if extension != "html" && extension != "htm" {
+        return None;
+    }
+    let (name, tag) = stem.rsplit_once('.')?;
+    if name.is_empty() || !is_language_tag(tag) {
+        return None;
+    }
+    let base = format!("{}/{}.{}", dir, name, extension);
+    Some((base, tag.to_string()))
+}
+
+/// A language tag such as `en` or `pt-BR`. This only accepts tags with a two-letter primary
+/// language, so that e.g. `page.min.html` is not mistaken for a variant.
+fn is_language_tag(tag: &str) -> bool {
+    let mut subtags = tag.split('-');
+    let primary = subtags.next().unwrap_or("");
+    primary.len() == 2
+        && primary.bytes().all(|b| b.is_ascii_alphabetic())
+        && subtags
+            .all(|s| (1..=8).contains(&s.len()) && s.bytes().all(|b| b.is_ascii_alphanumeric()))
+}
+
+/// The languages accepted by the client, most preferred first.
+fn accepted_languages(headers: &HeaderMap) -> Vec<String> {
+    let mut languages: Vec<(String, f32)> = headers
+        .get_all(ACCEPT_LANGUAGE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let tag = parts.next()?.trim().to_lowercase();
+            let quality = parts
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if tag.is_empty() || quality <= 0.0 {
+                None
+            } else {
+                Some((tag, quality))
+            }
+        })
+        .collect();
+    // A stable sort keeps the order of the header for equal qualities
+    languages.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    languages.into_iter().map(|(tag, _)| tag).collect()
+}
+
+/// Choose the variant best matching the `Accept-Language` header from `variants`, which are
+/// pairs of lowercased language tag and path. A requested `de` matches a `de-at` variant and vice versa,
+/// though exact matches are preferred.
+pub fn choose<'a>(headers: &HeaderMap, variants: &'a [(String, String)]) -> Option<&'a str> {
+    for language in accepted_languages(headers) {
+        if language == "*" {
+            return None;
+        }
+        let primary = language.split('-').next().unwrap_or("");
+        let found = variants
+            .iter()
+            .find(|(tag, _)| *tag == language)
+            .or_else(|| {
+                variants
+                    .iter()
+                    .find(|(tag, _)| tag.split('-').next() == Some(primary))
+            });
+        if let Some((_, path)) = found {
+            return Some(path);
+        }
+    }
+    None
+}
@@ -0,0 +1,2201 @@
+//! Serve files from memory. All files are loaded into a cache up front, either from a directory,
+//! an archive or the files embedded into the binary at build time. Alternatively, files can be
+//! read from a directory on every request.
+
+pub mod access_log;
+pub mod admin;
+mod archive;
+pub mod auth;
+mod checksum;
+pub mod compression;
+pub mod config_file;
+pub mod connection;
+pub mod cors;
+mod disposition;
+#[cfg(feature = "embed")]
+mod embedded;
+pub mod error;
+pub mod forwarded;
+mod glob;
+pub mod json;
+mod language;
+pub mod mime;
+pub mod panics;
+pub mod path;
+pub mod per_ip;
+pub mod preload;
+mod print_config;
+mod range;
+mod redirects;
+pub mod server;
+mod sitemap;
+pub mod stats;
+pub mod subst;
+pub mod telemetry;
+pub mod user_agent;
+pub mod validate;
+pub mod vars;
+pub mod vhost;
+pub mod watch;
+mod writable;
+
+use bytes::Bytes;
+use hyper::body::HttpBody;
+use hyper::header::{
+    HeaderValue, ACCEPT_ENCODING, ACCEPT_LANGUAGE, ACCEPT_RANGES, AGE, ALLOW, CACHE_CONTROL,
+    CONTENT_DISPOSITION, CONTENT_ENCODING, CONTENT_LANGUAGE, CONTENT_LENGTH, CONTENT_RANGE,
+    CONTENT_TYPE, ETAG, HOST, IF_NONE_MATCH, IF_RANGE, LINK, LOCATION, ORIGIN, RANGE, RETRY_AFTER,
+    SERVER, TE, USER_AGENT, VARY, WWW_AUTHENTICATE, X_CONTENT_TYPE_OPTIONS,
+};
+use hyper::http::uri::Builder;
+use hyper::{Body, HeaderMap, Method, Request, Response, StatusCode};
+use log::{debug, error, info, warn};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::fmt;
+use std::fs;
+use std::fs::read;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use access_log::{AccessLogFormat, Entry, RemoteAddr};
+use auth::BasicAuth;
+use compression::{Compression, Encoding};
+use cors::Cors;
+use disposition::Attachments;
+use error::Error;
+use forwarded::ProxyHeaders;
+use json::JsonFormat;
+use mime::MimeTypes;
+use preload::Preloads;
+use range::Ranges;
+use redirects::Redirects;
+use subst::Substitutions;
+use user_agent::UserAgentRules;
+
+/// How much of a URI beyond `--max-uri-length` is logged.
+const MAX_LOGGED_URI: usize = 256;
+
+/// How much of the body of a `GET` or `HEAD` request is read and discarded, so the connection
+/// can be reused. Hyper closes connections with a larger body instead.
+const MAX_DRAINED_BODY: usize = 64 * 1024;
+
+#[derive(Clone)]
+pub struct Config {
+    /// The directory or archive to serve, or `None` to serve the embedded files.
+    pub dir: Option<String>,
+    /// Directories (or archives) to fall back to, in order, for files which `dir` doesn't
+    /// contain.
+    pub fallback_dirs: Vec<String>,
+    pub address: IpAddr,
+    /// The scope (zone) of a link-local IPv6 `address`, as a network interface index.
+    pub scope_id: Option<u32>,
+    pub port: u16,
+    /// Refuse to listen on anything but a loopback address, such as `127.0.0.1` or `::1`.
+    pub local_only: bool,
+    /// The port of the admin API, which is only served if set.
+    pub admin_port: Option<u16>,
+    pub admin_address: IpAddr,
+    /// The `USER:PASSWORD` required for the admin API.
+    pub admin_auth: Option<String>,
+    pub redirect_http: bool,
+    pub threads: usize,
+    pub archive: bool,
+    /// Serve the files of a tar archive streamed to standard input, instead of `dir`.
+    pub stdin_tar: bool,
+    pub stats_interval: Option<Duration>,
+    pub etag_mode: EtagMode,
+    /// Methods other than GET and HEAD which are answered with a fixed status instead of a
+    /// `405 Method Not Allowed`.
+    pub allowed_methods: HashMap<Method, StatusCode>,
+    pub mime_types_file: Option<PathBuf>,
+    pub mime_overrides: Vec<(String, String)>,
+    pub sniff: bool,
+    /// Files which are served from memory without existing on disk, keyed by request path.
+    pub virtual_files: Vec<(String, Vec<u8>)>,
+    pub max_age: Option<u64>,
+    /// The path answered with `200 OK` for liveness checks, even before files are loaded.
+    pub health_path: Option<String>,
+    /// The path answered with a JSON summary of the server's state, for monitoring.
+    pub status_path: Option<String>,
+    /// Bind before loading files instead of after, answering `503` until they are loaded.
+    pub background_load: bool,
+    /// Keep answering `503` for this long after the files are loaded, before reporting ready.
+    pub ready_delay: Option<Duration>,
+    pub compression: Compression,
+    /// Read files from `dir` on every request instead of loading them into memory.
+    pub no_cache: bool,
+    /// A prefix of the paths in `dir` which isn't part of the request paths, like `/dist`. Files
+    /// outside of it aren't served.
+    pub strip_prefix_on_disk: Option<String>,
+    /// A prefix of the request paths which isn't part of the paths in `dir`, like `/static`.
+    pub add_url_prefix: Option<String>,
+    /// Serve each directory directly below `dir` as a namespace of its own, at the path of its
+    /// name, which no request or symlink can leave.
+    pub dir_per_request: bool,
+    /// Serve only files at exactly the requested path, without falling back to `index.html` for
+    /// directories.
+    pub no_index_fallback: bool,
+    /// The file names tried in turn for requests to a directory.
+    pub index_files: Vec<String>,
+    /// Pairs of directory (like `/docs/`) and the file names tried instead of `index_files` for
+    /// requests to it and the directories below it. The longest matching directory wins.
+    pub dir_index_files: Vec<(String, Vec<String>)>,
+    /// The path of the file served for requests to `/`, instead of `/index.html`.
+    pub root_document: Option<String>,
+    /// Serve `FILE.gz` for requests to `FILE` if only the former exists, decompressed for clients
+    /// which don't accept gzip.
+    pub precompressed_gzip: bool,
+    /// Serve `FILE.zst` for requests to `FILE` to clients which accept zstd.
+    pub precompressed_zstd: bool,
+    /// The encodings to send files in, most preferred first, among those the client accepts.
+    pub encoding_order: Vec<Encoding>,
+    /// Glob patterns of files which are served as attachments, to be downloaded.
+    pub attachments: Vec<String>,
+    /// Bind with `SO_REUSEPORT`, so that several processes can listen on the same port.
+    pub reuse_port: bool,
+    /// Fall back to matching request paths against cached files regardless of case.
+    pub case_insensitive: bool,
+    /// Ignore a trailing `.` in request paths, e.g. serve `/page` for `/page.`.
+    pub strip_trailing_dot: bool,
+    /// The page served with `503 Service Unavailable` while in maintenance mode.
+    pub maintenance_page: Option<PathBuf>,
+    /// `Link` preload hints for the files matching a glob pattern, see `preload::parse_preload`.
+    pub preloads: Vec<(String, String)>,
+    /// Prefixes to serve files from to the user agents matching a pattern, in order, see
+    /// `user_agent::parse_ua_rule`.
+    pub ua_rules: Vec<(String, String)>,
+    /// Watch `dir` for changes, applying them to the cache once none have happened for the
+    /// given duration.
+    pub watch: Option<Duration>,
+    /// A file of redirect rules, which are checked before looking up files.
+    pub redirects_file: Option<PathBuf>,
+    /// The status of the https and canonical host redirects: `301 Moved Permanently` or, to make clients
+    /// repeat the request with the same method, `308 Permanent Redirect`.
+    pub redirect_status: StatusCode,
+    /// Send the https and canonical host redirects without the HTML body linking to the target.
+    pub no_redirect_body: bool,
+    /// Directories served instead of `dir` for requests to the given hosts. Hosts like
+    /// `*.example.com` match all subdomains.
+    pub vhosts: Vec<(String, String)>,
+    /// The `Server` header sent with every response, if any.
+    pub server_header: Option<String>,
+    /// Generate `/sitemap.xml` and `/robots.txt` with absolute URLs below this URL, unless
+    /// there are files at these paths.
+    pub sitemap_url: Option<String>,
+    /// Accept `PUT` and `DELETE` requests, which store and remove files in `dir`.
+    pub writable: bool,
+    /// Answer uploads with `writable` which are larger than this with `413 Payload Too Large`.
+    pub max_upload_size: u64,
+    /// Send `Age: 0` with files, for intermediary caches.
+    pub age_header: bool,
+    /// The template for access log lines, see `access_log::AccessLogFormat`. Without one, a
+    /// built-in format is used.
+    pub access_log_format: Option<String>,
+    /// Leave the requests answered with `404 Not Found` out of the log.
+    pub no_log_404: bool,
+    /// The origins allowed to make cross-origin requests, or `*` for any. Without any, no CORS
+    /// headers are sent.
+    pub cors_allow_origins: Vec<String>,
+    /// The methods in responses to preflight requests, by default those which are answered.
+    pub cors_allow_methods: Vec<String>,
+    /// The request headers in responses to preflight requests.
+    pub cors_allow_headers: Vec<String>,
+    /// Allow whichever request headers preflight requests ask for, instead of `cors_allow_headers`.
+    pub cors_reflect_headers: bool,
+    /// How many seconds browsers may cache the responses to preflight requests for.
+    pub cors_max_age: Option<u64>,
+    /// Serve language variants such as `/index.de.html` for `/index.html` based on the
+    /// `Accept-Language` header, falling back to the variant in this language.
+    pub default_language: Option<String>,
+    /// The file to write the process ID to, for service supervision.
+    pub pid_file: Option<PathBuf>,
+    /// Redirect requests for any other host to this one.
+    pub canonical_host: Option<String>,
+    /// The number of connections served at the same time. Requests on further connections are
+    /// answered with `503 Service Unavailable`.
+    pub max_connections: Option<usize>,
+    /// The number of requests each client IP address may have in flight at the same time.
+    /// Further requests are answered with `429 Too Many Requests`.
+    pub max_concurrent_per_ip: Option<usize>,
+    /// Pairs of path prefix and `USER:PASSWORD` required for requests below the prefix, see
+    /// `auth::parse_basic_auth`.
+    pub basic_auth: Vec<(String, String)>,
+    /// Answer requests which take longer than this with `504 Gateway Timeout`.
+    pub request_timeout: Option<Duration>,
+    /// Drop connections which haven't sent a request this long after they were accepted.
+    pub connection_idle_timeout: Option<Duration>,
+    /// Request paths to read into the cache at startup even with `no_cache`.
+    pub preload_paths: Vec<String>,
+    /// The number of requests served on an HTTP/1 connection before it is closed.
+    pub keep_alive_max_requests: Option<usize>,
+    /// The length of the queue of connections waiting to be accepted.
+    pub listen_backlog: Option<i32>,
+    /// Send a `SourceMap` header with scripts and stylesheets which have a `.map` file next to
+    /// them.
+    pub source_maps: bool,
+    /// Warn about world-writable files and directories while loading `dir`.
+    pub check_permissions: bool,
+    /// Send the SHA-256 of files as trailer to clients which accept trailers.
+    pub checksum_trailer: bool,
+    /// Answer `GET` and `HEAD` requests with a body with `400 Bad Request`, rather than
+    /// ignoring the body.
+    pub reject_get_body: bool,
+    /// Answer requests whose path and query are longer than this with `414 URI Too Long`.
+    pub max_uri_length: usize,
+    /// Abort the process when a request handler panics, rather than serve on.
+    pub abort_on_panic: bool,
+    /// The header set by the proxy in front of us, and its value for requests made over plain
+    /// http, which the https redirect is based on.
+    pub proto_header: (String, String),
+    /// The proxy headers trusted for the client address, host and scheme.
+    pub proxy_headers: ProxyHeaders,
+    /// Values for placeholders in text files, by name.
+    pub substitutions: Vec<(String, String)>,
+    /// The syntax of placeholders, where `NAME` stands for the name of the value.
+    pub subst_pattern: String,
+    /// Answer requests for empty files with `204 No Content` rather than `200 OK`.
+    pub empty_as_204: bool,
+    /// Reformat JSON files when loading them, rather than serving them as they are.
+    pub json_format: Option<JsonFormat>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            dir: None,
+            fallback_dirs: Vec::new(),
+            address: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            scope_id: None,
+            port: 3000,
+            local_only: false,
+            admin_port: None,
+            admin_address: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            admin_auth: None,
+            redirect_http: false,
+            threads: num_cpus::get(),
+            archive: false,
+            stdin_tar: false,
+            stats_interval: None,
+            etag_mode: EtagMode::Strong,
+            allowed_methods: HashMap::new(),
+            mime_types_file: None,
+            mime_overrides: Vec::new(),
+            sniff: false,
+            virtual_files: Vec::new(),
+            max_age: None,
+            health_path: Some(String::from("/healthz")),
+            status_path: None,
+            background_load: false,
+            ready_delay: None,
+            compression: Compression::default(),
+            no_cache: false,
+            strip_prefix_on_disk: None,
+            add_url_prefix: None,
+            dir_per_request: false,
+            no_index_fallback: false,
+            index_files: vec![String::from("index.html")],
+            dir_index_files: Vec::new(),
+            root_document: None,
+            precompressed_gzip: false,
+            precompressed_zstd: false,
+            encoding_order: vec![Encoding::Zstd, Encoding::Gzip],
+            attachments: Vec::new(),
+            reuse_port: false,
+            case_insensitive: false,
+            strip_trailing_dot: false,
+            maintenance_page: None,
+            preloads: Vec::new(),
+            ua_rules: Vec::new(),
+            watch: None,
+            redirects_file: None,
+            redirect_status: StatusCode::MOVED_PERMANENTLY,
+            no_redirect_body: false,
+            vhosts: Vec::new(),
+            server_header: None,
+            sitemap_url: None,
+            writable: false,
+            max_upload_size: 100 * 1024 * 1024,
+            age_header: false,
+            access_log_format: None,
+            no_log_404: false,
+            cors_allow_origins: Vec::new(),
+            cors_allow_methods: Vec::new(),
+            cors_allow_headers: Vec::new(),
+            cors_reflect_headers: false,
+            cors_max_age: None,
+            default_language: None,
+            pid_file: None,
+            canonical_host: None,
+            max_connections: None,
+            max_concurrent_per_ip: None,
+            basic_auth: Vec::new(),
+            request_timeout: None,
+            connection_idle_timeout: None,
+            preload_paths: Vec::new(),
+            keep_alive_max_requests: None,
+            listen_backlog: None,
+            source_maps: false,
+            check_permissions: false,
+            checksum_trailer: false,
+            reject_get_body: false,
+            max_uri_length: 8 * 1024,
+            abort_on_panic: false,
+            proto_header: (String::from("x-forwarded-proto"), String::from("http")),
+            proxy_headers: ProxyHeaders::Ignore,
+            substitutions: Vec::new(),
+            subst_pattern: String::from("{{NAME}}"),
+            json_format: None,
+            empty_as_204: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum EtagMode {
+    /// A hash of the file contents.
+    Strong,
+    /// Derived from the file size and modification time, which avoids hashing every file.
+    Weak,
+    Off,
+}
+
+/// A file as read from the directory or archive being served.
+struct SourceFile {
+    content: Vec<u8>,
+    modified: Option<SystemTime>,
+}
+
+/// Walk `dir` and read every file below it into a map from request path to file, optionally
+/// warning about files and directories with unsafe permissions.
+fn load_directory(
+    dir: PathBuf,
+    check_permissions: bool,
+) -> Result<HashMap<String, SourceFile>, Error> {
+    let mut cache: HashMap<String, SourceFile> = HashMap::new();
+    let mut to_visit: VecDeque<PathBuf> = VecDeque::from(vec![dir.clone()]);
+    while !to_visit.is_empty() {
+        match to_visit.pop_front() {
+            Some(item) => {
+                if check_permissions {
+                    warn_if_writable(&item);
+                }
+                if item.is_dir() {
+                    let context = || format!("Failed to read directory {}", item.display());
+                    let children = fs::read_dir(&item).map_err(Error::io(context()))?;
+                    for child in children {
+                        to_visit.push_back(child.map_err(Error::io(context()))?.path());
+                    }
+                } else {
+                    let path = cache_key(&dir, &item)?;
+                    let modified = fs::metadata(&item).and_then(|m| m.modified()).ok();
+                    let content = read(&item)
+                        .map_err(Error::io(format!("Failed to read file {}", item.display())))?;
+                    debug!("Loaded {} bytes from {}", content.len(), path);
+                    cache.insert(path, SourceFile { content, modified });
+                }
+            }
+            None => {
+                warn!("Queue was empty. This was not expected.");
+            }
+        }
+    }
+    Ok(cache)
+}
+
+/// Warn if anyone may modify `path`, which lets anyone on the machine change what is served.
+#[cfg(unix)]
+fn warn_if_writable(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Ok(metadata) = fs::metadata(path) {
+        let mode = metadata.permissions().mode();
+        if mode & 0o002 != 0 {
+            let kind = if metadata.is_dir() {
+                "Directory"
+            } else {
+                "File"
+            };
+            warn!(
+                "{} {} is world-writable ({:o})",
+                kind,
+                path.display(),
+                mode & 0o7777
+            );
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn warn_if_writable(_path: &Path) {}
+
+fn etag(file: &SourceFile, mode: EtagMode) -> Option<String> {
+    match mode {
+        EtagMode::Strong => {
+            let mut hasher = DefaultHasher::new();
+            file.content.hash(&mut hasher);
+            Some(format!("\"{:016x}\"", hasher.finish()))
+        }
+        EtagMode::Weak => {
+            let modified = file
+                .modified
+                .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_secs());
+            Some(format!("W/\"{:x}-{:x}\"", file.content.len(), modified))
+        }
+        EtagMode::Off => None,
+    }
+}
+
+/// Whether the `Range` of `req` applies, given the ETag of the file. With `If-Range`, it only
+/// does if the file still has the given (strong) ETag. Dates are never matched, as files have no
+/// `Last-Modified`.
+fn if_range_matches(req: &Request<Body>, etag: Option<&str>) -> bool {
+    match req.headers().get(IF_RANGE) {
+        None => true,
+        Some(if_range) => etag
+            .is_some_and(|etag| !etag.starts_with("W/") && if_range.as_bytes() == etag.as_bytes()),
+    }
+}
+
+/// Read and discard `body`, up to `MAX_DRAINED_BODY` bytes. Returns whether it was read to the
+/// end, or failed to read.
+async fn drain(body: &mut Body) -> bool {
+    let mut drained = 0;
+    while let Some(chunk) = body.data().await {
+        match chunk {
+            Ok(chunk) => drained += chunk.len(),
+            Err(_) => return true,
+        }
+        if drained > MAX_DRAINED_BODY {
+            return false;
+        }
+    }
+    true
+}
+
+/// The entity tag of the variant of a file with `etag` which is encoded with `encoding`.
+fn encoded_etag(etag: &str, encoding: Encoding) -> String {
+    match etag.strip_suffix('"') {
+        Some(opaque) => format!("{}-{}\"", opaque, encoding.name()),
+        None => etag.to_string(),
+    }
+}
+
+/// Weak comparison of two entity tags, as used for `If-None-Match`.
+fn etag_matches(a: &str, b: &str) -> bool {
+    fn opaque(tag: &str) -> &str {
+        tag.strip_prefix("W/").unwrap_or(tag)
+    }
+    opaque(a) == opaque(b)
+}
+
+/// Whether the `If-None-Match` headers list `etag` (by weak comparison) or are `*`, in which case
+/// the client's copy is still current.
+fn if_none_match(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .any(|v| v.trim() == "*" || entity_tags(v).any(|tag| etag_matches(tag, etag)))
+}
+
+/// The entity tags in a comma-separated list like `"a", W/"b"`. Tags may contain commas
+/// themselves, so the list is split outside quotes only. Malformed elements are skipped.
+fn entity_tags(list: &str) -> impl Iterator<Item = &str> {
+    let mut rest = list;
+    std::iter::from_fn(move || loop {
+        rest = rest.trim_start_matches([',', ' ', '\t']);
+        if rest.is_empty() {
+            return None;
+        }
+        let opaque_start = if rest.starts_with("W/\"") { 3 } else { 1 };
+        if rest.starts_with('"') || opaque_start == 3 {
+            if let Some(len) = rest[opaque_start..].find('"') {
+                let end = opaque_start + len + 1;
+                let (tag, remainder) = rest.split_at(end);
+                rest = remainder;
+                return Some(tag);
+            }
+        }
+        // Not a tag, or one without its closing quote
+        rest = rest.find(',').map_or("", |comma| &rest[comma..]);
+    })
+}
+
+/// Compressed variants of a file which were found next to it, rather than compressed by us.
+#[derive(Default)]
+struct Precompressed {
+    gzip: Option<Bytes>,
+    zstd: Option<Bytes>,
+}
+
+struct CachedFile {
+    content: Bytes,
+    content_type: String,
+    /// Whether the content type was guessed from the content
+    sniffed: bool,
+    etag: Option<String>,
+    /// The gzip-compressed content, if it is worth compressing
+    gzip: Option<Bytes>,
+    /// The zstd-compressed content, from a precompressed `.zst` file
+    zstd: Option<Bytes>,
+    content_disposition: Option<String>,
+    link: Option<String>,
+    /// The language of a language variant, with `--negotiate-language`
+    language: Option<String>,
+    /// The SHA-256 of the content, with `--checksum-trailer`
+    sha256: Option<String>,
+}
+
+/// The request path at which `file` within `dir` is served. Request paths are absolute and always
+/// use `/` as separator, whereas the paths we get from the filesystem are relative to `dir` (which
+/// may or may not end in a separator) and use the platform's separator. Files outside of `dir` and
+/// paths which aren't Unicode have no request path.
+fn cache_key(dir: &Path, file: &Path) -> Result<String, Error> {
+    let unusable = |reason: &str| Error::Io {
+        context: format!("Unable to serve {}", file.display()),
+        source: io::Error::new(io::ErrorKind::InvalidData, reason),
+    };
+    let relative = file
+        .strip_prefix(dir)
+        .map_err(|_| unusable("not in the served directory"))?;
+    let mut key = String::new();
+    for component in relative.components() {
+        let component = component
+            .as_os_str()
+            .to_str()
+            .ok_or_else(|| unusable("the path isn't Unicode"))?;
+        key.push('/');
+        key.push_str(component);
+    }
+    Ok(key)
+}
+
+/// A short page linking to the target of a redirect, for clients which show the body rather than
+/// follow the `Location`.
+fn redirect_page(status: StatusCode, location: &str) -> String {
+    let title = format!(
+        "{} {}",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or("Redirect")
+    );
+    let location = sitemap::escape_xml(location);
+    format!(
+        "<html>\n<head><title>{title}</title></head>\n<body>\n<h1>{title}</h1>\n\
+         <p><a href=\"{location}\">{location}</a></p>\n</body>\n</html>\n",
+        title = title,
+        location = location
+    )
+}
+
+/// Read the file at request `path` below `dir` from disk, for `--no-cache`.
+async fn read_uncached(dir: &Path, path: &str) -> io::Result<Option<SourceFile>> {
+    // `path` is normalized, so it has no `..` segments, but it may still lead outside of
+    // `dir` through a symlink
+    let file_path = match tokio::fs::canonicalize(dir.join(&path[1..])).await {
+        Ok(file_path) => file_path,
+        Err(e) if is_not_found(&e) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if !file_path.starts_with(dir) {
+        warn!("Refusing to serve {} outside of the served directory", path);
+        return Ok(None);
+    }
+    let metadata = tokio::fs::metadata(&file_path).await?;
+    if metadata.is_dir() {
+        return Ok(None);
+    }
+    let content = match tokio::fs::read(&file_path).await {
+        Ok(content) => content,
+        Err(e) if is_not_found(&e) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    Ok(Some(SourceFile {
+        content,
+        modified: metadata.modified().ok(),
+    }))
+}
+
+/// The response to requests without the credentials required by `--basic-auth`.
+fn unauthorized() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .header(
+            WWW_AUTHENTICATE,
+            "Basic realm=\"httpserve\", charset=\"UTF-8\"",
+        )
+        .body(Body::empty())
+        .expect("Unable to create `http::Response`")
+}
+
+/// Whether reading a file failed because it (or one of its parent directories) doesn't exist.
+fn is_not_found(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::NotFound | io::ErrorKind::NotADirectory
+    )
+}
+
+/// What a request resolves to. Only files get the file-oriented handling (conditional requests,
+/// compression and so on), generated responses such as health checks and redirects are sent as
+/// they are.
+enum Resource {
+    File(FoundFile),
+    Generated(Response<Body>),
+    /// No file exists at the requested path
+    NotFound,
+    /// A `PUT` or `DELETE` request, with `--writable`
+    Modification,
+    /// The response to a CORS preflight request, which has its CORS headers already
+    Preflight(Response<Body>),
+}
+
+/// The response for paths without a file. Every way of not finding a file ends up here, so none
+/// of the headers describing a file (`ETag`, `Accept-Ranges`, `Vary` and so on) are ever sent
+/// along with it, and conditional and range requests for missing files get a plain `404` rather
+/// than `304` or `416`.
+fn not_found() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(Body::empty())
+        .expect("Unable to create `http::Response`")
+}
+
+/// A file to serve, along with what we learned while looking it up.
+struct FoundFile {
+    file: Arc<CachedFile>,
+    cache_status: CacheStatus,
+    /// Whether the file was chosen among language variants
+    negotiated: bool,
+    /// The path of the file's source map, with `--source-maps`
+    source_map: Option<String>,
+}
+
+/// Whether a file was served from the cache, or had to be read from disk.
+#[derive(Clone, Copy)]
+enum CacheStatus {
+    Hit,
+    Miss,
+}
+
+impl fmt::Display for CacheStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CacheStatus::Hit => "hit",
+            CacheStatus::Miss => "miss",
+        })
+    }
+}
+
+#[derive(Default)]
+struct Cache {
+    files: HashMap<String, Arc<CachedFile>>,
+    /// The files keyed by lowercased request path, for `--case-insensitive` lookups. If several
+    /// paths only differ in case, the first one in sort order wins.
+    lowercase: HashMap<String, Arc<CachedFile>>,
+    /// The paths of the files which were generated rather than loaded, such as the sitemap
+    generated: Vec<String>,
+    /// The language variants of each path, as pairs of language and path of the variant
+    variants: HashMap<String, Vec<(String, String)>>,
+}
+
+impl Cache {
+    fn new(
+        files: HashMap<String, Arc<CachedFile>>,
+        generated: Vec<String>,
+        case_insensitive: bool,
+        negotiate_language: bool,
+    ) -> Cache {
+        let mut lowercase = HashMap::new();
+        if case_insensitive {
+            let mut paths: Vec<&String> = files.keys().collect();
+            paths.sort_unstable();
+            for path in paths {
+                lowercase
+                    .entry(path.to_lowercase())
+                    .or_insert_with(|| Arc::clone(&files[path]));
+            }
+        }
+        let mut variants: HashMap<String, Vec<(String, String)>> = HashMap::new();
+        if negotiate_language {
+            for path in files.keys() {
+                if let Some((base, language)) = language::variant_of(path) {
+                    variants
+                        .entry(base)
+                        .or_default()
+                        .push((language.to_lowercase(), path.clone()));
+                }
+            }
+            // Make the choice between e.g. `de-at` and `de-ch` for `de` deterministic
+            variants.values_mut().for_each(|v| v.sort_unstable());
+        }
+        Cache {
+            files,
+            lowercase,
+            generated,
+            variants,
+        }
+    }
+
+    fn get(&self, path: &str) -> Option<&Arc<CachedFile>> {
+        self.files.get(path).or_else(|| {
+            if self.lowercase.is_empty() {
+                None
+            } else {
+                self.lowercase.get(&path.to_lowercase())
+            }
+        })
+    }
+}
+
+pub struct FileServer {
+    cache: RwLock<Arc<Cache>>,
+    ready: AtomicBool,
+    /// Whether `load` leaves it to `mark_ready` to report ready, for `ready_delay`
+    delay_ready: bool,
+    health_path: Option<String>,
+    status_path: Option<String>,
+    started: Instant,
+    /// The number of cached files and their total size, for the status endpoint
+    cached_files: AtomicUsize,
+    cached_bytes: AtomicU64,
+    http_to_https_redirect: bool,
+    /// The header telling whether a request was made over plain http, and its value if so
+    proto_header: (String, String),
+    proxy_headers: ProxyHeaders,
+    canonical_host: Option<String>,
+    redirect_status: StatusCode,
+    redirect_body: bool,
+    allowed_methods: HashMap<Method, StatusCode>,
+    mime_types: MimeTypes,
+    sniff: bool,
+    cache_control: Option<String>,
+    etag_mode: EtagMode,
+    compression: Compression,
+    /// The directory to read files from on every request, with `--no-cache`
+    uncached_dir: Option<PathBuf>,
+    /// The directory to store files in, with `--writable`
+    writable_dir: Option<PathBuf>,
+    max_upload_size: u64,
+    attachments: Attachments,
+    preloads: Preloads,
+    user_agent_rules: UserAgentRules,
+    case_insensitive: bool,
+    strip_trailing_dot: bool,
+    index_fallback: bool,
+    index_files: Vec<String>,
+    dir_index_files: Vec<(String, Vec<String>)>,
+    root_document: Option<String>,
+    precompressed_gzip: bool,
+    precompressed_zstd: bool,
+    dir_per_request: bool,
+    encoding_order: Vec<Encoding>,
+    maintenance: AtomicBool,
+    maintenance_page: Bytes,
+    redirects: Redirects,
+    basic_auth: BasicAuth,
+    server_header: Option<HeaderValue>,
+    /// The URL of the site, if a sitemap is generated
+    site_url: Option<String>,
+    age_header: bool,
+    access_log: Option<AccessLogFormat>,
+    log_404: bool,
+    cors: Cors,
+    request_timeout: Option<Duration>,
+    source_maps: bool,
+    checksum_trailer: bool,
+    reject_get_body: bool,
+    max_uri_length: usize,
+    substitutions: Substitutions,
+    json_format: Option<JsonFormat>,
+    empty_as_204: bool,
+    /// The (lowercased) fallback language, with `--negotiate-language`
+    default_language: Option<String>,
+}
+
+/// Load the files from the configured path (or the embedded files, if none is configured),
+/// along with any virtual files. Files in the fallback directories are added unless a file at
+/// the same path was found in one of the directories before.
+fn load_files(config: &Config) -> Result<HashMap<String, SourceFile>, Error> {
+    let mut files = match &config.dir {
+        // Files are read on demand instead, except for those to preload
+        Some(dir) if config.no_cache => {
+            let files = preload_files(dir, &config.preload_paths)?;
+            if config.dir_per_request {
+                confine_to_namespaces(Some(Path::new(dir)), files)?
+            } else {
+                files
+            }
+        }
+        Some(dir) => {
+            let mut files = load_path(dir, config)?;
+            for dir in &config.fallback_dirs {
+                info!("Falling back to {}", dir);
+                for (path, file) in load_path(dir, config)? {
+                    files.entry(path).or_insert(file);
+                }
+            }
+            remap_files(files, config)
+        }
+        None if config.stdin_tar => {
+            info!("Unpacking the archive on standard input");
+            let files = archive::load_stdin()
+                .map_err(Error::io("Unable to read the archive on standard input"))?;
+            if files.is_empty() {
+                warn!("The archive on standard input has no files");
+            }
+            if config.dir_per_request {
+                remap_files(confine_to_namespaces(None, files)?, config)
+            } else {
+                remap_files(files, config)
+            }
+        }
+        #[cfg(feature = "embed")]
+        None => {
+            info!("Serving embedded files");
+            remap_files(embedded::load()?, config)
+        }
+        // Only virtual files are served
+        #[cfg(not(feature = "embed"))]
+        None => HashMap::new(),
+    };
+    for path in config.preload_paths.iter().map(|path| preload_key(path)) {
+        if !files.contains_key(&path) {
+            warn!("Unable to preload {}: no such file", path);
+        }
+    }
+    let now = SystemTime::now();
+    for (path, content) in &config.virtual_files {
+        debug!("Adding virtual file {}", path);
+        let file = SourceFile {
+            content: content.clone(),
+            modified: Some(now),
+        };
+        files.insert(path.clone(), file);
+    }
+    Ok(files)
+}
+
+/// Move the loaded files to the paths they are served at, for `--strip-prefix-on-disk` and
+/// `--add-url-prefix`.
+fn remap_files(files: HashMap<String, SourceFile>, config: &Config) -> HashMap<String, SourceFile> {
+    let strip = config.strip_prefix_on_disk.as_deref();
+    let add = config.add_url_prefix.as_deref();
+    if strip.is_none() && add.is_none() {
+        return files;
+    }
+    files
+        .into_iter()
+        .filter_map(|(path, file)| match path::remap(&path, strip, add) {
+            Some(served) => Some((served, file)),
+            None => {
+                debug!("Skipping {}, which is outside of the stripped prefix", path);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Read the files at the given request paths below `dir` into the cache, for `--no-cache`. Files
+/// which can't be read are skipped.
+fn preload_files(dir: &str, paths: &[String]) -> Result<HashMap<String, SourceFile>, Error> {
+    let dir = canonicalize(Path::new(dir))?;
+    let mut files = HashMap::new();
+    for path in paths.iter().map(|path| preload_key(path)) {
+        // As for requests, symlinks may not lead outside of `dir`
+        let file_path = match fs::canonicalize(dir.join(&path[1..])) {
+            Ok(file_path) if file_path.starts_with(&dir) && file_path.is_file() => file_path,
+            _ => continue,
+        };
+        match read(&file_path) {
+            Ok(content) => {
+                debug!("Preloaded {} bytes from {}", content.len(), path);
+                let modified = fs::metadata(&file_path).and_then(|m| m.modified()).ok();
+                files.insert(path, SourceFile { content, modified });
+            }
+            Err(e) => warn!("Unable to preload {}: {}", path, e),
+        }
+    }
+    Ok(files)
+}
+
+/// The cache key of a path to preload, where a directory stands for its `index.html`.
+fn preload_key(path: &str) -> String {
+    let mut path = path::normalize(path);
+    if path.ends_with('/') {
+        path.push_str("index.html");
+    }
+    path
+}
+
+/// Load the files from a directory, or an archive.
+fn load_path(dir: &str, config: &Config) -> Result<HashMap<String, SourceFile>, Error> {
+    let path = PathBuf::from(dir);
+    if config.archive || archive::has_archive_extension(&path) {
+        info!("Unpacking archive {}", path.display());
+        let files = archive::load(&path).map_err(Error::io(format!(
+            "Failed to read archive {}",
+            path.display()
+        )))?;
+        if config.dir_per_request {
+            confine_to_namespaces(None, files)
+        } else {
+            Ok(files)
+        }
+    } else {
+        let files = load_directory(path.clone(), config.check_permissions)?;
+        if config.dir_per_request {
+            confine_to_namespaces(Some(&path), files)
+        } else {
+            Ok(files)
+        }
+    }
+}
+
+/// The served directory, for options which read from or write to it on demand.
+fn served_dir(config: &Config, not_a_dir: &str) -> Result<PathBuf, Error> {
+    let dir = match &config.dir {
+        Some(dir) => canonicalize(Path::new(dir))?,
+        None => return Err(Error::Config(String::from(not_a_dir))),
+    };
+    if !dir.is_dir() {
+        return Err(Error::Config(String::from(not_a_dir)));
+    }
+    Ok(dir)
+}
+
+/// The absolute path of the served directory `dir`, without symlinks.
+fn canonicalize(dir: &Path) -> Result<PathBuf, Error> {
+    fs::canonicalize(dir).map_err(Error::io(format!(
+        "Unable to resolve the served directory {}",
+        dir.display()
+    )))
+}
+
+/// Keep only the files which are in a namespace, for `--dir-per-request`. Within a directory
+/// `dir`, the namespace has to be a directory of its own (rather than a symlink), and the file
+/// has to be inside of it, which a symlink may lead out of.
+fn confine_to_namespaces(
+    dir: Option<&Path>,
+    files: HashMap<String, SourceFile>,
+) -> Result<HashMap<String, SourceFile>, Error> {
+    let root = dir.map(canonicalize).transpose()?;
+    Ok(files
+        .into_iter()
+        .filter(|(path, _)| {
+            let namespace = match path::namespace(path) {
+                Some(namespace) => namespace,
+                None => {
+                    debug!("Skipping {}, which isn't in any namespace", path);
+                    return false;
+                }
+            };
+            let root = match &root {
+                Some(root) => root,
+                None => return true,
+            };
+            let namespace_dir = root.join(namespace);
+            let confined = fs::canonicalize(&namespace_dir).is_ok_and(|dir| dir == namespace_dir)
+                && fs::canonicalize(root.join(&path[1..]))
+                    .is_ok_and(|file| file.starts_with(&namespace_dir));
+            if !confined {
+                warn!("Refusing to serve {} outside of its namespace", path);
+            }
+            confined
+        })
+        .collect())
+}
+
+impl FileServer {
+    /// Load the contents of the configured path into memory. The path is either a directory, or
+    /// an archive which is unpacked into the cache. Archives are detected by their extension, or
+    /// unconditionally if `--archive` is set. Without a path, the files of the archive on standard
+    /// input are served with `--stdin-tar`, and otherwise the files embedded at build time.
+    pub fn new(config: &Config) -> Result<FileServer, Error> {
+        let file_server = FileServer::unloaded(config)?;
+        file_server.load(config)?;
+        Ok(file_server)
+    }
+
+    /// Create a server without loading any files. It answers all requests except health checks
+    /// with `503 Service Unavailable` until `load` has completed.
+    pub fn unloaded(config: &Config) -> Result<FileServer, Error> {
+        let uncached_dir = if config.no_cache {
+            let dir = served_dir(config, "--no-cache can only serve a directory")?;
+            if !config.fallback_dirs.is_empty() {
+                return Err(Error::Config(String::from(
+                    "--no-cache can only serve a single directory",
+                )));
+            }
+            info!("Serving {} without caching", dir.display());
+            Some(dir)
+        } else {
+            None
+        };
+        let writable_dir = if config.writable {
+            Some(served_dir(
+                config,
+                "--writable can only write to a directory",
+            )?)
+        } else {
+            None
+        };
+        let server_header = match config.server_header.as_deref() {
+            Some(value) if !value.is_empty() => {
+                Some(HeaderValue::from_str(value).map_err(|_| Error::Header {
+                    name: "Server",
+                    value: value.to_string(),
+                })?)
+            }
+            _ => None,
+        };
+        let root_document = match config.root_document.as_deref() {
+            Some(path) if !path.starts_with('/') => {
+                return Err(Error::Config(String::from(
+                    "The root document must start with /",
+                )))
+            }
+            path => path.map(path::normalize),
+        };
+        let maintenance_page = match &config.maintenance_page {
+            Some(path) => Bytes::from(read(path).map_err(Error::io(format!(
+                "Failed to read maintenance page {}",
+                path.display()
+            )))?),
+            None => Bytes::new(),
+        };
+        Ok(FileServer {
+            cache: RwLock::new(Arc::new(Cache::default())),
+            ready: AtomicBool::new(false),
+            delay_ready: config.ready_delay.is_some(),
+            health_path: config.health_path.clone(),
+            status_path: config.status_path.clone(),
+            started: Instant::now(),
+            cached_files: AtomicUsize::new(0),
+            cached_bytes: AtomicU64::new(0),
+            http_to_https_redirect: config.redirect_http,
+            proto_header: config.proto_header.clone(),
+            proxy_headers: config.proxy_headers,
+            canonical_host: config.canonical_host.clone(),
+            redirect_status: config.redirect_status,
+            redirect_body: !config.no_redirect_body,
+            allowed_methods: config.allowed_methods.clone(),
+            mime_types: MimeTypes::new(config.mime_types_file.as_deref(), &config.mime_overrides)?,
+            sniff: config.sniff,
+            cache_control: config.max_age.map(|max_age| match max_age {
+                0 => String::from("no-cache"),
+                max_age => format!("public, max-age={}", max_age),
+            }),
+            etag_mode: config.etag_mode,
+            compression: config.compression.clone(),
+            uncached_dir,
+            writable_dir,
+            max_upload_size: config.max_upload_size,
+            attachments: Attachments::new(&config.attachments)?,
+            preloads: Preloads::new(&config.preloads)?,
+            user_agent_rules: UserAgentRules::new(&config.ua_rules)?,
+            redirects: Redirects::new(config.redirects_file.as_deref())?,
+            basic_auth: BasicAuth::new(&config.basic_auth, config.case_insensitive),
+            age_header: config.age_header,
+            access_log: config
+                .access_log_format
+                .as_deref()
+                .map(|template| {
+                    AccessLogFormat::parse(template)
+                        .map_err(|e| Error::Config(format!("Invalid access log format: {}", e)))
+                })
+                .transpose()?,
+            log_404: !config.no_log_404,
+            cors: Cors::new(
+                &config.cors_allow_origins,
+                &config.cors_allow_methods,
+                &config.cors_allow_headers,
+                config.cors_reflect_headers,
+                config.cors_max_age,
+            )?,
+            request_timeout: config.request_timeout,
+            source_maps: config.source_maps,
+            checksum_trailer: config.checksum_trailer,
+            reject_get_body: config.reject_get_body,
+            max_uri_length: config.max_uri_length,
+            substitutions: Substitutions::new(&config.substitutions, &config.subst_pattern)?,
+            json_format: config.json_format,
+            empty_as_204: config.empty_as_204,
+            default_language: config.default_language.as_ref().map(|l| l.to_lowercase()),
+            site_url: config
+                .sitemap_url
+                .as_ref()
+                .map(|url| url.trim_end_matches('/').to_string()),
+            server_header,
+            case_insensitive: config.case_insensitive,
+            strip_trailing_dot: config.strip_trailing_dot,
+            index_fallback: !config.no_index_fallback,
+            index_files: config.index_files.clone(),
+            dir_index_files: config.dir_index_files.clone(),
+            root_document,
+            precompressed_gzip: config.precompressed_gzip,
+            precompressed_zstd: config.precompressed_zstd,
+            dir_per_request: config.dir_per_request,
+            encoding_order: config.encoding_order.clone(),
+            maintenance: AtomicBool::new(false),
+            maintenance_page,
+        })
+    }
+
+    /// Load the configured files into the cache, after which the server is ready. With a
+    /// `ready_delay`, it's only ready once `mark_ready` is called.
+    pub fn load(&self, config: &Config) -> Result<(), Error> {
+        let sources = load_files(config)?;
+        let mut zstd = if self.precompressed_zstd {
+            self.zstd_variants(&sources)
+        } else {
+            HashMap::new()
+        };
+        let decompressed = if self.precompressed_gzip {
+            self.decompress_precompressed(&sources, &mut zstd)
+        } else {
+            Vec::new()
+        };
+        let files: HashMap<String, Arc<CachedFile>> = sources
+            .into_iter()
+            .map(|(path, file)| {
+                let precompressed = Precompressed {
+                    gzip: None,
+                    zstd: zstd.remove(&path),
+                };
+                let cached = self.prepare_encoded(&path, file, precompressed);
+                (path, Arc::new(cached))
+            })
+            .chain(
+                decompressed
+                    .into_iter()
+                    .map(|(path, file)| (path, Arc::new(file))),
+            )
+            .collect();
+        info!("Loaded {} files", files.len());
+        if let Some(root_document) = &self.root_document {
+            if self.uncached_dir.is_none() && !files.contains_key(root_document) {
+                warn!("The root document {} doesn't exist", root_document);
+            }
+        }
+        self.replace_cache(files);
+        if !self.delay_ready {
+            self.mark_ready();
+        }
+        Ok(())
+    }
+
+    /// Start serving files, rather than answering `503 Service Unavailable`.
+    pub fn mark_ready(&self) {
+        self.ready.store(true, Ordering::Release);
+    }
+
+    /// Apply changes to the cached files, where `None` means that the file (or directory) at a
+    /// path was removed. Requests keep being served from the previous cache until the changes
+    /// are applied all at once.
+    fn update(&self, changes: HashMap<String, Option<SourceFile>>) {
+        let cache = self.cache();
+        let mut files = cache.files.clone();
+        for path in &cache.generated {
+            files.remove(path);
+        }
+        let count = changes.len();
+        for (path, change) in changes {
+            match change {
+                Some(file) => {
+                    let cached = self.prepare(&path, file);
+                    files.insert(path, Arc::new(cached));
+                }
+                None => {
+                    let dir = path.clone() + "/";
+                    files.retain(|key, _| key != &path && !key.starts_with(&dir));
+                }
+            }
+        }
+        info!(
+            "Updated {} changed files, serving {} files",
+            count,
+            files.len()
+        );
+        self.replace_cache(files);
+    }
+
+    fn replace_cache(&self, mut files: HashMap<String, Arc<CachedFile>>) {
+        let mut generated = Vec::new();
+        if let Some(site_url) = &self.site_url {
+            // Real files take precedence over the generated ones
+            let paths = files.keys().map(String::as_str);
+            let sitemap = sitemap::sitemap(site_url, paths, self.index_fallback);
+            let robots = sitemap::robots(site_url);
+            for (path, content) in [("/sitemap.xml", sitemap), ("/robots.txt", robots)] {
+                if !files.contains_key(path) {
+                    let file = SourceFile {
+                        content: content.into_bytes(),
+                        modified: Some(SystemTime::now()),
+                    };
+                    files.insert(path.to_string(), Arc::new(self.prepare(path, file)));
+                    generated.push(path.to_string());
+                }
+            }
+        }
+        let bytes: usize = files.values().map(|file| file.content.len()).sum();
+        self.cached_files.store(files.len(), Ordering::Relaxed);
+        self.cached_bytes.store(bytes as u64, Ordering::Relaxed);
+        let cache = Cache::new(
+            files,
+            generated,
+            self.case_insensitive,
+            self.default_language.is_some(),
+        );
+        *self.cache.write().expect("Cache lock poisoned") = Arc::new(cache);
+    }
+
+    /// The files to serve for `.gz` files among `files` which have no uncompressed counterpart,
+    /// with `--precompressed-gzip`.
+    fn decompress_precompressed(
+        &self,
+        files: &HashMap<String, SourceFile>,
+        zstd: &mut HashMap<String, Bytes>,
+    ) -> Vec<(String, CachedFile)> {
+        files
+            .iter()
+            .filter_map(|(path, gz)| {
+                let path = path.strip_suffix(".gz")?;
+                if path.ends_with('/') || files.contains_key(path) {
+                    return None;
+                }
+                let cached = self.prepare_precompressed(path, gz, zstd.remove(path))?;
+                Some((path.to_string(), cached))
+            })
+            .collect()
+    }
+
+    /// The content of `.zst` files among `files`, keyed by the path of the file they are the
+    /// compressed variant of, with `--precompressed-zstd`. As we can't decompress them, that file
+    /// has to exist as well (or its `.gz` variant, with `--precompressed-gzip`).
+    fn zstd_variants(&self, files: &HashMap<String, SourceFile>) -> HashMap<String, Bytes> {
+        files
+            .iter()
+            .filter_map(|(path, zst)| {
+                let path = path.strip_suffix(".zst")?;
+                let original = files.contains_key(path)
+                    || (self.precompressed_gzip && files.contains_key(&format!("{}.gz", path)));
+                if path.ends_with('/') || !original {
+                    return None;
+                }
+                Some((path.to_string(), Bytes::from(zst.content.clone())))
+            })
+            .collect()
+    }
+
+    /// Prepare the file at `path` from its gzip-compressed content `gz`. The compressed content is
+    /// sent as is to clients which accept it, unless substitutions change the content.
+    fn prepare_precompressed(
+        &self,
+        path: &str,
+        gz: &SourceFile,
+        zstd: Option<Bytes>,
+    ) -> Option<CachedFile> {
+        let content = match compression::gunzip(&gz.content) {
+            Ok(content) => content,
+            Err(e) => {
+                warn!("Unable to decompress {}.gz: {}", path, e);
+                return None;
+            }
+        };
+        debug!(
+            "Decompressed {}.gz from {} to {} bytes",
+            path,
+            gz.content.len(),
+            content.len()
+        );
+        let file = SourceFile {
+            content,
+            modified: gz.modified,
+        };
+        let precompressed = Precompressed {
+            gzip: Some(Bytes::from(gz.content.clone())),
+            zstd,
+        };
+        Some(self.prepare_encoded(path, file, precompressed))
+    }
+
+    /// Determine everything needed to serve `file` at `path`.
+    fn prepare(&self, path: &str, file: SourceFile) -> CachedFile {
+        self.prepare_encoded(path, file, Precompressed::default())
+    }
+
+    /// Prepare `file` for serving, with the `precompressed` variants there are of it.
+    fn prepare_encoded(
+        &self,
+        path: &str,
+        mut file: SourceFile,
+        precompressed: Precompressed,
+    ) -> CachedFile {
+        let (content_type, sniffed) = match self.mime_types.lookup(path) {
+            Some(content_type) => (content_type, false),
+            None if self.sniff => (mime::sniff(&file.content), true),
+            None => (mime::DEFAULT_CONTENT_TYPE, false),
+        };
+        // Substitute and reformat before anything else, so the ETag, checksum and compressed
+        // content (and with them the `Content-Length`) are those of the content as served
+        file.content = self.substitutions.apply(content_type, file.content);
+        let mut reformatted = false;
+        if let Some(format) = self.json_format.filter(|_| json::is_json(content_type)) {
+            match json::reformat(&file.content, format) {
+                Ok(content) => {
+                    file.content = content;
+                    reformatted = true;
+                }
+                Err(e) => warn!("Serving {} as it is, as it isn't valid JSON: {}", path, e),
+            }
+        }
+        let etag = etag(&file, self.etag_mode);
+        let sha256 = if self.checksum_trailer {
+            Some(checksum::sha256_hex(&file.content))
+        } else {
+            None
+        };
+        // Precompressed variants are of the content before it was changed
+        let altered = reformatted || self.substitutions.applies_to(content_type);
+        let gzip = match precompressed.gzip {
+            Some(gzip) if !altered => Some(gzip),
+            _ => {
+                let gzip = self.compression.gzip(content_type, &file.content);
+                if let Some(gzip) = &gzip {
+                    debug!(
+                        "Compressed {} from {} to {} bytes",
+                        path,
+                        file.content.len(),
+                        gzip.len()
+                    );
+                }
+                gzip
+            }
+        };
+        CachedFile {
+            content: Bytes::from(file.content),
+            content_type: content_type.to_string(),
+            sniffed,
+            etag,
+            gzip,
+            zstd: precompressed.zstd.filter(|_| !altered),
+            content_disposition: self.attachments.content_disposition(path),
+            link: self.preloads.link(path),
+            language: self
+                .default_language
+                .as_ref()
+                .and_then(|_| language::variant_of(path))
+                .map(|(_, language)| language),
+            sha256,
+        }
+    }
+
+    /// The number of cached files and their total size in bytes, uncompressed.
+    pub fn cache_stats(&self) -> (usize, u64) {
+        (
+            self.cached_files.load(Ordering::Relaxed),
+            self.cached_bytes.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Switch maintenance mode on or off. While it is on, everything except health checks and
+    /// the status endpoint is answered with the maintenance page. Returns whether it is now on.
+    pub fn toggle_maintenance(&self) -> bool {
+        !self.maintenance.fetch_xor(true, Ordering::Relaxed)
+    }
+
+    fn cache(&self) -> Arc<Cache> {
+        Arc::clone(&self.cache.read().expect("Cache lock poisoned"))
+    }
+
+    pub async fn handle(&self, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+        let started = Instant::now();
+        let method = req.method().clone();
+        let uri = req.uri().clone();
+        let version = req.version();
+        let remote = req.extensions().get::<RemoteAddr>().map(|remote| remote.0);
+        let request_headers = match &self.access_log {
+            Some(access_log) if access_log.uses_request_headers() => req.headers().clone(),
+            _ => HeaderMap::new(),
+        };
+        let mut response = match self.request_timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, self.respond(req)).await {
+                Ok(response) => response?,
+                Err(_) => {
+                    warn!("{} {} timed out after {:?}", method, uri, timeout);
+                    Response::builder()
+                        .status(StatusCode::GATEWAY_TIMEOUT)
+                        .body(Body::empty())
+                        .expect("Unable to create `http::Response`")
+                }
+            },
+            None => self.respond(req).await?,
+        };
+        if let Some(server) = &self.server_header {
+            response.headers_mut().insert(SERVER, server.clone());
+        }
+        let status = response.status().as_u16();
+        let size = response.body().size_hint().exact();
+        if method == Method::HEAD {
+            // Hyper leaves out the body of responses to HEAD requests over HTTP/1, but not over
+            // HTTP/2, where clients then reset the stream. The response is the one to `GET`, with
+            // the same encoding, so `Content-Length` is that of the body `GET` would get, e.g. the
+            // compressed size. Compressed generated responses have no known length, and `204` and
+            // `304` responses have none at all.
+            let bodyless = matches!(
+                response.status(),
+                StatusCode::NO_CONTENT | StatusCode::NOT_MODIFIED
+            );
+            match size {
+                Some(size) if !bodyless && !response.headers().contains_key(CONTENT_LENGTH) => {
+                    response
+                        .headers_mut()
+                        .insert(CONTENT_LENGTH, HeaderValue::from(size));
+                }
+                _ => {}
+            }
+            *response.body_mut() = Body::empty();
+        }
+        let size = size.unwrap_or(0);
+        if !self.log_404 && response.status() == StatusCode::NOT_FOUND {
+            return Ok(response);
+        }
+        if let Some(access_log) = &self.access_log {
+            let entry = Entry {
+                remote,
+                method: &method,
+                uri: &uri,
+                version,
+                request_headers: &request_headers,
+                status: response.status(),
+                response_headers: response.headers(),
+                bytes: size,
+                duration: started.elapsed(),
+            };
+            info!("{}", access_log.render(&entry));
+            return Ok(response);
+        }
+        match response.extensions().get::<CacheStatus>() {
+            Some(cache) => info!("{} {} {} {} bytes ({})", method, uri, status, size, cache),
+            None => info!("{} {} {} {} bytes", method, uri, status, size),
+        }
+        Ok(response)
+    }
+
+    async fn respond(&self, mut req: Request<Body>) -> Result<Response<Body>, Infallible> {
+        let uri = req.uri().path_and_query().map_or("", |pq| pq.as_str());
+        if uri.len() > self.max_uri_length {
+            debug!(
+                "Rejecting {} {}... with a URI of {} bytes",
+                req.method(),
+                // URIs consist of ASCII characters only
+                &uri[..uri.len().min(MAX_LOGGED_URI)],
+                uri.len()
+            );
+            return Ok(Response::builder()
+                .status(StatusCode::URI_TOO_LONG)
+                .body(Body::empty())
+                .expect("Unable to create `http::Response`"));
+        }
+        // Some clients send a body with `GET` requests, which means nothing to us
+        let safe = matches!(*req.method(), Method::GET | Method::HEAD);
+        if safe && !req.body().is_end_stream() {
+            if self.reject_get_body {
+                warn!(
+                    "Rejecting {} {} with a request body",
+                    req.method(),
+                    req.uri().path()
+                );
+                return Ok(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::empty())
+                    .expect("Unable to create `http::Response`"));
+            }
+            if !drain(req.body_mut()).await {
+                debug!(
+                    "Not reading the rest of the body of {} {}",
+                    req.method(),
+                    req.uri().path()
+                );
+            }
+        }
+        let origin = req.headers().get(ORIGIN).cloned();
+        let mut response = match self.resolve(&req).await {
+            Resource::File(found) => self.serve_file(&req, &found),
+            Resource::Generated(response) => self.compress_generated(&req, response),
+            Resource::NotFound => not_found(),
+            Resource::Modification => self.modify(req).await,
+            Resource::Preflight(response) => return Ok(response),
+        };
+        self.cors.apply(origin.as_ref(), &mut response);
+        Ok(response)
+    }
+
+    /// Determine what `req` refers to: one of the served files, or one of the responses we
+    /// generate ourselves.
+    async fn resolve(&self, req: &Request<Body>) -> Resource {
+        let method = req.method();
+        let uri = req.uri();
+
+        // Rather than guess what a client means by a header we read which isn't visible ASCII,
+        // reject the request
+        if let Some(name) = self.unreadable_header(req) {
+            warn!(
+                "Rejecting {} {} with an invalid {} header",
+                method,
+                uri.path(),
+                name
+            );
+            return Resource::Generated(
+                Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::empty())
+                    .expect("Unable to create `http::Response`"),
+            );
+        }
+
+        // `OPTIONS *` asks about the server as a whole rather than any particular resource
+        if method == Method::OPTIONS && uri.path() == "*" {
+            return Resource::Generated(
+                Response::builder()
+                    .status(StatusCode::NO_CONTENT)
+                    .header(ALLOW, self.allow_header())
+                    .body(Body::empty())
+                    .expect("Unable to create `http::Response`"),
+            );
+        }
+
+        // Browsers send preflight requests without credentials, so they're answered before those
+        // are checked
+        if let Some(preflight) = self
+            .cors
+            .preflight(method, req.headers(), || self.allow_header())
+        {
+            return Resource::Preflight(preflight);
+        }
+
+        if self.health_path.as_deref() == Some(uri.path()) {
+            return Resource::Generated(
+                Response::builder()
+                    .status(StatusCode::OK)
+                    .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+                    .body(Body::from("ok"))
+                    .expect("Unable to create `http::Response`"),
+            );
+        }
+
+        if self.status_path.as_deref() == Some(uri.path()) {
+            return Resource::Generated(self.status());
+        }
+
+        // Paths which can't be decoded are rejected later on, whatever the credentials
+        let authorized = match self.request_path(uri.path()) {
+            Some(path) => self.basic_auth.is_authorized(&path, req.headers()),
+            None => true,
+        };
+        if !authorized {
+            return Resource::Generated(unauthorized());
+        }
+
+        if self.maintenance.load(Ordering::Relaxed) {
+            return Resource::Generated(
+                Response::builder()
+                    .status(StatusCode::SERVICE_UNAVAILABLE)
+                    .header(CONTENT_TYPE, "text/html; charset=utf-8")
+                    .header(CACHE_CONTROL, "no-store")
+                    .body(Body::from(self.maintenance_page.clone()))
+                    .expect("Unable to create `http::Response`"),
+            );
+        }
+
+        // Don't answer 404 for files which simply haven't been loaded yet
+        if !self.ready.load(Ordering::Acquire) {
+            return Resource::Generated(
+                Response::builder()
+                    .status(StatusCode::SERVICE_UNAVAILABLE)
+                    .header(RETRY_AFTER, "5")
+                    .body(Body::empty())
+                    .expect("Unable to create `http::Response`"),
+            );
+        }
+
+        if let Some(redirect) = self.build_canonical_redirect(req) {
+            return Resource::Generated(redirect);
+        }
+
+        let response = match *method {
+            Method::GET | Method::HEAD => match self.redirects.find(uri.path()) {
+                Some((status, location)) => Response::builder()
+                    .status(status)
+                    .header(LOCATION, location)
+                    .body(Body::empty())
+                    .expect("Unable to create `http::Response`"),
+                None => return self.find_file(req).await,
+            },
+            Method::PUT | Method::DELETE if self.writable_dir.is_some() => {
+                return Resource::Modification
+            }
+            // Otherwise, the request body is never read. For requests with `Expect: 100-continue`, Hyper
+            // only sends `100 Continue` once the body is read, so these clients get the final
+            // response straight away and don't send the body at all.
+            _ => match self.allowed_methods.get(method) {
+                Some(status) => Response::builder()
+                    .status(status)
+                    .body(Body::empty())
+                    .expect("Unable to create `http::Response`"),
+                None => Response::builder()
+                    .status(StatusCode::METHOD_NOT_ALLOWED)
+                    .header(ALLOW, self.allow_header())
+                    .body(Body::empty())
+                    .expect("Unable to create `http::Response`"),
+            },
+        };
+        Resource::Generated(response)
+    }
+
+    /// Summarize the state of the server as JSON. Until the files are loaded, the status is
+    /// `loading` and the response is `503 Service Unavailable`.
+    fn status(&self) -> Response<Body> {
+        let (status, code) = if self.ready.load(Ordering::Acquire) {
+            ("ok", StatusCode::OK)
+        } else {
+            ("loading", StatusCode::SERVICE_UNAVAILABLE)
+        };
+        let body = format!(
+            "{{\"status\":\"{}\",\"files\":{},\"bytes\":{},\"uptime_seconds\":{}}}",
+            status,
+            self.cached_files.load(Ordering::Relaxed),
+            self.cached_bytes.load(Ordering::Relaxed),
+            self.started.elapsed().as_secs()
+        );
+        Response::builder()
+            .status(code)
+            .header(CONTENT_TYPE, "application/json")
+            .header(CACHE_CONTROL, "no-store")
+            .body(Body::from(body))
+            .expect("Unable to create `http::Response`")
+    }
+
+    /// The value of the `Allow` header, listing all methods we respond to.
+    fn allow_header(&self) -> String {
+        let mut methods = vec!["GET", "HEAD"];
+        if self.writable_dir.is_some() {
+            methods.extend(["PUT", "DELETE"]);
+        }
+        let mut extra: Vec<&str> = self.allowed_methods.keys().map(Method::as_str).collect();
+        extra.sort_unstable();
+        methods.extend(extra);
+        methods.join(", ")
+    }
+
+    /// Find the file served at `path`, from the cache or (with `--no-cache`) from disk.
+    async fn lookup(&self, path: &str) -> io::Result<Option<(Arc<CachedFile>, CacheStatus)>> {
+        if let Some(file) = self.cache().get(path) {
+            return Ok(Some((Arc::clone(file), CacheStatus::Hit)));
+        }
+        let dir = match &self.uncached_dir {
+            Some(dir) => dir,
+            None => return Ok(None),
+        };
+        // With `--dir-per-request`, each namespace is read like a served directory of its own
+        let (dir, file_path) = if self.dir_per_request {
+            match path::namespace(path) {
+                Some(namespace) => (dir.join(namespace), &path[namespace.len() + 1..]),
+                None => return Ok(None),
+            }
+        } else {
+            (dir.clone(), path)
+        };
+        let dir = dir.as_path();
+        let zstd = if self.precompressed_zstd && !path.ends_with('/') {
+            read_uncached(dir, &format!("{}.zst", file_path))
+                .await?
+                .map(|zst| Bytes::from(zst.content))
+        } else {
+            None
+        };
+        if let Some(file) = read_uncached(dir, file_path).await? {
+            let precompressed = Precompressed { gzip: None, zstd };
+            let cached = self.prepare_encoded(path, file, precompressed);
+            return Ok(Some((Arc::new(cached), CacheStatus::Miss)));
+        }
+        if self.precompressed_gzip && !path.ends_with('/') {
+            if let Some(gz) = read_uncached(dir, &format!("{}.gz", file_path)).await? {
+                let cached = self.prepare_precompressed(path, &gz, zstd);
+                return Ok(cached.map(|cached| (Arc::new(cached), CacheStatus::Miss)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The path of the file to look up for a request path, see `path::request_path`.
+    fn request_path(&self, path: &str) -> Option<String> {
+        path::request_path(path, self.strip_trailing_dot)
+    }
+
+    /// The file names to try for a request to the directory `dir`.
+    fn index_files(&self, dir: &str) -> &[String] {
+        self.dir_index_files
+            .iter()
+            .filter(|(prefix, _)| dir.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(&self.index_files, |(_, names)| names)
+    }
+
+    /// Look up the file at `path`, falling back to the index files for directories. Returns the
+    /// path of the file, or the one to look for language variants of if there is none.
+    async fn lookup_or_index(
+        &self,
+        path: String,
+    ) -> (String, io::Result<Option<(Arc<CachedFile>, CacheStatus)>>) {
+        let found = self.lookup(&path).await;
+        // apply a simple fallback rule to fetch the index file, such as index.html
+        if !self.index_fallback || !path.ends_with('/') || !matches!(found, Ok(None)) {
+            return (path, found);
+        }
+        let index_files = self.index_files(&path);
+        for index_file in index_files {
+            let candidate = format!("{}{}", path, index_file);
+            let found = self.lookup(&candidate).await;
+            if !matches!(found, Ok(None)) {
+                return (candidate, found);
+            }
+        }
+        // Without any, language variants are looked for as those of the first one
+        let first = index_files.first().map_or("", String::as_str);
+        (format!("{}{}", path, first), Ok(None))
+    }
+
+    /// Find the file for the path of `req`, falling back to the index files for directories.
+    async fn find_file(&self, req: &Request<Body>) -> Resource {
+        // Rather than resolve `..`, which may lead into another namespace, reject it
+        if self.dir_per_request && path::has_parent_segment(req.uri().path()) {
+            warn!(
+                "Rejecting {} {} which leads out of its namespace",
+                req.method(),
+                req.uri().path()
+            );
+            return Resource::Generated(
+                Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::empty())
+                    .expect("Unable to create `http::Response`"),
+            );
+        }
+        let mut path = match self.request_path(req.uri().path()) {
+            Some(path) => path,
+            None => {
+                return Resource::Generated(
+                    Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .body(Body::empty())
+                        .expect("Unable to create `http::Response`"),
+                )
+            }
+        };
+        if path == "/" {
+            if let Some(root_document) = &self.root_document {
+                path = root_document.clone();
+            }
+        }
+        let user_agent_prefix = req
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|user_agent| self.user_agent_rules.prefix(user_agent));
+        let (path, mut found) = match user_agent_prefix {
+            Some(prefix) => {
+                // Without a file under the prefix, the one for all other clients is served
+                match self.lookup_or_index(format!("{}{}", prefix, path)).await {
+                    (_, Ok(None)) => self.lookup_or_index(path).await,
+                    found => found,
+                }
+            }
+            None => self.lookup_or_index(path).await,
+        };
+        let mut negotiated = false;
+        let mut variant_path = None;
+        if let Some(default_language) = &self.default_language {
+            let cache = self.cache();
+            if let Some(variants) = cache.variants.get(&path) {
+                negotiated = true;
+                // The file itself is preferred over the default language, if there is one
+                let chosen = language::choose(req.headers(), variants).or_else(|| match found {
+                    Ok(None) => variants
+                        .iter()
+                        .find(|(language, _)| language == default_language)
+                        .map(|(_, path)| path.as_str()),
+                    _ => None,
+                });
+                let chosen = chosen.and_then(|variant| Some((variant, cache.get(variant)?)));
+                if let Some((variant, file)) = chosen {
+                    found = Ok(Some((Arc::clone(file), CacheStatus::Hit)));
+                    variant_path = Some(variant.to_string());
+                }
+            }
+        }
+        // Credentials were checked for the request path, but the file may be at another one: the
+        // root document, an index file, or a variant for the user agent or language
+        let served_path = variant_path.as_deref().unwrap_or(&path);
+        if matches!(found, Ok(Some(_)))
+            && !self.basic_auth.is_authorized(served_path, req.headers())
+        {
+            return Resource::Generated(unauthorized());
+        }
+        match found {
+            Ok(Some((file, cache_status))) => {
+                let source_map = self.source_map(&path, &file);
+                Resource::File(FoundFile {
+                    file,
+                    cache_status,
+                    negotiated,
+                    source_map,
+                })
+            }
+            Ok(None) => Resource::NotFound,
+            Err(e) => {
+                error!("Failed to read {}: {}", path, e);
+                Resource::Generated(
+                    Response::builder()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .body(Body::empty())
+                        .expect("Unable to create `http::Response`"),
+                )
+            }
+        }
+    }
+
+    /// Compress a generated response on the fly if it is worth it, with the same settings as
+    /// files. Unlike files, generated responses aren't compressed up front.
+    fn compress_generated(
+        &self,
+        req: &Request<Body>,
+        mut response: Response<Body>,
+    ) -> Response<Body> {
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+        let size = response.body().size_hint().exact();
+        if response.headers().contains_key(CONTENT_ENCODING)
+            || !self.compression.should_compress(content_type, size)
+        {
+            return response;
+        }
+        response
+            .headers_mut()
+            .append(VARY, HeaderValue::from_static("Accept-Encoding"));
+        if compression::accepts_gzip(req.headers()) {
+            let headers = response.headers_mut();
+            headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+            headers.remove(CONTENT_LENGTH);
+            let body = std::mem::take(response.body_mut());
+            *response.body_mut() = self.compression.gzip_stream(body);
+        }
+        response
+    }
+
+    /// The `SourceMap` header for a script or stylesheet at `path`, if its source map is cached
+    /// next to it.
+    fn source_map(&self, path: &str, file: &CachedFile) -> Option<String> {
+        let is_asset = file.content_type.starts_with("text/javascript")
+            || file.content_type.starts_with("text/css");
+        if !self.source_maps || !is_asset {
+            return None;
+        }
+        let map = format!("{}.map", path);
+        self.cache().get(&map).map(|_| path::percent_encode(&map))
+    }
+
+    /// Respond with a file, taking care of everything which only applies to files, such as
+    /// conditional requests and compression. Responses to HEAD requests get the same headers,
+    /// and `handle` leaves out their body afterwards.
+    fn serve_file(&self, req: &Request<Body>, found: &FoundFile) -> Response<Body> {
+        let file = &found.file;
+        let mut builder = Response::builder()
+            .extension(found.cache_status)
+            .header(CONTENT_TYPE, &file.content_type);
+        if file.sniffed {
+            // The type was already guessed, don't let the client guess differently
+            builder = builder.header(X_CONTENT_TYPE_OPTIONS, "nosniff");
+        }
+        if let Some(content_disposition) = &file.content_disposition {
+            builder = builder.header(CONTENT_DISPOSITION, content_disposition);
+        }
+        if let Some(link) = &file.link {
+            builder = builder.header(LINK, link);
+        }
+        if let Some(cache_control) = &self.cache_control {
+            builder = builder.header(CACHE_CONTROL, cache_control);
+        }
+        if self.age_header {
+            // Files are always served fresh from memory. Hyper adds the `Date` header.
+            builder = builder.header(AGE, "0");
+        }
+        if let Some(language) = &file.language {
+            builder = builder.header(CONTENT_LANGUAGE, language);
+        }
+        if let Some(source_map) = &found.source_map {
+            builder = builder.header("SourceMap", source_map);
+        }
+        let mut vary = Vec::new();
+        if found.negotiated {
+            vary.push("Accept-Language");
+        }
+        if file.gzip.is_some() || file.zstd.is_some() {
+            vary.push("Accept-Encoding");
+        }
+        if !self.user_agent_rules.is_empty() {
+            vary.push("User-Agent");
+        }
+        if !vary.is_empty() {
+            builder = builder.header(VARY, vary.join(", "));
+        }
+        builder = builder.header(ACCEPT_RANGES, "bytes");
+        let len = file.content.len() as u64;
+        let ranges = match req.headers().get(RANGE).and_then(|v| v.to_str().ok()) {
+            Some(range) if if_range_matches(req, file.etag.as_deref()) => range::parse(range, len),
+            _ => Ranges::Full,
+        };
+        // Ranges refer to the uncompressed content
+        let encoded = match ranges {
+            Ranges::Full => self.encoding_order.iter().find_map(|&encoding| {
+                let content = match encoding {
+                    Encoding::Zstd => file.zstd.as_ref(),
+                    Encoding::Gzip => file.gzip.as_ref(),
+                }?;
+                compression::accepts(req.headers(), encoding).then_some((encoding, content))
+            }),
+            _ => None,
+        };
+        if let Some(etag) = &file.etag {
+            // Each encoding is a representation of its own, which caches must not mix up
+            let etag = match encoded {
+                Some((encoding, _)) => encoded_etag(etag, encoding),
+                None => etag.clone(),
+            };
+            let not_modified = if_none_match(req.headers(), &etag);
+            builder = builder.header(ETAG, etag);
+            if not_modified {
+                return builder
+                    .status(StatusCode::NOT_MODIFIED)
+                    .body(Body::empty())
+                    .expect("Unable to create `http::Response`");
+            }
+        }
+        match ranges {
+            Ranges::Full => {}
+            Ranges::Unsatisfiable => {
+                return builder
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(CONTENT_RANGE, format!("bytes */{}", len))
+                    .body(Body::empty())
+                    .expect("Unable to create `http::Response`");
+            }
+            Ranges::Satisfiable(ranges) => {
+                let body = match ranges.as_slice() {
+                    [(start, end)] => {
+                        builder =
+                            builder.header(CONTENT_RANGE, range::content_range(*start, *end, len));
+                        file.content.slice(*start as usize..=*end as usize)
+                    }
+                    ranges => {
+                        let boundary = range::boundary(file.etag.as_deref());
+                        if let Some(headers) = builder.headers_mut() {
+                            let content_type =
+                                format!("multipart/byteranges; boundary={}", boundary);
+                            headers.insert(
+                                CONTENT_TYPE,
+                                HeaderValue::from_str(&content_type)
+                                    .expect("Boundaries are valid header values"),
+                            );
+                        }
+                        range::multipart(&file.content, &file.content_type, ranges, &boundary)
+                    }
+                };
+                return builder
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .body(Body::from(body))
+                    .expect("Unable to create `http::Response`");
+            }
+        }
+        if self.empty_as_204 && file.content.is_empty() {
+            return builder
+                .status(StatusCode::NO_CONTENT)
+                .body(Body::empty())
+                .expect("Unable to create `http::Response`");
+        }
+        let content = match encoded {
+            Some((encoding, content)) => {
+                builder = builder.header(CONTENT_ENCODING, encoding.name());
+                content.clone()
+            }
+            None => file.content.clone(),
+        };
+        let body = match &file.sha256 {
+            // Without a `Content-Length`, as Hyper would end the stream before the trailers once
+            // that many bytes are sent. Hyper also drops the `Trailer` header over HTTP/2.
+            Some(sha256) if checksum::accepts_trailers(req) => {
+                checksum::with_trailer(content, sha256)
+            }
+            _ => Body::from(content),
+        };
+        builder
+            .status(StatusCode::OK)
+            .body(body)
+            .expect("Unable to create `http::Response`")
+    }
+
+    /// The name of the first header we interpret whose value isn't visible ASCII, if any.
+    fn unreadable_header(&self, req: &Request<Body>) -> Option<String> {
+        let names = [HOST, ACCEPT_ENCODING, ACCEPT_LANGUAGE, IF_NONE_MATCH, TE];
+        names
+            .iter()
+            .map(|name| name.as_str())
+            .chain(std::iter::once(self.proto_header.0.as_str()))
+            .find(|name| {
+                req.headers()
+                    .get_all(*name)
+                    .iter()
+                    .any(|value| value.to_str().is_err())
+            })
+            .map(String::from)
+    }
+
+    /// Whether the proxy in front of us received `req` over plain http, according to the trusted
+    /// proxy headers or else the configured header, or `None` if the headers are missing. The
+    /// standard `Forwarded` header is parsed for the `proto` of the first proxy.
+    fn is_insecure(&self, req: &Request<Body>) -> Option<bool> {
+        if let Some(proto) = self.proxy_headers.proto(req.headers()) {
+            return Some(proto.trim().eq_ignore_ascii_case("http"));
+        }
+        let (name, insecure_value) = &self.proto_header;
+        let value = if name.eq_ignore_ascii_case("forwarded") {
+            forwarded::forwarded(req.headers(), "proto")?
+        } else {
+            req.headers().get(name.as_str())?.to_str().ok()?.to_string()
+        };
+        Some(value.trim().eq_ignore_ascii_case(insecure_value))
+    }
+
+    /// The lowercased host of a request without the port, from the trusted proxy headers or else
+    /// the request itself.
+    fn request_host(&self, req: &Request<Body>) -> Option<String> {
+        match self.proxy_headers.host(req.headers()) {
+            Some(host) => Some(vhost::normalize_host(&host)),
+            None => vhost::request_host(req),
+        }
+    }
+
+    /// Redirect to the canonical scheme and host, in a single redirect if both are wrong. The
+    /// http -> https redirect is based on the presence of the `x-forwarded-proto` header in the
+    /// request. This is as described in the following fly.io blog post:
+    /// https://fly.io/blog/always-be-connecting-with-https/
+    fn build_canonical_redirect(&self, req: &Request<Body>) -> Option<Response<Body>> {
+        let uri = req.uri();
+        if !self.http_to_https_redirect && self.canonical_host.is_none() {
+            return None;
+        }
+
+        // With `301`, clients would repeat other requests as `GET`
+        let preserves_method = self.redirect_status == StatusCode::PERMANENT_REDIRECT;
+        if !preserves_method && !matches!(*req.method(), Method::GET | Method::HEAD) {
+            return None;
+        }
+
+        let insecure = self.is_insecure(req);
+        let to_https = self.http_to_https_redirect && insecure == Some(true);
+        let to_canonical_host = self.canonical_host.as_deref().is_some_and(|canonical| {
+            let canonical = canonical.split(':').next().unwrap_or(canonical);
+            self.request_host(req)
+                .is_some_and(|host| !host.eq_ignore_ascii_case(canonical))
+        });
+        if !to_https && !to_canonical_host {
+            return None;
+        }
+        let scheme = match insecure {
+            _ if to_https => "https",
+            Some(true) => "http",
+            Some(false) => "https",
+            None => uri.scheme_str().unwrap_or("http"),
+        };
+
+        // Request targets such as `*` (from `OPTIONS *`) have no path, redirect those to the root.
+        // The query string is part of the path and query, so it is preserved in the redirect.
+        let path_and_query = uri.path_and_query().map_or("/", |pq| pq.as_str());
+
+        // Determining the current host can go via three methods:
+        // - behind a trusted proxy: via the host it forwarded
+        // - in http1.1 and earlier: via the "host" header set on the request
+        // - in http2 onwards: via the "authority" component of the Uri
+        let forwarded_host = self.proxy_headers.host(req.headers());
+        let host = match (&self.canonical_host, &forwarded_host) {
+            (Some(canonical_host), _) => canonical_host.as_str(),
+            (None, Some(forwarded_host)) => forwarded_host.as_str(),
+            (None, None) => match req.headers().get("host") {
+                // Hosts which are not ASCII can't be redirected to
+                Some(host) => host.to_str().ok()?,
+                None => uri.authority()?.as_str(),
+            },
+        };
+
+        let location = Builder::new()
+            .scheme(scheme)
+            .path_and_query(path_and_query)
+            .authority(host)
+            .build()
+            .ok()?;
+
+        info!("Redirecting to {} for {}", location, path_and_query);
+
+        let location = location.to_string();
+        let builder = Response::builder()
+            .status(self.redirect_status)
+            .header(LOCATION, &location);
+        let response = if self.redirect_body {
+            builder
+                .header(CONTENT_TYPE, "text/html; charset=utf-8")
+                .body(Body::from(redirect_page(self.redirect_status, &location)))
+        } else {
+            builder.body(Body::empty())
+        };
+        Some(response.expect("Unable to create https redirect"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_keys_join_nested_directories_with_forward_slashes() {
+        let dir = Path::new("/srv/site");
+        let file: PathBuf = ["/srv/site", "docs", "api", "index.html"].iter().collect();
+        assert_eq!(cache_key(dir, &file).unwrap(), "/docs/api/index.html");
+        assert_eq!(
+            cache_key(dir, &dir.join("index.html")).unwrap(),
+            "/index.html"
+        );
+        assert!(cache_key(dir, Path::new("/srv/other/index.html")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn has_no_cache_key_for_paths_which_arent_unicode() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = Path::new("/srv/site");
+        let file = dir.join(OsStr::from_bytes(b"caf\xe9.html"));
+        assert!(cache_key(dir, &file).is_err());
+    }
+}
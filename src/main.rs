@@ -1,71 +1,371 @@
-use clap::{crate_version, App, Arg};
-use hyper::header::LOCATION;
-use hyper::http::uri::Builder;
-use hyper::service::{make_service_fn, service_fn};
-use hyper::{Body, Method, Request, Response, Server, StatusCode};
-use log::{debug, error, info, warn};
-use simplelog::{ColorChoice, ConfigBuilder, LevelFilter, TermLogger, TerminalMode};
-use std::collections::{HashMap, VecDeque};
-use std::convert::Infallible;
-use std::fs;
-use std::fs::read;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use clap::{crate_version, App, Arg, ArgMatches};
+use hyper::{Method, StatusCode};
+use log::{error, info};
+use simplelog::{ColorChoice, ConfigBuilder, LevelFilter, TermLogger, TerminalMode, WriteLogger};
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::io::{self, IsTerminal};
+use std::net::{IpAddr, Ipv4Addr};
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::time::Duration;
 
-#[tokio::main]
-async fn main() {
+use httpserve::auth;
+use httpserve::compression::{Compression, Encoding};
+use httpserve::config_file;
+use httpserve::cors;
+use httpserve::error::Error;
+use httpserve::forwarded::ProxyHeaders;
+use httpserve::json::JsonFormat;
+use httpserve::mime;
+use httpserve::panics;
+use httpserve::path;
+use httpserve::preload;
+use httpserve::server::ServerBuilder;
+use httpserve::subst;
+use httpserve::telemetry;
+use httpserve::user_agent;
+use httpserve::vars;
+use httpserve::{Config, EtagMode};
+
+fn main() -> ExitCode {
     configure_logging();
-    let config = parse_config();
-    info!("Starting httpserve on {}:{}", config.address, config.port);
-    let addr = SocketAddr::from((config.address, config.port));
-
-    let file_server = Arc::new(FileServer::new(
-        PathBuf::from(config.dir),
-        config.redirect_http,
-    ));
-
-    let make_svc = make_service_fn(move |_conn| {
-        let file_server = Arc::clone(&file_server);
-        async move {
-            Ok::<_, Infallible>(service_fn(move |req| {
-                let file_server = Arc::clone(&file_server);
-                async move { file_server.handle(req).await }
-            }))
+    let config = match parse_config() {
+        Ok(Command::Serve(config)) => config,
+        Ok(Command::PrintConfig(config)) => {
+            print!("{}", config.to_toml());
+            return ExitCode::SUCCESS;
         }
-    });
+        Err(e) => return fail(e, 1),
+    };
+    // Conflicting options have a status of their own, unlike the other reasons not to start
+    if let Err(e) = config.check_conflicts() {
+        return fail(e, 2);
+    }
+    match serve(config) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => fail(e, 1),
+    }
+}
+
+/// Report `error` the way returning it from `main` would, with the exit status `status`.
+fn fail(error: Error, status: u8) -> ExitCode {
+    eprintln!("Error: {}", error);
+    ExitCode::from(status)
+}
+
+fn serve(config: Config) -> Result<(), Error> {
+    panics::install_hook(config.abort_on_panic);
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(config.threads)
+        .enable_all()
+        .build()
+        .map_err(Error::io("Unable to build Tokio runtime"))?;
+    runtime.block_on(
+        ServerBuilder::new(config)
+            .with_graceful_shutdown(shutdown_signal())
+            .serve(),
+    )
+}
+
+/// Wait for a request to shut down: Ctrl+C, or on Unix also `SIGTERM`, which is what service
+/// managers send.
+#[cfg(unix)]
+async fn shutdown_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
 
-    let server = Server::bind(&addr)
-        .serve(make_svc)
-        .with_graceful_shutdown(async {
-            tokio::signal::ctrl_c()
-                .await
-                .expect("failed to install CTRL+C signal handler")
-        });
+    let terminate = async {
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                error!(
+                    "Unable to handle SIGTERM, which stops the server without finishing the \
+                     requests in progress: {}",
+                    e
+                );
+                std::future::pending().await
+            }
+        }
+    };
+    tokio::select! {
+        _ = ctrl_c() => {}
+        _ = terminate => {}
+    }
+    info!("Shutting down");
+}
 
-    if let Err(e) = server.await {
-        error!("server error: {}", e);
+#[cfg(not(unix))]
+async fn shutdown_signal() {
+    ctrl_c().await;
+    info!("Shutting down");
+}
+
+/// Wait for Ctrl+C. Should it be impossible to handle, the server keeps running (until Ctrl+C
+/// stops it the usual way) rather than shut down right away.
+async fn ctrl_c() {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        error!(
+            "Unable to handle Ctrl+C, which stops the server without finishing the requests in \
+             progress: {}",
+            e
+        );
+        std::future::pending().await
     }
 }
 
-struct Config {
-    dir: String,
-    address: IpAddr,
-    port: u16,
-    redirect_http: bool,
+/// Parse the value of `--option`, such as a number.
+fn parse<T: FromStr>(option: &str, value: &str) -> Result<T, Error>
+where
+    T::Err: Display,
+{
+    value
+        .parse()
+        .map_err(|e| Error::Config(format!("Invalid --{} {}: {}", option, value, e)))
 }
 
-fn parse_config() -> Config {
-    let matches = App::new("httpserve")
+/// Fail with `message` unless `condition` holds, like `assert!` for the options.
+fn ensure(condition: bool, message: impl FnOnce() -> String) -> Result<(), Error> {
+    if condition {
+        Ok(())
+    } else {
+        Err(Error::Config(message()))
+    }
+}
+
+/// Parse the value of `--option`, which must be at least 1.
+fn parse_count(option: &str, value: &str) -> Result<usize, Error> {
+    let n = parse(option, value)?;
+    ensure(n > 0, || format!("--{} must be at least 1", option))?;
+    Ok(n)
+}
+
+/// Parse the value of `--option` in seconds, which must be positive.
+fn parse_timeout(option: &str, value: &str) -> Result<Duration, Error> {
+    let seconds = parse::<f64>(option, value)?;
+    ensure(seconds > 0.0 && seconds.is_finite(), || {
+        format!("--{} must be positive", option)
+    })?;
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+/// Parse a header name with an optional value, e.g. `x-forwarded-ssl=off`.
+fn parse_proto_header(value: &str) -> Result<(String, String), Error> {
+    let (name, value) = value.split_once('=').unwrap_or((value, "http"));
+    ensure(
+        hyper::header::HeaderName::from_bytes(name.as_bytes()).is_ok(),
+        || format!("Invalid header name {} in --proto-header", name),
+    )?;
+    Ok((name.to_lowercase(), value.to_string()))
+}
+
+/// Check the name of an index file, which can't be in another directory.
+fn parse_index_file(name: &str) -> Result<String, Error> {
+    ensure(!name.is_empty() && !name.contains('/'), || {
+        format!(
+            "Invalid index file name {}, expected a name like index.html",
+            name
+        )
+    })?;
+    Ok(name.to_string())
+}
+
+/// Parse a directory with its index files, e.g. `/docs=README.html,index.html`.
+fn parse_dir_index(value: &str) -> Result<(String, Vec<String>), Error> {
+    let (dir, names) = value.split_once('=').ok_or_else(|| {
+        Error::Config(format!(
+            "Invalid --dir-index {}, expected DIR=NAME[,NAME...]",
+            value
+        ))
+    })?;
+    let dir = format!("/{}/", dir.trim_matches('/'));
+    let dir = if dir == "//" { String::from("/") } else { dir };
+    let names = names
+        .split(',')
+        .map(parse_index_file)
+        .collect::<Result<_, _>>()?;
+    Ok((dir, names))
+}
+
+/// Parse a comma-separated list of encodings like `zstd,gzip`.
+fn parse_encoding_order(value: &str) -> Result<Vec<Encoding>, Error> {
+    let mut order = Vec::new();
+    for name in value.split(',').map(str::trim) {
+        let encoding = Encoding::parse(name).ok_or_else(|| {
+            Error::Config(format!(
+                "Unknown encoding {} in --encoding-order, expected zstd or gzip",
+                name
+            ))
+        })?;
+        ensure(!order.contains(&encoding), || {
+            format!("--encoding-order lists {} twice", name)
+        })?;
+        order.push(encoding);
+    }
+    Ok(order)
+}
+
+/// Parse an IP address, which may be an IPv6 address with a zone like `fe80::1%eth0`. The zone is
+/// either the name or the index of a network interface.
+fn parse_address(value: &str) -> Result<(IpAddr, Option<u32>), Error> {
+    let (address, zone) = match value.split_once('%') {
+        Some((address, zone)) => (address, Some(zone)),
+        None => (value, None),
+    };
+    let address = parse::<IpAddr>("address", address)?;
+    let scope_id = match zone {
+        Some(zone) => {
+            ensure(address.is_ipv6(), || {
+                String::from("Only IPv6 addresses can have a zone")
+            })?;
+            let index = zone.parse::<u32>().ok().or_else(|| interface_index(zone));
+            Some(index.ok_or_else(|| {
+                Error::Config(format!("Unknown network interface {} in --address", zone))
+            })?)
+        }
+        None => None,
+    };
+    Ok((address, scope_id))
+}
+
+#[cfg(unix)]
+fn interface_index(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    // SAFETY: `name` is a valid NUL-terminated string which outlives the call
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    if index == 0 {
+        None
+    } else {
+        Some(index)
+    }
+}
+
+/// Interfaces can only be given by index on other platforms.
+#[cfg(not(unix))]
+fn interface_index(_name: &str) -> Option<u32> {
+    None
+}
+
+/// The command line, with environment variables in the arguments expanded (see `vars::expand`),
+/// so that they can be used in quoted values and wherever the shell doesn't expand them.
+fn expanded_args() -> Result<Vec<OsString>, Error> {
+    std::env::args_os()
+        .enumerate()
+        .map(|(i, arg)| match arg.to_str() {
+            Some(value) if i > 0 => vars::expand(value)
+                .map(OsString::from)
+                .map_err(|e| Error::Config(format!("Unable to expand argument {}: {}", value, e))),
+            _ => Ok(arg),
+        })
+        .collect()
+}
+
+/// The arguments given in the `--config` files, followed by those on the command line. Values in
+/// the files are expanded like those on the command line.
+fn merged_args() -> Result<Vec<OsString>, Error> {
+    let args = expanded_args()?;
+    // Only to find the config files and which options the command line gives
+    let cli = app(false).get_matches_from(args.clone());
+    let files = match cli.values_of("config") {
+        Some(files) => files,
+        None => return Ok(args),
+    };
+    let entries = config_file::merge(
+        files
+            .map(|file| {
+                let text = std::fs::read_to_string(file)
+                    .map_err(Error::io(format!("Unable to read config file {}", file)))?;
+                config_file::parse(&text)
+                    .map_err(|e| Error::Config(format!("Invalid config file {}: {}", file, e)))
+            })
+            .collect::<Result<_, _>>()?,
+    );
+    let given = |key: &str| {
+        let name = match key {
+            "dir" => "DIR",
+            "fallback-dirs" => "dir",
+            "redirect-http" => "redirect",
+            key => key,
+        };
+        cli.occurrences_of(name) > 0
+    };
+    let mut merged = args[..1].to_vec();
+    for arg in config_file::to_args(&entries, given) {
+        let arg = vars::expand(&arg).map_err(|e| {
+            Error::Config(format!("Unable to expand {} in a config file: {}", arg, e))
+        })?;
+        merged.push(OsString::from(arg));
+    }
+    merged.extend_from_slice(&args[1..]);
+    Ok(merged)
+}
+
+/// What the command line asks for.
+enum Command {
+    Serve(Config),
+    /// Print the configuration, for `--print-config`
+    PrintConfig(Config),
+}
+
+fn parse_config() -> Result<Command, Error> {
+    let matches = app(true).get_matches_from(merged_args()?);
+    let config = config_from(&matches)?;
+    if matches.is_present("print-config") {
+        Ok(Command::PrintConfig(config))
+    } else {
+        Ok(Command::Serve(config))
+    }
+}
+
+/// The command line interface. Without `dir_required`, the arguments are accepted without a
+/// directory to serve, as it may come from a config file.
+fn app(dir_required: bool) -> App<'static, 'static> {
+    let dir = Arg::with_name("DIR")
+        .value_name("DIR")
+        .help(if cfg!(feature = "embed") {
+            "Set the directory (or .zip/.tar/.tar.gz archive) to serve instead of the embedded \
+             files"
+        } else {
+            "Set the directory (or .zip/.tar/.tar.gz archive) to serve"
+        })
+        .takes_value(true);
+    let dir = if dir_required {
+        dir.required_unless_one(&["dir", "stdin-tar"])
+            .required(!cfg!(feature = "embed"))
+    } else {
+        dir
+    };
+    App::new("httpserve")
         .version(crate_version!())
         .author("James Guthrie")
         .about("Serve files from a directory")
+        .arg(dir)
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .help(
+                    "Read options from FILE, in the format of --print-config. Given multiple \
+                     times, later files override earlier ones, and the command line overrides \
+                     them all",
+                )
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
         .arg(
-            Arg::with_name("DIR")
+            Arg::with_name("dir")
+                .long("dir")
                 .value_name("DIR")
-                .help("Set the directory to serve")
-                .required(true)
-                .takes_value(true),
+                .help(
+                    "Add a directory (or archive) to serve files from. Given multiple times, \
+                     files are served from the first one containing them, after DIR",
+                )
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
         )
         .arg(
             Arg::with_name("port")
@@ -80,7 +380,33 @@ fn parse_config() -> Config {
                 .short("a")
                 .long("address")
                 .value_name("ADDRESS")
-                .help("Sets the address to bind to")
+                .help("Sets the address to bind to, with %ZONE for link-local IPv6 addresses")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("local-only")
+                .long("local-only")
+                .help("Refuse to listen on any address but a loopback one, like 127.0.0.1 or ::1"),
+        )
+        .arg(
+            Arg::with_name("admin-port")
+                .long("admin-port")
+                .value_name("PORT")
+                .help("Serve the admin API (GET /cache/stats, POST /cache/clear) on PORT")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("admin-address")
+                .long("admin-address")
+                .value_name("ADDRESS")
+                .help("Set the address the admin API binds to [default: 127.0.0.1]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("admin-auth")
+                .long("admin-auth")
+                .value_name("USER:PASSWORD")
+                .help("Require the credentials for the admin API")
                 .takes_value(true),
         )
         .arg(
@@ -89,161 +415,924 @@ fn parse_config() -> Config {
                 .long("redirect-http")
                 .help("Whether to redirect http to https"),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("threads")
+                .short("t")
+                .long("threads")
+                .value_name("THREADS")
+                .help("Set the number of worker threads (defaults to the number of CPUs)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("archive")
+                .long("archive")
+                .help("Treat DIR as an archive regardless of its extension"),
+        )
+        .arg(
+            Arg::with_name("stdin-tar")
+                .long("stdin-tar")
+                .help("Serve the files of a tar (or .tar.gz) archive piped to standard input")
+                .conflicts_with_all(&["DIR", "dir", "archive"]),
+        )
+        .arg(
+            Arg::with_name("stats-interval")
+                .long("stats-interval")
+                .value_name("SECONDS")
+                .help("Log a summary of requests served every SECONDS seconds")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("access-log-format")
+                .long("access-log-format")
+                .value_name("FORMAT")
+                .help(
+                    "Log requests in FORMAT, with directives like %h (client), %r (request line), \
+                     %s (status), %b (bytes) and %D (microseconds)",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("no-log-404")
+                .long("no-log-404")
+                .help("Don't log requests answered with 404 Not Found, e.g. those of scanners"),
+        )
+        .arg(
+            Arg::with_name("etag-mode")
+                .long("etag-mode")
+                .value_name("MODE")
+                .help("Set how ETags are generated")
+                .possible_values(&["strong", "weak", "off"])
+                .default_value("strong")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("allow-method")
+                .long("allow-method")
+                .value_name("METHOD[=STATUS]")
+                .help(
+                    "Respond to METHOD with an empty STATUS (default 200) response instead of 405",
+                )
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("mime")
+                .long("mime")
+                .value_name(".EXT=TYPE")
+                .help("Serve files with extension EXT with content type TYPE")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("mime-types")
+                .long("mime-types")
+                .value_name("FILE")
+                .help("Load additional content types from an Apache-style mime.types file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("sniff").long("sniff").help(
+                "Guess the content type of files without a known extension from their contents",
+            ),
+        )
+        .arg(
+            Arg::with_name("virtual")
+                .long("virtual")
+                .value_name("PATH=CONTENT")
+                .help(
+                    "Serve CONTENT at PATH, taking precedence over a file at the same path. \
+                     The content type is derived from the extension of PATH",
+                )
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("max-age")
+                .long("max-age")
+                .value_name("SECONDS")
+                .help("Allow clients to cache files for SECONDS seconds (0 to always revalidate)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("health-path")
+                .long("health-path")
+                .value_name("PATH")
+                .help("Answer health checks at PATH (empty to disable)")
+                .default_value("/healthz")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("status-path")
+                .long("status-path")
+                .value_name("PATH")
+                .help(
+                    "Report the number of cached files, their size and the uptime as JSON at PATH",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("background-load")
+                .long("background-load")
+                .help("Start listening before all files are loaded, answering 503 until they are"),
+        )
+        .arg(
+            Arg::with_name("ready-delay")
+                .long("ready-delay")
+                .value_name("SECONDS")
+                .help("Keep answering 503 for SECONDS after the files are loaded")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("compress")
+                .long("compress")
+                .help("Serve gzip-compressed responses to clients which accept them"),
+        )
+        .arg(
+            Arg::with_name("compress-min-size")
+                .long("compress-min-size")
+                .value_name("BYTES")
+                .help("Don't compress responses smaller than BYTES")
+                .default_value("1024")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("gzip-level")
+                .long("gzip-level")
+                .value_name("LEVEL")
+                .help("Set the gzip compression level, from 1 (fastest) to 9 (smallest)")
+                .default_value("6")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("no-cache")
+                .long("no-cache")
+                .help("Read files from DIR on every request instead of loading them into memory")
+                .conflicts_with("archive"),
+        )
+        .arg(
+            Arg::with_name("no-index-fallback")
+                .long("no-index-fallback")
+                .help("Answer requests for directories with 404 instead of serving their index.html"),
+        )
+        .arg(
+            Arg::with_name("index")
+                .long("index")
+                .value_name("NAME")
+                .help(
+                    "Serve the file NAME for requests to a directory (index.html by default). \
+                     Given multiple times, the first existing one is served",
+                )
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("dir-index")
+                .long("dir-index")
+                .value_name("DIR=NAME[,NAME...]")
+                .help(
+                    "Serve the first existing NAME instead of the --index files for requests to \
+                     DIR and the directories below it",
+                )
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("root-document")
+                .long("root-document")
+                .value_name("PATH")
+                .help("Serve the file at PATH, e.g. /pages/home.html, for requests to /")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("strip-prefix-on-disk")
+                .long("strip-prefix-on-disk")
+                .value_name("PREFIX")
+                .help("Serve DIR/PREFIX/FILE at /FILE, and nothing outside of DIR/PREFIX")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("add-url-prefix")
+                .long("add-url-prefix")
+                .value_name("PREFIX")
+                .help("Serve DIR/FILE at /PREFIX/FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dir-per-request")
+                .long("dir-per-request")
+                .help(
+                    "Serve each directory in DIR at /NAME/, confined to it, and nothing outside \
+                     of these directories",
+                ),
+        )
+        .arg(
+            Arg::with_name("precompressed-gzip")
+                .long("precompressed-gzip")
+                .help(
+                    "Serve FILE.gz for FILE if only the former exists, decompressed for clients \
+                     which don't accept gzip",
+                ),
+        )
+        .arg(
+            Arg::with_name("precompressed-zstd")
+                .long("precompressed-zstd")
+                .help("Serve FILE.zst for FILE to clients which accept zstd"),
+        )
+        .arg(
+            Arg::with_name("encoding-order")
+                .long("encoding-order")
+                .value_name("ENCODINGS")
+                .help("Set the preferred encodings of files, most preferred first")
+                .default_value("zstd,gzip")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("attachment")
+                .long("attachment")
+                .value_name("GLOB")
+                .help("Serve files matching GLOB as downloads, e.g. '*.csv' or '/reports/**'")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("reuse-port")
+                .long("reuse-port")
+                .help("Allow other processes to listen on the same port (Linux and BSD only)"),
+        )
+        .arg(
+            Arg::with_name("case-insensitive")
+                .long("case-insensitive")
+                .help("Match request paths regardless of case if there is no exact match"),
+        )
+        .arg(
+            Arg::with_name("strip-trailing-dot")
+                .long("strip-trailing-dot")
+                .help("Ignore a trailing '.' in request paths"),
+        )
+        .arg(
+            Arg::with_name("maintenance-page")
+                .long("maintenance-page")
+                .value_name("FILE")
+                .help("Answer requests with FILE and 503 while in maintenance mode, toggled by SIGUSR1")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("preload")
+                .long("preload")
+                .value_name("GLOB=<URL>;as=TYPE")
+                .help("Add a Link preload hint for URL to the responses for files matching GLOB")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("cors-allow-origin")
+                .long("cors-allow-origin")
+                .value_name("ORIGIN")
+                .help("Allow cross-origin requests from ORIGIN, such as https://example.com, or * for any")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("cors-allow-methods")
+                .long("cors-allow-methods")
+                .value_name("METHODS")
+                .help("Allow the comma-separated METHODS in preflight responses, by default those answered")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cors-allow-headers")
+                .long("cors-allow-headers")
+                .value_name("HEADERS")
+                .help("Allow the comma-separated request HEADERS in preflight responses")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cors-reflect-headers")
+                .long("cors-reflect-headers")
+                .help("Allow whichever request headers preflight requests ask for")
+                .conflicts_with("cors-allow-headers"),
+        )
+        .arg(
+            Arg::with_name("cors-max-age")
+                .long("cors-max-age")
+                .value_name("SECONDS")
+                .help("Let browsers cache preflight responses for SECONDS")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("ua-rule")
+                .long("ua-rule")
+                .value_name("PATTERN=>PREFIX")
+                .help("Serve files from under PREFIX to user agents matching PATTERN, ~ for a regex")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("preload-path")
+                .long("preload-path")
+                .value_name("PATH")
+                .help("Keep the file at request PATH in memory from startup, even with --no-cache")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("watch")
+                .long("watch")
+                .help("Reload files in DIR when they change")
+                .conflicts_with_all(&[
+                    "archive",
+                    "no-cache",
+                    "precompressed-gzip",
+                    "precompressed-zstd",
+                ]),
+        )
+        .arg(
+            Arg::with_name("watch-debounce")
+                .long("watch-debounce")
+                .value_name("MILLISECONDS")
+                .help("Wait until there have been no changes for MILLISECONDS before reloading")
+                .default_value("200")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("redirects")
+                .long("redirects")
+                .value_name("FILE")
+                .help("Redirect requests according to the rules in FILE, e.g. '/old /new 301'")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("redirect-status")
+                .long("redirect-status")
+                .value_name("STATUS")
+                .help("Set the status of the https and canonical host redirects, 308 preserves the method")
+                .possible_values(&["301", "308"])
+                .default_value("301")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("no-redirect-body")
+                .long("no-redirect-body")
+                .help("Send the https and canonical host redirects without a body linking to the target"),
+        )
+        .arg(
+            Arg::with_name("vhost")
+                .long("vhost")
+                .value_name("HOST=DIR")
+                .help("Serve DIR for requests to HOST (or to any subdomain, with *.HOST)")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("print-config")
+                .long("print-config")
+                .help("Print the effective configuration as TOML and exit"),
+        )
+        .arg(
+            Arg::with_name("server-header")
+                .long("server-header")
+                .value_name("VALUE")
+                .help("Send a Server header with VALUE on every response (empty for none)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("generate-sitemap")
+                .long("generate-sitemap")
+                .help("Serve a generated /sitemap.xml listing all HTML files, and a /robots.txt")
+                .requires("site-url"),
+        )
+        .arg(
+            Arg::with_name("site-url")
+                .long("site-url")
+                .value_name("URL")
+                .help("Set the URL at which the site is served, for the sitemap")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("writable")
+                .long("writable")
+                .help("Store and remove files in DIR on PUT and DELETE requests")
+                .conflicts_with("archive"),
+        )
+        .arg(
+            Arg::with_name("max-upload-size")
+                .long("max-upload-size")
+                .value_name("BYTES")
+                .help("Answer uploads with --writable larger than BYTES with 413")
+                .default_value("104857600")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("age-header")
+                .long("age-header")
+                .help("Send 'Age: 0' with files, to tell intermediary caches that they are fresh"),
+        )
+        .arg(
+            Arg::with_name("negotiate-language")
+                .long("negotiate-language")
+                .value_name("DEFAULT")
+                .help(
+                    "Serve variants like index.de.html for index.html based on Accept-Language, \
+                     falling back to the DEFAULT language",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("pid-file")
+                .long("pid-file")
+                .value_name("FILE")
+                .help("Write the process ID to FILE once listening, and remove it on shutdown")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("proxy-headers")
+                .long("proxy-headers")
+                .value_name("HEADERS")
+                .help(
+                    "Trust the Forwarded or X-Forwarded-* headers (preferring the one given) for \
+                     the client address, host and scheme",
+                )
+                .possible_values(&["none", "forwarded", "x-forwarded"])
+                .default_value("none")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("canonical-host")
+                .long("canonical-host")
+                .value_name("HOST")
+                .help("Redirect requests for any other host to HOST, keeping the path and query")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-connections")
+                .long("max-connections")
+                .value_name("N")
+                .help("Answer requests on connections beyond the first N with 503 and close them")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-concurrent-per-ip")
+                .long("max-concurrent-per-ip")
+                .value_name("N")
+                .help("Answer requests beyond N in flight from the same client IP with 429")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("basic-auth")
+                .long("basic-auth")
+                .value_name("[PREFIX:]USER:PASSWORD")
+                .help("Require the credentials for paths below PREFIX (or all paths) (repeatable)")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("request-timeout")
+                .long("request-timeout")
+                .value_name("SECONDS")
+                .help("Answer requests taking longer than SECONDS with 504 Gateway Timeout")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("connection-idle-timeout")
+                .long("connection-idle-timeout")
+                .value_name("SECONDS")
+                .help("Drop connections which haven't sent a request within SECONDS of connecting")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("keep-alive-max-requests")
+                .long("keep-alive-max-requests")
+                .value_name("N")
+                .help("Close HTTP/1 connections after serving N requests on them")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("listen-backlog")
+                .long("listen-backlog")
+                .value_name("N")
+                .help("Queue up to N connections which have not been accepted yet")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("source-maps")
+                .long("source-maps")
+                .help("Send a SourceMap header with scripts and stylesheets which have a .map file"),
+        )
+        .arg(
+            Arg::with_name("check-permissions")
+                .long("check-permissions")
+                .help("Warn about world-writable files and directories in DIR on startup"),
+        )
+        .arg(
+            Arg::with_name("checksum-trailer")
+                .long("checksum-trailer")
+                .help("Send the SHA-256 of files as a trailer over HTTP/2, if the client accepts it"),
+        )
+        .arg(
+            Arg::with_name("max-uri-length")
+                .long("max-uri-length")
+                .value_name("BYTES")
+                .help("Answer requests whose path and query are longer than BYTES with 414")
+                .default_value("8192")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("abort-on-panic")
+                .long("abort-on-panic")
+                .help("Abort the process when handling a request panics, instead of serving on"),
+        )
+        .arg(
+            Arg::with_name("reject-get-body")
+                .long("reject-get-body")
+                .help("Reject GET and HEAD requests with a body with 400, rather than ignoring it"),
+        )
+        .arg(
+            Arg::with_name("subst")
+                .long("subst")
+                .value_name("NAME=VALUE")
+                .help("Replace the placeholder for NAME with VALUE in text files, e.g. {{NAME}}")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("subst-pattern")
+                .long("subst-pattern")
+                .value_name("PATTERN")
+                .help("Set the syntax of placeholders for --subst, where NAME stands for the name")
+                .default_value("{{NAME}}")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("empty-as-204")
+                .long("empty-as-204")
+                .help("Answer requests for empty files with 204 No Content instead of 200"),
+        )
+        .arg(
+            Arg::with_name("json-reformat")
+                .long("json-reformat")
+                .value_name("FORMAT")
+                .help("Minify or pretty-print JSON files when loading them")
+                .possible_values(&["minify", "pretty"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("proto-header")
+                .long("proto-header")
+                .value_name("NAME[=VALUE]")
+                .help(
+                    "Set the header (and its value, http by default) marking plain http requests \
+                     for the https redirect, e.g. Forwarded",
+                )
+                .default_value("x-forwarded-proto=http"),
+        )
+}
 
-    let dir = matches.value_of("DIR").unwrap().to_string();
-    let address = matches
-        .value_of("address")
-        .map_or(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), |addr| {
-            addr.parse::<IpAddr>().expect("Unable to parse IP address")
-        });
-    let port = matches.value_of("port").map_or(3000, |p| {
-        p.parse::<u16>().expect("Unable to parse port number")
-    });
+/// Parse each value of `--option` with `parse`.
+fn parse_values<T>(
+    matches: &ArgMatches,
+    option: &str,
+    parse: impl Fn(&str) -> Result<T, Error>,
+) -> Result<Vec<T>, Error> {
+    matches
+        .values_of(option)
+        .into_iter()
+        .flatten()
+        .map(parse)
+        .collect()
+}
+
+/// Parse the value of `--option` if it's given, such as a number.
+fn parse_value<T: FromStr>(matches: &ArgMatches, option: &str) -> Result<Option<T>, Error>
+where
+    T::Err: Display,
+{
+    matches
+        .value_of(option)
+        .map(|value| parse(option, value))
+        .transpose()
+}
+
+fn config_from(matches: &ArgMatches) -> Result<Config, Error> {
+    // The positional DIR takes precedence, followed by each --dir in the order given
+    let mut dirs = matches
+        .values_of("DIR")
+        .into_iter()
+        .flatten()
+        .chain(matches.values_of("dir").into_iter().flatten())
+        .map(String::from);
+    let dir = dirs.next();
+    let fallback_dirs = dirs.collect();
+    let (address, scope_id) = match matches.value_of("address") {
+        Some(address) => parse_address(address)?,
+        None => (IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), None),
+    };
+    let port = parse_value(matches, "port")?.unwrap_or(3000);
     let redirect_http = matches.is_present("redirect");
-    Config {
+    let threads = match matches.value_of("threads") {
+        Some(threads) => parse_count("threads", threads)?,
+        None => num_cpus::get(),
+    };
+    let stats_interval = match parse_value::<u64>(matches, "stats-interval")? {
+        Some(seconds) => {
+            ensure(seconds >= 1, || {
+                String::from("--stats-interval must be at least 1 second")
+            })?;
+            Some(Duration::from_secs(seconds))
+        }
+        None => None,
+    };
+    let allowed_methods = parse_values(matches, "allow-method", parse_allowed_method)?
+        .into_iter()
+        .collect();
+    let gzip_level = parse("gzip-level", matches.value_of("gzip-level").unwrap())?;
+    ensure((1..=9).contains(&gzip_level), || {
+        String::from("The gzip level must be between 1 and 9")
+    })?;
+    let admin_auth = match matches.value_of("admin-auth") {
+        Some(credentials) => {
+            ensure(credentials.contains(':'), || {
+                String::from("Admin auth must be of the form USER:PASSWORD")
+            })?;
+            Some(credentials.to_string())
+        }
+        None => None,
+    };
+    let ready_delay = match parse_value::<f64>(matches, "ready-delay")? {
+        Some(seconds) => {
+            ensure(seconds >= 0.0 && seconds.is_finite(), || {
+                String::from("--ready-delay must not be negative")
+            })?;
+            Some(Duration::from_secs_f64(seconds))
+        }
+        None => None,
+    };
+    let index_files = match matches.values_of("index") {
+        Some(names) => names.map(parse_index_file).collect::<Result<_, _>>()?,
+        None => Config::default().index_files,
+    };
+    let watch = if matches.is_present("watch") {
+        let debounce = parse(
+            "watch-debounce",
+            matches.value_of("watch-debounce").unwrap(),
+        )?;
+        Some(Duration::from_millis(debounce))
+    } else {
+        None
+    };
+    let config = Config {
         dir,
+        fallback_dirs,
         address,
+        scope_id,
         port,
+        local_only: matches.is_present("local-only"),
+        admin_port: parse_value(matches, "admin-port")?,
+        admin_address: parse_value(matches, "admin-address")?
+            .unwrap_or(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))),
+        admin_auth,
         redirect_http,
-    }
+        threads,
+        archive: matches.is_present("archive"),
+        stdin_tar: matches.is_present("stdin-tar"),
+        stats_interval,
+        etag_mode: match matches.value_of("etag-mode").unwrap() {
+            "weak" => EtagMode::Weak,
+            "off" => EtagMode::Off,
+            _ => EtagMode::Strong,
+        },
+        allowed_methods,
+        mime_types_file: matches.value_of("mime-types").map(PathBuf::from),
+        mime_overrides: parse_values(matches, "mime", mime::parse_override)?,
+        sniff: matches.is_present("sniff"),
+        virtual_files: parse_values(matches, "virtual", parse_virtual_file)?,
+        max_age: parse_value(matches, "max-age")?,
+        health_path: matches
+            .value_of("health-path")
+            .filter(|p| !p.is_empty())
+            .map(String::from),
+        status_path: matches.value_of("status-path").map(String::from),
+        background_load: matches.is_present("background-load"),
+        ready_delay,
+        compression: Compression {
+            enabled: matches.is_present("compress"),
+            min_size: parse(
+                "compress-min-size",
+                matches.value_of("compress-min-size").unwrap(),
+            )?,
+            gzip_level,
+        },
+        no_cache: matches.is_present("no-cache"),
+        attachments: matches
+            .values_of("attachment")
+            .map_or_else(Vec::new, |values| values.map(String::from).collect()),
+        reuse_port: matches.is_present("reuse-port"),
+        case_insensitive: matches.is_present("case-insensitive"),
+        strip_trailing_dot: matches.is_present("strip-trailing-dot"),
+        no_index_fallback: matches.is_present("no-index-fallback"),
+        index_files,
+        dir_index_files: parse_values(matches, "dir-index", parse_dir_index)?,
+        strip_prefix_on_disk: matches
+            .value_of("strip-prefix-on-disk")
+            .and_then(path::normalize_prefix),
+        add_url_prefix: matches
+            .value_of("add-url-prefix")
+            .and_then(path::normalize_prefix),
+        dir_per_request: matches.is_present("dir-per-request"),
+        precompressed_gzip: matches.is_present("precompressed-gzip"),
+        precompressed_zstd: matches.is_present("precompressed-zstd"),
+        encoding_order: parse_encoding_order(matches.value_of("encoding-order").unwrap())?,
+        root_document: matches.value_of("root-document").map(String::from),
+        maintenance_page: matches.value_of("maintenance-page").map(PathBuf::from),
+        watch,
+        vhosts: parse_values(matches, "vhost", parse_vhost)?,
+        sitemap_url: if matches.is_present("generate-sitemap") {
+            matches.value_of("site-url").map(String::from)
+        } else {
+            None
+        },
+        writable: matches.is_present("writable"),
+        max_upload_size: parse(
+            "max-upload-size",
+            matches.value_of("max-upload-size").unwrap(),
+        )?,
+        age_header: matches.is_present("age-header"),
+        access_log_format: matches.value_of("access-log-format").map(String::from),
+        no_log_404: matches.is_present("no-log-404"),
+        cors_allow_origins: matches
+            .values_of("cors-allow-origin")
+            .map_or_else(Vec::new, |values| values.map(String::from).collect()),
+        cors_allow_methods: matches
+            .value_of("cors-allow-methods")
+            .map_or_else(Vec::new, cors::parse_list),
+        cors_allow_headers: matches
+            .value_of("cors-allow-headers")
+            .map_or_else(Vec::new, cors::parse_list),
+        cors_reflect_headers: matches.is_present("cors-reflect-headers"),
+        cors_max_age: parse_value(matches, "cors-max-age")?,
+        default_language: matches.value_of("negotiate-language").map(String::from),
+        pid_file: matches.value_of("pid-file").map(PathBuf::from),
+        canonical_host: matches.value_of("canonical-host").map(String::from),
+        keep_alive_max_requests: matches
+            .value_of("keep-alive-max-requests")
+            .map(|n| parse_count("keep-alive-max-requests", n))
+            .transpose()?,
+        listen_backlog: parse_value(matches, "listen-backlog")?,
+        source_maps: matches.is_present("source-maps"),
+        check_permissions: matches.is_present("check-permissions"),
+        checksum_trailer: matches.is_present("checksum-trailer"),
+        reject_get_body: matches.is_present("reject-get-body"),
+        abort_on_panic: matches.is_present("abort-on-panic"),
+        max_uri_length: parse(
+            "max-uri-length",
+            matches.value_of("max-uri-length").unwrap(),
+        )?,
+        proto_header: parse_proto_header(matches.value_of("proto-header").unwrap())?,
+        proxy_headers: match matches.value_of("proxy-headers").unwrap() {
+            "forwarded" => ProxyHeaders::Forwarded,
+            "x-forwarded" => ProxyHeaders::XForwarded,
+            _ => ProxyHeaders::Ignore,
+        },
+        preload_paths: matches
+            .values_of("preload-path")
+            .map_or_else(Vec::new, |values| values.map(String::from).collect()),
+        substitutions: parse_values(matches, "subst", subst::parse_substitution)?,
+        subst_pattern: matches.value_of("subst-pattern").unwrap().to_string(),
+        empty_as_204: matches.is_present("empty-as-204"),
+        json_format: matches
+            .value_of("json-reformat")
+            .map(|format| match format {
+                "minify" => JsonFormat::Minify,
+                _ => JsonFormat::Pretty,
+            }),
+        request_timeout: matches
+            .value_of("request-timeout")
+            .map(|t| parse_timeout("request-timeout", t))
+            .transpose()?,
+        connection_idle_timeout: matches
+            .value_of("connection-idle-timeout")
+            .map(|t| parse_timeout("connection-idle-timeout", t))
+            .transpose()?,
+        basic_auth: parse_values(matches, "basic-auth", auth::parse_basic_auth)?,
+        max_connections: matches
+            .value_of("max-connections")
+            .map(|n| parse_count("max-connections", n))
+            .transpose()?,
+        max_concurrent_per_ip: matches
+            .value_of("max-concurrent-per-ip")
+            .map(|n| parse_count("max-concurrent-per-ip", n))
+            .transpose()?,
+        server_header: matches.value_of("server-header").map(String::from),
+        redirects_file: matches.value_of("redirects").map(PathBuf::from),
+        redirect_status: match matches.value_of("redirect-status") {
+            Some("308") => StatusCode::PERMANENT_REDIRECT,
+            _ => StatusCode::MOVED_PERMANENTLY,
+        },
+        no_redirect_body: matches.is_present("no-redirect-body"),
+        preloads: parse_values(matches, "preload", preload::parse_preload)?,
+        ua_rules: parse_values(matches, "ua-rule", user_agent::parse_ua_rule)?,
+    };
+    Ok(config)
 }
 
-fn configure_logging() {
-    let config = ConfigBuilder::new()
-        .set_target_level(LevelFilter::Trace)
-        .set_time_format(String::from("%Y-%m-%dT%H:%M:%S%.3f"))
-        .build();
-    let _ = TermLogger::init(
-        LevelFilter::Debug,
-        config,
-        TerminalMode::Stdout,
-        ColorChoice::Auto,
-    );
+fn parse_virtual_file(value: &str) -> Result<(String, Vec<u8>), Error> {
+    let (path, content) = value.split_once('=').ok_or_else(|| {
+        Error::Config(format!(
+            "Invalid --virtual {}, expected the form PATH=CONTENT",
+            value
+        ))
+    })?;
+    ensure(path.starts_with('/'), || {
+        format!("The path of --virtual {} must start with /", value)
+    })?;
+    Ok((path.to_string(), unescape(content).into_bytes()))
 }
 
-struct FileServer {
-    cache: HashMap<String, Vec<u8>>,
-    http_to_https_redirect: bool,
-}
-
-impl FileServer {
-    pub fn new(dir: PathBuf, http_to_https_redirect: bool) -> FileServer {
-        let mut cache: HashMap<String, Vec<u8>> = HashMap::new();
-        let mut to_visit: VecDeque<PathBuf> = VecDeque::from(vec![dir.clone()]);
-        while to_visit.len() > 0 {
-            match to_visit.pop_front() {
-                Some(item) => {
-                    if item.is_dir() {
-                        let children = fs::read_dir(&item).expect("Failed to read directory");
-                        children.into_iter().for_each(|child| {
-                            let new_path = child.expect("Unable to traverse directory").path();
-                            to_visit.push_back(new_path);
-                        });
-                    } else {
-                        let copy = item.to_owned();
-                        let file_path = copy.to_str().expect("Path not Unicode");
-                        let path = file_path
-                            .strip_prefix((*&dir).to_str().expect("Path not Unicode"))
-                            .unwrap();
-                        let content = read(item).expect("Failed to read file");
-                        debug!("Loaded {} bytes from {}", content.len(), path);
-                        cache.insert(path.to_owned(), content);
-                    }
-                }
-                None => {
-                    warn!("Queue was empty. This was not expected.");
-                }
-            }
+/// Replace the escape sequences `\n`, `\t` and `\\` so that multi-line content can be given
+/// on the command line.
+fn unescape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
         }
-        return FileServer {
-            cache,
-            http_to_https_redirect,
-        };
-    }
-
-    async fn handle(&self, req: Request<Body>) -> Result<Response<Body>, Infallible> {
-        let method = req.method();
-        let uri = req.uri();
-
-        info!("{} {}", method, uri);
-
-        let response = match *method {
-            Method::GET => {
-                self.build_https_redirect(&req).unwrap_or_else(|| {
-                    let mut path = uri.path().to_string();
-                    if !self.cache.contains_key(&*path) {
-                        // apply a simple fallback rule to fetch index.html
-                        if uri.path().ends_with("/") {
-                            path = uri.path().to_string() + "index.html";
-                        }
-                    }
-                    let maybe_body = self.cache.get(&*path);
-                    return match maybe_body {
-                        Some(body) => Response::builder()
-                            .status(StatusCode::OK)
-                            .body(Body::from(body.to_owned()))
-                            .expect("Unable to create `http::Response`"),
-                        None => Response::builder()
-                            .status(StatusCode::NOT_FOUND)
-                            .body(Body::empty())
-                            .expect("Unable to create `http::Response`"),
-                    };
-                })
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('\\') => result.push('\\'),
+            Some(other) => {
+                result.push('\\');
+                result.push(other);
             }
-            _ => Response::builder()
-                .status(StatusCode::METHOD_NOT_ALLOWED)
-                .body(Body::empty())
-                .expect("Unable to create `http::Response`"),
-        };
-        return Ok(response);
-    }
-
-    /// A simple http -> https redirect, based on the presence of the `x-forwarded-proto` header in
-    /// the request. This is as described in the following fly.io blog post:
-    /// https://fly.io/blog/always-be-connecting-with-https/
-    fn build_https_redirect(&self, req: &Request<Body>) -> Option<Response<Body>> {
-        let uri = req.uri();
-        if !self.http_to_https_redirect {
-            return None;
-        }
-
-        let fwd_proto = req.headers().get("x-forwarded-proto");
-        if !fwd_proto.is_some() {
-            return None;
-        }
-
-        if fwd_proto.unwrap() != "http" {
-            return None;
+            None => result.push('\\'),
         }
+    }
+    result
+}
 
-        let path_and_query = uri.path_and_query().expect("No path and query");
+/// Parse a virtual host of the form `HOST=DIR`.
+fn parse_vhost(value: &str) -> Result<(String, String), Error> {
+    let (host, dir) = value.split_once('=').ok_or_else(|| {
+        Error::Config(format!(
+            "Invalid --vhost {}, expected the form HOST=DIR",
+            value
+        ))
+    })?;
+    Ok((host.to_string(), dir.to_string()))
+}
 
-        // Determining the current host can go via two methods:
-        // - in http1.1 and earlier: via the "host" header set on the request
-        // - in http2 onwards: via the "authority" component of the Uri
-        let host = req.headers().get("host").map_or_else(
-            // Unwrap here and on the line below should only cause a problem if the authority ot
-            // hostname do not contain ASCII characters. Ignore this edge case for now.
-            || uri.authority().unwrap().as_str(),
-            |v| v.to_str().unwrap(),
-        );
+fn parse_allowed_method(value: &str) -> Result<(Method, StatusCode), Error> {
+    let (method, status) = match value.split_once('=') {
+        Some((method, status)) => (method, status),
+        None => (value, "200"),
+    };
+    let method = parse::<Method>("allow-method", method)?;
+    ensure(method != Method::GET && method != Method::HEAD, || {
+        String::from("GET and HEAD are always allowed")
+    })?;
+    let status = parse::<StatusCode>("allow-method", status)?;
+    Ok((method, status))
+}
 
-        let https_request = Builder::new()
-            .scheme("https")
-            .path_and_query(path_and_query.clone())
-            .authority(host)
-            .build()
-            .unwrap();
-
-        info!("Redirecting to https for {}", path_and_query);
-
-        return Some(
-            Response::builder()
-                .status(StatusCode::MOVED_PERMANENTLY)
-                .header(LOCATION, https_request.to_string())
-                .body(Body::empty())
-                .expect("Unable to create https redirect"),
-        );
+fn configure_logging() {
+    if telemetry::init_subscriber() {
+        return;
+    }
+    let config = ConfigBuilder::new()
+        .set_target_level(LevelFilter::Trace)
+        .set_time_format(String::from("%Y-%m-%dT%H:%M:%S%.3f"))
+        .build();
+    // Without a terminal, e.g. under systemd or Docker, log plain lines without colors
+    let result = if io::stdout().is_terminal() {
+        TermLogger::init(
+            LevelFilter::Debug,
+            config.clone(),
+            TerminalMode::Stdout,
+            ColorChoice::Auto,
+        )
+    } else {
+        WriteLogger::init(LevelFilter::Debug, config.clone(), io::stdout())
+    };
+    // Rather than run without any logs, try plain lines on stderr
+    let result = result.or_else(|e| {
+        eprintln!("Unable to log to stdout ({}), logging to stderr instead", e);
+        WriteLogger::init(LevelFilter::Debug, config, io::stderr())
+    });
+    if let Err(e) = result {
+        eprintln!("Unable to initialize logging: {}", e);
     }
 }
@@ -0,0 +1,193 @@
+//! Content types for responses, looked up by file extension with overrides from `--mime` and
+//! `--mime-types`, or sniffed from the first bytes of a file.
+
+use crate::error::Error;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// The content type for files whose extension we don't recognise.
+pub const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+const BUILTIN: &[(&str, &str)] = &[
+    ("avif", "image/avif"),
+    ("css", "text/css; charset=utf-8"),
+    ("csv", "text/csv; charset=utf-8"),
+    ("gif", "image/gif"),
+    ("gz", "application/gzip"),
+    ("htm", "text/html; charset=utf-8"),
+    ("html", "text/html; charset=utf-8"),
+    ("ico", "image/x-icon"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("js", "text/javascript; charset=utf-8"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("md", "text/markdown; charset=utf-8"),
+    ("mjs", "text/javascript; charset=utf-8"),
+    ("mp3", "audio/mpeg"),
+    ("mp4", "video/mp4"),
+    ("ogg", "audio/ogg"),
+    ("otf", "font/otf"),
+    ("pdf", "application/pdf"),
+    ("png", "image/png"),
+    ("svg", "image/svg+xml"),
+    ("tar", "application/x-tar"),
+    ("ttf", "font/ttf"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("wasm", "application/wasm"),
+    ("wav", "audio/wav"),
+    ("webm", "video/webm"),
+    ("webmanifest", "application/manifest+json"),
+    ("webp", "image/webp"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("xml", "application/xml"),
+    ("zip", "application/zip"),
+];
+
+/// Mapping from (lowercase) file extension to content type.
+pub struct MimeTypes {
+    types: HashMap<String, String>,
+}
+
+impl MimeTypes {
+    /// Build the mapping from the built-in types, then the types listed in an Apache-style
+    /// `mime.types` file, then the explicit `overrides`. Later sources take precedence.
+    pub fn new(
+        mime_types_file: Option<&Path>,
+        overrides: &[(String, String)],
+    ) -> Result<MimeTypes, Error> {
+        let mut types: HashMap<String, String> = BUILTIN
+            .iter()
+            .map(|(ext, t)| (ext.to_string(), t.to_string()))
+            .collect();
+        if let Some(path) = mime_types_file {
+            let content = fs::read_to_string(path).map_err(Error::io(format!(
+                "Failed to read mime.types file {}",
+                path.display()
+            )))?;
+            types.extend(parse_mime_types(&content));
+        }
+        for (ext, t) in overrides {
+            types.insert(ext.to_lowercase(), t.clone());
+        }
+        Ok(MimeTypes { types })
+    }
+
+    /// Look up the content type for a request path, based on its extension.
+    pub fn lookup(&self, path: &str) -> Option<&str> {
+        let file_name = path.rsplit('/').next().unwrap_or(path);
+        let (_, ext) = file_name.rsplit_once('.')?;
+        self.types.get(&ext.to_lowercase()).map(String::as_str)
+    }
+}
+
+/// Parse an override of the form `.ext=type/subtype` (the leading dot is optional).
+pub fn parse_override(value: &str) -> Result<(String, String), Error> {
+    let (ext, t) = value.split_once('=').ok_or_else(|| {
+        Error::Config(format!(
+            "Invalid --mime {}, expected the form .EXT=TYPE",
+            value
+        ))
+    })?;
+    Ok((ext.trim_start_matches('.').to_string(), t.to_string()))
+}
+
+/// Parse the contents of a `mime.types` file, where each line consists of a type followed by
+/// the extensions which map to it, e.g. `application/wasm wasm`.
+fn parse_mime_types(content: &str) -> Vec<(String, String)> {
+    let mut types = Vec::new();
+    for line in content.lines() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut parts = line.split_whitespace();
+        if let Some(t) = parts.next() {
+            for ext in parts {
+                types.push((ext.to_lowercase(), t.to_string()));
+            }
+        }
+    }
+    types
+}
+
+/// Guess the content type of a file from its first bytes. This only distinguishes a handful of
+/// common types, anything else is either plain text or `DEFAULT_CONTENT_TYPE`.
+pub fn sniff(content: &[u8]) -> &'static str {
+    let head = &content[..content.len().min(512)];
+    if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return "image/png";
+    }
+    if head.starts_with(b"\xff\xd8\xff") {
+        return "image/jpeg";
+    }
+    if head.starts_with(b"GIF87a") || head.starts_with(b"GIF89a") {
+        return "image/gif";
+    }
+    if head.len() >= 12 && head.starts_with(b"RIFF") && &head[8..12] == b"WEBP" {
+        return "image/webp";
+    }
+
+    // Text formats may have a byte order mark and leading whitespace
+    let text = head.strip_prefix(b"\xef\xbb\xbf").unwrap_or(head);
+    let start = text
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(text.len());
+    if looks_like_html(&text[start..]) {
+        return "text/html; charset=utf-8";
+    }
+    if looks_like_json(content) {
+        return "application/json";
+    }
+    let is_text = !head.contains(&0)
+        && match std::str::from_utf8(head) {
+            Ok(_) => true,
+            // The sniffed prefix may end in the middle of a multi-byte character
+            Err(e) => e.error_len().is_none(),
+        };
+    if is_text {
+        return "text/plain; charset=utf-8";
+    }
+    DEFAULT_CONTENT_TYPE
+}
+
+/// Check for one of the tags listed in the HTML sniffing rules of the WHATWG MIME Sniffing
+/// standard, followed by a space or `>`.
+fn looks_like_html(text: &[u8]) -> bool {
+    const TAGS: &[&[u8]] = &[
+        b"<!doctype html",
+        b"<html",
+        b"<head",
+        b"<script",
+        b"<iframe",
+        b"<h1",
+        b"<div",
+        b"<font",
+        b"<table",
+        b"<a",
+        b"<style",
+        b"<title",
+        b"<b",
+        b"<body",
+        b"<br",
+        b"<p",
+        b"<!--",
+    ];
+    TAGS.iter().any(|tag| {
+        text.len() > tag.len()
+            && text[..tag.len()].eq_ignore_ascii_case(tag)
+            && (text[tag.len()] == b' ' || text[tag.len()] == b'>')
+    })
+}
+
+/// A cheap check for JSON objects and arrays, which doesn't validate the whole document.
+fn looks_like_json(content: &[u8]) -> bool {
+    let trimmed = content
+        .strip_prefix(b"\xef\xbb\xbf")
+        .unwrap_or(content)
+        .trim_ascii();
+    matches!(
+        (trimmed.first(), trimmed.last()),
+        (Some(b'{'), Some(b'}')) | (Some(b'['), Some(b']'))
+    )
+}
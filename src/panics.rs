@@ -0,0 +1,54 @@
+//! Logging of panics, with the request being handled when they happened, and `--abort-on-panic`.
+
+use log::error;
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::future::Future;
+use std::panic::{self, PanicHookInfo};
+
+tokio::task_local! {
+    /// The method and path of the request the current task is handling.
+    static REQUEST: String;
+}
+
+/// Handle a request, for which `description` (like `GET /index.html`) is logged with any panic.
+pub async fn with_request<F: Future>(description: String, handler: F) -> F::Output {
+    REQUEST.scope(description, handler).await
+}
+
+/// Log panics as errors instead of printing them, with a backtrace if `RUST_BACKTRACE` asks for
+/// one. Hyper carries on serving other connections after a panic; with `abort`, the process is
+/// aborted instead, for a supervisor to restart it.
+pub fn install_hook(abort: bool) {
+    panic::set_hook(Box::new(move |info| {
+        let request = REQUEST
+            .try_with(|request| format!(" while handling {}", request))
+            .unwrap_or_default();
+        let backtrace = Backtrace::capture();
+        let backtrace = match backtrace.status() {
+            BacktraceStatus::Captured => format!("\n{}", backtrace),
+            _ => String::new(),
+        };
+        error!(
+            "Panic{} at {}: {}{}",
+            request,
+            info.location()
+                .map_or_else(|| String::from("an unknown location"), |l| l.to_string()),
+            message(info),
+            backtrace
+        );
+        if abort {
+            error!("Aborting after the panic, because of --abort-on-panic");
+            std::process::abort();
+        }
+    }));
+}
+
+fn message<'a>(info: &'a PanicHookInfo) -> &'a str {
+    match info.payload().downcast_ref::<&str>() {
+        Some(message) => message,
+        None => info
+            .payload()
+            .downcast_ref::<String>()
+            .map_or("Box<dyn Any>", String::as_str),
+    }
+}
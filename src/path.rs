@@ -0,0 +1,214 @@
+//! Turn request paths into the paths files are cached under. This is kept free of any server
+//! state, so that it can be fuzzed (see `fuzz/`).
+
+/// The path to look up for the path of a request: percent-decoded and normalized, optionally
+/// without a trailing `.`. Returns `None` for paths which can't refer to any file, because they
+/// decode to invalid UTF-8 or contain a NUL byte.
+pub fn request_path(path: &str, strip_trailing_dot: bool) -> Option<String> {
+    // Decoding first means that encoded separators and dots, like `..%2F`, are normalized too
+    let mut decoded = percent_decode(path)?;
+    if decoded.contains('\0') {
+        return None;
+    }
+    // Before normalizing, so that e.g. `/...` can't turn into `/..`
+    if strip_trailing_dot && decoded.ends_with('.') {
+        decoded.pop();
+    }
+    Some(normalize(&decoded))
+}
+
+/// Normalize a request path to the form used as cache key: it always starts with `/`, and
+/// empty, `.` and `..` segments are resolved. A trailing `/` is kept, so that an empty path or
+/// one like `/a/..` ends up as `/` and gets the index fallback.
+pub fn normalize(path: &str) -> String {
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    let mut normalized = String::new();
+    for segment in &segments {
+        normalized.push('/');
+        normalized.push_str(segment);
+    }
+    let is_dir = segments.is_empty()
+        || path.ends_with('/')
+        || path.ends_with("/.")
+        || path.ends_with("/..")
+        || path == "."
+        || path == "..";
+    if is_dir {
+        normalized.push('/');
+    }
+    normalized
+}
+
+/// The namespace of a normalized request path, with `--dir-per-request`: its first segment,
+/// provided there is something below it. `/alice/file` and `/alice/` are in namespace `alice`,
+/// whereas `/` and `/file` are in none.
+pub fn namespace(path: &str) -> Option<&str> {
+    let (namespace, _) = path.strip_prefix('/')?.split_once('/')?;
+    Some(namespace).filter(|namespace| !namespace.is_empty())
+}
+
+/// Whether the decoded `path` has a `..` segment, which `normalize` would resolve.
+pub fn has_parent_segment(path: &str) -> bool {
+    percent_decode(path).is_some_and(|decoded| decoded.split('/').any(|segment| segment == ".."))
+}
+
+/// Map the request path of a file as found in the served directory to the path it is served at,
+/// for `--strip-prefix-on-disk` and `--add-url-prefix`: first `strip` is removed, then `add` is
+/// prepended. Both are normalized prefixes without a trailing `/`, like `/dist`. Returns `None`
+/// for files outside of `strip`, which aren't served.
+pub fn remap(path: &str, strip: Option<&str>, add: Option<&str>) -> Option<String> {
+    let rest = match strip {
+        Some(strip) => path
+            .strip_prefix(strip)
+            .filter(|rest| rest.starts_with('/'))?,
+        None => path,
+    };
+    Some(format!("{}{}", add.unwrap_or(""), rest))
+}
+
+/// Normalize a prefix for `remap`, e.g. `dist/` to `/dist`. Returns `None` for prefixes which
+/// are empty once normalized, like `/`.
+pub fn normalize_prefix(prefix: &str) -> Option<String> {
+    let normalized = normalize(prefix);
+    let normalized = normalized.trim_end_matches('/');
+    Some(normalized.to_string()).filter(|normalized| !normalized.is_empty())
+}
+
+/// Encode a request path for use in a URL, leaving the `/` separators as they are.
+pub fn percent_encode(path: &str) -> String {
+    let mut encoded = String::new();
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Decode `%XX` escapes. A `%` which isn't followed by two hex digits is kept as it is.
+fn percent_decode(path: &str) -> Option<String> {
+    if !path.contains('%') {
+        return Some(path.to_string());
+    }
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match bytes.get(i + 1..i + 3) {
+            Some(hex) if bytes[i] == b'%' && hex.iter().all(u8::is_ascii_hexdigit) => {
+                Some(hex_value(hex[0]) << 4 | hex_value(hex[1]))
+            }
+            _ => None,
+        };
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn hex_value(digit: u8) -> u8 {
+    match digit {
+        b'0'..=b'9' => digit - b'0',
+        b'a'..=b'f' => digit - b'a' + 10,
+        _ => digit - b'A' + 10,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_and_normalizes_request_paths() {
+        let path = |path| request_path(path, false);
+        assert_eq!(path("/a%20b/c.html").as_deref(), Some("/a b/c.html"));
+        assert_eq!(path("/a/%2e%2e/b").as_deref(), Some("/b"));
+        assert_eq!(path("/a/..%2F..%2Fetc").as_deref(), Some("/etc"));
+        assert_eq!(path("/%C3%A9").as_deref(), Some("/é"));
+        assert_eq!(path("").as_deref(), Some("/"));
+        // Incomplete escapes are kept
+        assert_eq!(path("/100%").as_deref(), Some("/100%"));
+        assert_eq!(path("/%zz%4").as_deref(), Some("/%zz%4"));
+    }
+
+    #[test]
+    fn rejects_paths_which_cant_be_files() {
+        assert_eq!(request_path("/a%00b", false), None);
+        assert_eq!(request_path("/%ff", false), None);
+        assert_eq!(request_path("/%C3", false), None);
+    }
+
+    #[test]
+    fn strips_a_trailing_dot_before_normalizing() {
+        assert_eq!(request_path("/file.", true).as_deref(), Some("/file"));
+        assert_eq!(request_path("/file.", false).as_deref(), Some("/file."));
+        assert_eq!(request_path("/a/...", true).as_deref(), Some("/"));
+    }
+
+    #[test]
+    fn normalizes_segments() {
+        assert_eq!(normalize("a/b"), "/a/b");
+        assert_eq!(normalize("/a/./b//c/"), "/a/b/c/");
+        assert_eq!(normalize("/a/.."), "/");
+        assert_eq!(normalize("/a/b/."), "/a/b/");
+        assert_eq!(normalize("/../../etc/passwd"), "/etc/passwd");
+        assert_eq!(normalize(".."), "/");
+        assert_eq!(normalize("/a..b/.c"), "/a..b/.c");
+    }
+
+    #[test]
+    fn finds_namespaces_and_parent_segments() {
+        assert_eq!(namespace("/alice/file"), Some("alice"));
+        assert_eq!(namespace("/alice/"), Some("alice"));
+        assert_eq!(namespace("/file"), None);
+        assert_eq!(namespace("/"), None);
+        assert!(has_parent_segment("/a/%2e%2E/b"));
+        assert!(has_parent_segment("/a/.."));
+        assert!(!has_parent_segment("/a..b/...c"));
+    }
+
+    #[test]
+    fn remaps_prefixes() {
+        assert_eq!(
+            remap("/dist/app.js", Some("/dist"), Some("/static")).as_deref(),
+            Some("/static/app.js")
+        );
+        assert_eq!(
+            remap("/app.js", None, Some("/static")).as_deref(),
+            Some("/static/app.js")
+        );
+        assert_eq!(remap("/distx/app.js", Some("/dist"), None), None);
+        assert_eq!(remap("/dist", Some("/dist"), None), None);
+        assert_eq!(normalize_prefix("dist/").as_deref(), Some("/dist"));
+        assert_eq!(normalize_prefix("/a/./b/").as_deref(), Some("/a/b"));
+        assert_eq!(normalize_prefix("/"), None);
+    }
+
+    #[test]
+    fn percent_encodes_all_but_unreserved_characters() {
+        assert_eq!(
+            percent_encode("/a b/é?#%.txt"),
+            "/a%20b/%C3%A9%3F%23%25.txt"
+        );
+        assert_eq!(percent_encode("/-._~/"), "/-._~/");
+    }
+}
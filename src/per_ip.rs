@@ -0,0 +1,58 @@
+//! Limits on the number of requests each client IP address has in flight at the same time, for
+//! `--max-concurrent-per-ip`.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+/// Counts the requests in flight per client. Clients without any are not tracked at all, so the
+/// counters don't grow with the number of clients ever seen.
+pub struct PerIpLimit {
+    max: usize,
+    in_flight: Mutex<HashMap<IpAddr, usize>>,
+}
+
+impl PerIpLimit {
+    pub fn new(max: usize) -> PerIpLimit {
+        PerIpLimit {
+            max,
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count a request from `ip` until the returned guard is dropped, unless `ip` has as many
+    /// requests in flight as allowed already.
+    pub fn try_acquire(self: &Arc<Self>, ip: IpAddr) -> Option<InFlight> {
+        let mut in_flight = self.in_flight.lock().expect("Per-IP counter lock poisoned");
+        let count = in_flight.entry(ip).or_insert(0);
+        if *count >= self.max {
+            return None;
+        }
+        *count += 1;
+        Some(InFlight {
+            limit: Arc::clone(self),
+            ip,
+        })
+    }
+}
+
+/// A request in flight, which stops counting once dropped.
+pub struct InFlight {
+    limit: Arc<PerIpLimit>,
+    ip: IpAddr,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let mut in_flight = match self.limit.in_flight.lock() {
+            Ok(in_flight) => in_flight,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Some(count) = in_flight.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                in_flight.remove(&self.ip);
+            }
+        }
+    }
+}
@@ -0,0 +1,69 @@
+//! `Link` preload hints for `--preload`, which let browsers start fetching what a page needs
+//! before they have parsed it.
+
+use crate::error::Error;
+use crate::glob::PathGlob;
+
+/// `Link` preload hints attached to the responses for matching files, as set with `--preload`.
+pub struct Preloads {
+    hints: Vec<(PathGlob, String)>,
+}
+
+impl Preloads {
+    pub fn new(hints: &[(String, String)]) -> Result<Preloads, Error> {
+        Ok(Preloads {
+            hints: hints
+                .iter()
+                .map(|(pattern, link)| Ok((PathGlob::new(pattern)?, link.clone())))
+                .collect::<Result<_, Error>>()?,
+        })
+    }
+
+    /// The `Link` header for the file at `path`, combining all hints whose pattern matches.
+    pub fn link(&self, path: &str) -> Option<String> {
+        let links: Vec<&str> = self
+            .hints
+            .iter()
+            .filter(|(pattern, _)| pattern.is_match(path))
+            .map(|(_, link)| link.as_str())
+            .collect();
+        if links.is_empty() {
+            None
+        } else {
+            Some(links.join(", "))
+        }
+    }
+}
+
+/// Parse a hint of the form `GLOB=<URL>;PARAM=VALUE...`, e.g. `*.html=</app.css>;as=style`. The
+/// angle brackets are optional, and `rel=preload` is added unless another `rel` is given.
+pub fn parse_preload(value: &str) -> Result<(String, String), Error> {
+    let (pattern, link) = value.split_once('=').ok_or_else(|| {
+        Error::Config(format!(
+            "Invalid --preload {}, expected the form GLOB=<URL>;as=TYPE",
+            value
+        ))
+    })?;
+    let mut parts = link.split(';').map(str::trim);
+    let target = parts.next().unwrap_or("");
+    if target.is_empty() {
+        return Err(Error::Config(format!(
+            "The --preload {} needs a URL",
+            value
+        )));
+    }
+    let mut formatted = if target.starts_with('<') {
+        target.to_string()
+    } else {
+        format!("<{}>", target)
+    };
+    let params: Vec<&str> = parts.filter(|p| !p.is_empty()).collect();
+    if !params.iter().any(|p| p.starts_with("rel=")) {
+        formatted.push_str("; rel=preload");
+    }
+    for param in params {
+        formatted.push_str("; ");
+        formatted.push_str(param);
+    }
+    Ok((pattern.to_string(), formatted))
+}
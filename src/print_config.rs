@@ -0,0 +1,325 @@
+//! Render the effective configuration for `--print-config`. Keys are named after the command
+//! line flags, and list values use the same format as the flags.
+
+use crate::json::JsonFormat;
+use crate::{Config, EtagMode};
+use std::fmt::Write;
+use std::path::Path;
+use std::time::Duration;
+
+impl Config {
+    /// Render the configuration as TOML, marking every value which differs from the default.
+    pub fn to_toml(&self) -> String {
+        let defaults = Config::default().lines();
+        let mut output = String::new();
+        for line in self.lines() {
+            if defaults.contains(&line) {
+                writeln!(output, "{}", line).unwrap();
+            } else {
+                writeln!(output, "{}  # set", line).unwrap();
+            }
+        }
+        output
+    }
+
+    fn lines(&self) -> Vec<String> {
+        let mut allowed_methods: Vec<String> = self
+            .allowed_methods
+            .iter()
+            .map(|(method, status)| format!("{}={}", method, status.as_u16()))
+            .collect();
+        allowed_methods.sort();
+        let pairs = |pairs: &[(String, String)], separator: &str| -> Vec<String> {
+            pairs
+                .iter()
+                .map(|(a, b)| format!("{}{}{}", a, separator, b))
+                .collect()
+        };
+        vec![
+            optional("dir", self.dir.as_deref().map(string)),
+            format!("fallback-dirs = {}", list(&self.fallback_dirs)),
+            format!(
+                "address = {}",
+                string(&match self.scope_id {
+                    Some(scope_id) => format!("{}%{}", self.address, scope_id),
+                    None => self.address.to_string(),
+                })
+            ),
+            format!("port = {}", self.port),
+            format!("local-only = {}", self.local_only),
+            optional("admin-port", self.admin_port.map(|p| p.to_string())),
+            format!(
+                "admin-address = {}",
+                string(&self.admin_address.to_string())
+            ),
+            optional(
+                "admin-auth",
+                self.admin_auth.as_deref().map(|credentials| {
+                    let user = credentials.split(':').next().unwrap_or("");
+                    string(&format!("{}:<password>", user))
+                }),
+            ),
+            format!("threads = {}", self.threads),
+            format!("archive = {}", self.archive),
+            format!("stdin-tar = {}", self.stdin_tar),
+            format!("no-cache = {}", self.no_cache),
+            format!("check-permissions = {}", self.check_permissions),
+            format!("watch = {}", self.watch.is_some()),
+            optional("watch-debounce", self.watch.map(millis)),
+            format!("background-load = {}", self.background_load),
+            optional(
+                "ready-delay",
+                self.ready_delay.map(|d| d.as_secs_f64().to_string()),
+            ),
+            format!("redirect-http = {}", self.redirect_http),
+            format!("redirect-status = {}", self.redirect_status.as_u16()),
+            format!("no-redirect-body = {}", self.no_redirect_body),
+            format!(
+                "proto-header = {}",
+                string(&format!("{}={}", self.proto_header.0, self.proto_header.1))
+            ),
+            format!("proxy-headers = {}", string(self.proxy_headers.name())),
+            optional("canonical-host", self.canonical_host.as_deref().map(string)),
+            optional("redirects", self.redirects_file.as_deref().map(path)),
+            format!("writable = {}", self.writable),
+            format!("max-upload-size = {}", self.max_upload_size),
+            format!("reuse-port = {}", self.reuse_port),
+            optional(
+                "max-connections",
+                self.max_connections.map(|n| n.to_string()),
+            ),
+            optional(
+                "max-concurrent-per-ip",
+                self.max_concurrent_per_ip.map(|n| n.to_string()),
+            ),
+            optional(
+                "keep-alive-max-requests",
+                self.keep_alive_max_requests.map(|n| n.to_string()),
+            ),
+            optional("listen-backlog", self.listen_backlog.map(|n| n.to_string())),
+            optional(
+                "request-timeout",
+                self.request_timeout.map(|t| t.as_secs_f64().to_string()),
+            ),
+            optional(
+                "connection-idle-timeout",
+                self.connection_idle_timeout
+                    .map(|t| t.as_secs_f64().to_string()),
+            ),
+            optional("pid-file", self.pid_file.as_deref().map(path)),
+            optional(
+                "stats-interval",
+                self.stats_interval.map(|d| d.as_secs().to_string()),
+            ),
+            optional(
+                "access-log-format",
+                self.access_log_format.as_deref().map(string),
+            ),
+            format!("no-log-404 = {}", self.no_log_404),
+            format!("cors-allow-origin = {}", list(&self.cors_allow_origins)),
+            optional(
+                "cors-allow-methods",
+                Some(self.cors_allow_methods.join(","))
+                    .filter(|methods| !methods.is_empty())
+                    .map(|methods| string(&methods)),
+            ),
+            optional(
+                "cors-allow-headers",
+                Some(self.cors_allow_headers.join(","))
+                    .filter(|headers| !headers.is_empty())
+                    .map(|headers| string(&headers)),
+            ),
+            format!("cors-reflect-headers = {}", self.cors_reflect_headers),
+            optional("cors-max-age", self.cors_max_age.map(|s| s.to_string())),
+            format!(
+                "etag-mode = {}",
+                string(match self.etag_mode {
+                    EtagMode::Strong => "strong",
+                    EtagMode::Weak => "weak",
+                    EtagMode::Off => "off",
+                })
+            ),
+            optional("max-age", self.max_age.map(|s| s.to_string())),
+            format!("allow-method = {}", list(&allowed_methods)),
+            optional("mime-types", self.mime_types_file.as_deref().map(path)),
+            format!(
+                "mime = {}",
+                list(
+                    &pairs(&self.mime_overrides, "=")
+                        .into_iter()
+                        .map(|m| format!(".{}", m))
+                        .collect::<Vec<_>>()
+                )
+            ),
+            format!("sniff = {}", self.sniff),
+            format!("compress = {}", self.compression.enabled),
+            format!("compress-min-size = {}", self.compression.min_size),
+            format!("gzip-level = {}", self.compression.gzip_level),
+            optional(
+                "strip-prefix-on-disk",
+                self.strip_prefix_on_disk.as_deref().map(string),
+            ),
+            optional("add-url-prefix", self.add_url_prefix.as_deref().map(string)),
+            format!("dir-per-request = {}", self.dir_per_request),
+            format!("precompressed-gzip = {}", self.precompressed_gzip),
+            format!("precompressed-zstd = {}", self.precompressed_zstd),
+            format!(
+                "encoding-order = {}",
+                string(
+                    &self
+                        .encoding_order
+                        .iter()
+                        .map(|encoding| encoding.name())
+                        .collect::<Vec<_>>()
+                        .join(",")
+                )
+            ),
+            format!("age-header = {}", self.age_header),
+            format!("source-maps = {}", self.source_maps),
+            format!("checksum-trailer = {}", self.checksum_trailer),
+            format!("reject-get-body = {}", self.reject_get_body),
+            format!("max-uri-length = {}", self.max_uri_length),
+            format!("abort-on-panic = {}", self.abort_on_panic),
+            format!("subst = {}", list(&pairs(&self.substitutions, "="))),
+            format!("subst-pattern = {}", string(&self.subst_pattern)),
+            format!("empty-as-204 = {}", self.empty_as_204),
+            optional(
+                "json-reformat",
+                self.json_format.map(|format| {
+                    string(match format {
+                        JsonFormat::Minify => "minify",
+                        JsonFormat::Pretty => "pretty",
+                    })
+                }),
+            ),
+            format!("attachment = {}", list(&self.attachments)),
+            format!("preload-path = {}", list(&self.preload_paths)),
+            format!("preload = {}", list(&pairs(&self.preloads, "="))),
+            format!("ua-rule = {}", list(&pairs(&self.ua_rules, "=>"))),
+            format!("case-insensitive = {}", self.case_insensitive),
+            format!("strip-trailing-dot = {}", self.strip_trailing_dot),
+            format!("no-index-fallback = {}", self.no_index_fallback),
+            format!("index = {}", list(&self.index_files)),
+            format!(
+                "dir-index = {}",
+                list(
+                    &self
+                        .dir_index_files
+                        .iter()
+                        .map(|(dir, names)| format!("{}={}", dir, names.join(",")))
+                        .collect::<Vec<_>>()
+                )
+            ),
+            optional("root-document", self.root_document.as_deref().map(string)),
+            optional(
+                "negotiate-language",
+                self.default_language.as_deref().map(string),
+            ),
+            // The contents of virtual files may be large, or secret
+            format!(
+                "virtual = {}",
+                list(
+                    &self
+                        .virtual_files
+                        .iter()
+                        .map(|(p, content)| format!("{}=<{} bytes>", p, content.len()))
+                        .collect::<Vec<_>>()
+                )
+            ),
+            format!("vhost = {}", list(&pairs(&self.vhosts, "="))),
+            // Only the users, not their passwords
+            format!(
+                "basic-auth = {}",
+                list(
+                    &self
+                        .basic_auth
+                        .iter()
+                        .map(|(prefix, credentials)| {
+                            let user = credentials.split(':').next().unwrap_or("");
+                            format!("{}:{}:<password>", prefix, user)
+                        })
+                        .collect::<Vec<_>>()
+                )
+            ),
+            optional("server-header", self.server_header.as_deref().map(string)),
+            format!("generate-sitemap = {}", self.sitemap_url.is_some()),
+            optional("site-url", self.sitemap_url.as_deref().map(string)),
+            optional("health-path", self.health_path.as_deref().map(string)),
+            optional("status-path", self.status_path.as_deref().map(string)),
+            optional(
+                "maintenance-page",
+                self.maintenance_page.as_deref().map(path),
+            ),
+        ]
+    }
+}
+
+/// A key whose value is not set is commented out.
+fn optional(key: &str, value: Option<String>) -> String {
+    match value {
+        Some(value) => format!("{} = {}", key, value),
+        None => format!("# {} is not set", key),
+    }
+}
+
+fn string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn path(value: &Path) -> String {
+    string(&value.to_string_lossy())
+}
+
+fn millis(value: Duration) -> String {
+    value.as_millis().to_string()
+}
+
+fn list(values: &[String]) -> String {
+    let values: Vec<String> = values.iter().map(|v| string(v)).collect();
+    format!("[{}]", values.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leaves_out_passwords_and_virtual_file_contents() {
+        let config = Config {
+            basic_auth: vec![
+                (String::from("/"), String::from("alice:basic-secret")),
+                (
+                    String::from("/admin"),
+                    String::from("bob:secret:with:colons"),
+                ),
+            ],
+            admin_auth: Some(String::from("ops:admin-secret")),
+            virtual_files: vec![(String::from("/token.txt"), b"file-secret".to_vec())],
+            ..Config::default()
+        };
+        let toml = config.to_toml();
+        assert!(!toml.contains("secret"), "{}", toml);
+        let lines: Vec<&str> = toml.lines().collect();
+        assert!(lines
+            .contains(&r#"basic-auth = ["/:alice:<password>", "/admin:bob:<password>"]  # set"#));
+        assert!(lines.contains(&r#"admin-auth = "ops:<password>"  # set"#));
+        assert!(lines.contains(&r#"virtual = ["/token.txt=<11 bytes>"]  # set"#));
+    }
+
+    #[test]
+    fn marks_only_the_values_which_differ_from_the_defaults() {
+        assert!(!Config::default().to_toml().contains("# set"));
+        let config = Config {
+            port: 8080,
+            no_cache: true,
+            ..Config::default()
+        };
+        let toml = config.to_toml();
+        let set: Vec<&str> = toml
+            .lines()
+            .filter(|line| line.ends_with("# set"))
+            .collect();
+        assert_eq!(set, ["port = 8080  # set", "no-cache = true  # set"]);
+        assert!(toml.lines().any(|line| line == r#"address = "127.0.0.1""#));
+    }
+}
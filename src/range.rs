@@ -0,0 +1,224 @@
+//! Byte range requests, such as `Range: bytes=0-99`. Several ranges in one request are answered
+//! with a `multipart/byteranges` body.
+
+use bytes::Bytes;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::time::SystemTime;
+
+/// Requests for more (non-overlapping) ranges than this get the full content instead, which is
+/// cheaper than a body split into that many parts.
+const MAX_RANGES: usize = 16;
+
+/// What to serve for the `Range` header of a request.
+pub enum Ranges {
+    /// The full content, as the header is missing, malformed or not worth honoring
+    Full,
+    /// Nothing, as none of the ranges overlap the content
+    Unsatisfiable,
+    /// The given ranges of the content, sorted and without overlaps, with inclusive ends
+    Satisfiable(Vec<(u64, u64)>),
+}
+
+/// Parse a `Range` header for content of `len` bytes. Overlapping and adjacent ranges are
+/// combined into one.
+pub fn parse(header: &str, len: u64) -> Ranges {
+    let specs = match header.trim().strip_prefix("bytes=") {
+        Some(specs) => specs,
+        None => return Ranges::Full,
+    };
+    let mut ranges = Vec::new();
+    let mut any = false;
+    for spec in specs
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+    {
+        any = true;
+        let (start, end) = match spec.split_once('-') {
+            Some(bounds) => bounds,
+            None => return Ranges::Full,
+        };
+        let range = if start.is_empty() {
+            // The last `end` bytes
+            match number(end) {
+                Some(0) => None,
+                Some(_) if len == 0 => None,
+                Some(suffix) => Some((len.saturating_sub(suffix), len - 1)),
+                None => return Ranges::Full,
+            }
+        } else {
+            let start = match number(start) {
+                Some(start) => start,
+                None => return Ranges::Full,
+            };
+            let end = match end {
+                "" => None,
+                end => match number(end) {
+                    Some(end) if end >= start => Some(end),
+                    _ => return Ranges::Full,
+                },
+            };
+            if start >= len {
+                None
+            } else {
+                Some((start, end.map_or(len - 1, |end| end.min(len - 1))))
+            }
+        };
+        ranges.extend(range);
+    }
+    if !any {
+        return Ranges::Full;
+    }
+    if ranges.is_empty() {
+        return Ranges::Unsatisfiable;
+    }
+    ranges.sort_unstable();
+    let mut combined: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match combined.last_mut() {
+            Some((_, last_end)) if start <= last_end.saturating_add(1) => {
+                *last_end = (*last_end).max(end);
+            }
+            _ => combined.push((start, end)),
+        }
+    }
+    if combined.len() > MAX_RANGES {
+        return Ranges::Full;
+    }
+    Ranges::Satisfiable(combined)
+}
+
+/// A number in a range, which consists of digits only.
+fn number(value: &str) -> Option<u64> {
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok()
+}
+
+/// The `Content-Range` of the range from `start` to `end` (inclusive) of `len` bytes.
+pub fn content_range(start: u64, end: u64, len: u64) -> String {
+    format!("bytes {}-{}/{}", start, end, len)
+}
+
+/// A boundary for a multipart body, which is unlikely to occur in the content.
+pub fn boundary(etag: Option<&str>) -> String {
+    let mut hasher = DefaultHasher::new();
+    etag.hash(&mut hasher);
+    SystemTime::now().hash(&mut hasher);
+    format!("httpserve-{:016x}", hasher.finish())
+}
+
+/// A `multipart/byteranges` body with a part for each of the `ranges` of `content`.
+pub fn multipart(
+    content: &Bytes,
+    content_type: &str,
+    ranges: &[(u64, u64)],
+    boundary: &str,
+) -> Bytes {
+    let len = content.len() as u64;
+    let mut body = Vec::new();
+    for &(start, end) in ranges {
+        body.extend_from_slice(
+            format!(
+                "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+                boundary,
+                content_type,
+                content_range(start, end, len)
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(&content[start as usize..=end as usize]);
+    }
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    Bytes::from(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn satisfiable(header: &str, len: u64) -> Vec<(u64, u64)> {
+        match parse(header, len) {
+            Ranges::Satisfiable(ranges) => ranges,
+            Ranges::Full => panic!("{:?} served in full", header),
+            Ranges::Unsatisfiable => panic!("{:?} unsatisfiable", header),
+        }
+    }
+
+    #[test]
+    fn parses_ranges_with_and_without_ends() {
+        assert_eq!(satisfiable("bytes=0-4", 10), [(0, 4)]);
+        assert_eq!(satisfiable("bytes=5-", 10), [(5, 9)]);
+        assert_eq!(satisfiable("bytes=2-100", 10), [(2, 9)]);
+        assert_eq!(satisfiable(" bytes=1-1 ", 10), [(1, 1)]);
+    }
+
+    #[test]
+    fn parses_suffix_ranges() {
+        assert_eq!(satisfiable("bytes=-3", 10), [(7, 9)]);
+        assert_eq!(satisfiable("bytes=-20", 10), [(0, 9)]);
+        assert!(matches!(parse("bytes=-0", 10), Ranges::Unsatisfiable));
+        assert!(matches!(parse("bytes=-1", 0), Ranges::Unsatisfiable));
+    }
+
+    #[test]
+    fn serves_malformed_ranges_in_full() {
+        for header in [
+            "bytes=4-2",
+            "bytes=a-b",
+            "bytes=1",
+            "bytes=+1-2",
+            "bytes=1--2",
+            "bytes=-",
+            "bytes=",
+            "bytes=,",
+            "items=0-1",
+            "0-1",
+        ] {
+            assert!(matches!(parse(header, 10), Ranges::Full), "{}", header);
+        }
+    }
+
+    #[test]
+    fn ignores_ranges_after_the_end() {
+        assert!(matches!(parse("bytes=10-", 10), Ranges::Unsatisfiable));
+        assert!(matches!(
+            parse("bytes=10-20,30-", 10),
+            Ranges::Unsatisfiable
+        ));
+        assert_eq!(satisfiable("bytes=10-20,0-0", 10), [(0, 0)]);
+    }
+
+    #[test]
+    fn combines_overlapping_and_adjacent_ranges() {
+        assert_eq!(satisfiable("bytes=6-7, 0-1,2-3", 10), [(0, 3), (6, 7)]);
+        assert_eq!(satisfiable("bytes=0-5,2-3,-2", 10), [(0, 5), (8, 9)]);
+        assert_eq!(satisfiable("bytes=0-,3-4", 10), [(0, 9)]);
+    }
+
+    #[test]
+    fn serves_too_many_ranges_in_full() {
+        let ranges = |count: u64| {
+            let specs: Vec<String> = (0..count).map(|i| format!("{0}-{0}", 2 * i)).collect();
+            format!("bytes={}", specs.join(","))
+        };
+        assert_eq!(satisfiable(&ranges(16), 100).len(), 16);
+        assert!(matches!(parse(&ranges(17), 100), Ranges::Full));
+        // Only the ranges left once they're combined count
+        assert_eq!(satisfiable(&format!("{},0-99", ranges(17)), 100), [(0, 99)]);
+    }
+
+    #[test]
+    fn lays_out_multipart_bodies() {
+        let content = Bytes::from_static(b"0123456789");
+        let body = multipart(&content, "text/plain", &[(0, 1), (8, 9)], "b");
+        assert_eq!(
+            &body[..],
+            &b"\r\n--b\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/10\r\n\r\n01\
+               \r\n--b\r\nContent-Type: text/plain\r\nContent-Range: bytes 8-9/10\r\n\r\n89\
+               \r\n--b--\r\n"[..]
+        );
+    }
+}
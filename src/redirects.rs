@@ -0,0 +1,223 @@
+//! Redirect rules for `--redirects`, which are checked before looking up files.
+
+use crate::error::Error;
+use crate::vars;
+use hyper::StatusCode;
+use std::fs;
+use std::path::Path;
+
+/// Redirect rules, as read from a Netlify-style `_redirects` file. Each line consists of a path,
+/// the target and optionally the status, e.g. `/old-path /new-path 301`. A path ending in `/*`
+/// matches everything below it, and `:splat` in the target is replaced by the matched part.
+pub struct Redirects {
+    rules: Vec<Rule>,
+}
+
+struct Rule {
+    /// The path, or its prefix (including the trailing `/`) if `splat` is set
+    from: String,
+    splat: bool,
+    to: String,
+    status: StatusCode,
+}
+
+impl Redirects {
+    pub fn new(path: Option<&Path>) -> Result<Redirects, Error> {
+        match path {
+            Some(path) => {
+                let content = fs::read_to_string(path).map_err(Error::io(format!(
+                    "Failed to read redirects file {}",
+                    path.display()
+                )))?;
+                Redirects::parse(&content).map_err(|e| {
+                    Error::Config(format!("Invalid redirects file {}: {}", path.display(), e))
+                })
+            }
+            None => Ok(Redirects { rules: Vec::new() }),
+        }
+    }
+
+    /// Parse the rules, failing on the first invalid one so that mistakes are noticed at
+    /// startup.
+    fn parse(content: &str) -> Result<Redirects, String> {
+        let mut rules = Vec::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = strip_comment(line);
+            let line = vars::expand(line)
+                .map_err(|e| format!("invalid redirect rule on line {}: {}", i + 1, e))?;
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let (from, to, status) = match parts[..] {
+                [] => continue,
+                [from, to] => (from, to, StatusCode::MOVED_PERMANENTLY),
+                [from, to, status] => {
+                    let status = status
+                        .parse::<u16>()
+                        .ok()
+                        .and_then(|s| StatusCode::from_u16(s).ok())
+                        .filter(|s| is_redirect(*s))
+                        .ok_or_else(|| {
+                            format!("invalid redirect status on line {}: {}", i + 1, status)
+                        })?;
+                    (from, to, status)
+                }
+                _ => {
+                    return Err(format!(
+                        "invalid redirect rule on line {}, expected FROM TO [STATUS]",
+                        i + 1
+                    ))
+                }
+            };
+            if !from.starts_with('/') {
+                return Err(format!(
+                    "invalid redirect rule on line {}, the path must start with /",
+                    i + 1
+                ));
+            }
+            let (from, splat) = match from.strip_suffix('*') {
+                Some(prefix) if prefix.ends_with('/') => (prefix, true),
+                _ => (from, false),
+            };
+            if !splat && to.contains(":splat") {
+                return Err(format!(
+                    "invalid redirect rule on line {}, :splat requires a path ending in /*",
+                    i + 1
+                ));
+            }
+            rules.push(Rule {
+                from: from.to_string(),
+                splat,
+                to: to.to_string(),
+                status,
+            });
+        }
+        Ok(Redirects { rules })
+    }
+
+    /// The status and `Location` of the first rule matching `path`, if any.
+    pub fn find(&self, path: &str) -> Option<(StatusCode, String)> {
+        self.rules.iter().find_map(|rule| {
+            if rule.splat {
+                // `/blog/*` also matches `/blog` itself
+                let dir = &rule.from[..rule.from.len() - 1];
+                let splat = path
+                    .strip_prefix(&rule.from)
+                    .or_else(|| (path == dir).then_some(""))?;
+                Some((rule.status, rule.to.replace(":splat", splat)))
+            } else if path == rule.from {
+                Some((rule.status, rule.to.clone()))
+            } else {
+                None
+            }
+        })
+    }
+}
+
+/// `line` without its comment. A `#` only starts one at the start of the line or after
+/// whitespace, so that targets like `/new#section` keep their fragment.
+fn strip_comment(line: &str) -> &str {
+    let mut previous = None;
+    for (i, c) in line.char_indices() {
+        if c == '#' && previous.is_none_or(char::is_whitespace) {
+            return &line[..i];
+        }
+        previous = Some(c);
+    }
+    line
+}
+
+fn is_redirect(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 301 | 302 | 303 | 307 | 308)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(redirects: &Redirects, path: &str) -> Option<(u16, String)> {
+        redirects
+            .find(path)
+            .map(|(status, location)| (status.as_u16(), location))
+    }
+
+    #[test]
+    fn parses_rules_with_and_without_status() {
+        let redirects = Redirects::parse(
+            "# Moved pages\n\
+             \n\
+             /old /new\n\
+             /temporary   https://example.com/  302  # for now\n",
+        )
+        .unwrap();
+        assert_eq!(find(&redirects, "/old"), Some((301, "/new".into())));
+        assert_eq!(
+            find(&redirects, "/temporary"),
+            Some((302, "https://example.com/".into()))
+        );
+        assert_eq!(find(&redirects, "/old/"), None);
+        assert_eq!(find(&redirects, "/new"), None);
+    }
+
+    #[test]
+    fn keeps_fragments_in_targets() {
+        let redirects = Redirects::parse("/old /new#section 308 #comment").unwrap();
+        assert_eq!(find(&redirects, "/old"), Some((308, "/new#section".into())));
+        assert_eq!(strip_comment("#/a /b"), "");
+        assert_eq!(strip_comment("/a /b\t# c"), "/a /b\t");
+        assert_eq!(strip_comment("/a#b /c#d"), "/a#b /c#d");
+    }
+
+    #[test]
+    fn substitutes_the_splat() {
+        let redirects = Redirects::parse(
+            "/blog/* /news/:splat 302\n\
+             /blog/archive /never\n\
+             /docs/* /manual/",
+        )
+        .unwrap();
+        assert_eq!(
+            find(&redirects, "/blog/2020/post"),
+            Some((302, "/news/2020/post".into()))
+        );
+        // The first matching rule wins
+        assert_eq!(
+            find(&redirects, "/blog/archive"),
+            Some((302, "/news/archive".into()))
+        );
+        assert_eq!(find(&redirects, "/blog"), Some((302, "/news/".into())));
+        assert_eq!(find(&redirects, "/blogs"), None);
+        assert_eq!(
+            find(&redirects, "/docs/a/b"),
+            Some((301, "/manual/".into()))
+        );
+        // A `*` which doesn't follow a `/` is part of the path
+        let redirects = Redirects::parse("/file* /other").unwrap();
+        assert_eq!(find(&redirects, "/file*"), Some((301, "/other".into())));
+        assert_eq!(find(&redirects, "/files"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_rules_with_their_line() {
+        let error = |content| Redirects::parse(content).err().unwrap();
+        assert_eq!(error("/a /b 200"), "invalid redirect status on line 1: 200");
+        assert_eq!(
+            error("/a /b\n/c /d 3o1"),
+            "invalid redirect status on line 2: 3o1"
+        );
+        assert_eq!(
+            error("/a /b 301 extra"),
+            "invalid redirect rule on line 1, expected FROM TO [STATUS]"
+        );
+        assert_eq!(
+            error("/a"),
+            "invalid redirect rule on line 1, expected FROM TO [STATUS]"
+        );
+        assert_eq!(
+            error("a /b"),
+            "invalid redirect rule on line 1, the path must start with /"
+        );
+        assert_eq!(
+            error("/a /b/:splat"),
+            "invalid redirect rule on line 1, :splat requires a path ending in /*"
+        );
+    }
+}
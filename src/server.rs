@@ -0,0 +1,425 @@
+//! The HTTP server around a `FileServer`, as run by the `httpserve` binary: loading the files,
+//! binding the listener, and all the options which act on connections rather than requests,
+//! such as `--max-connections`. Applications which bring their own Hyper server can call
+//! `FileServer::handle` directly instead.
+
+use crate::access_log::RemoteAddr;
+use crate::admin::Admin;
+use crate::connection::{Connection, Incoming};
+use crate::error::Error;
+use crate::panics;
+use crate::per_ip::PerIpLimit;
+use crate::stats::Stats;
+use crate::telemetry::ConnectionSpan;
+use crate::validate::Problem;
+use crate::vhost::VirtualHosts;
+use crate::watch;
+use crate::{Config, FileServer};
+use hyper::header::{HeaderValue, CONNECTION, CONTENT_TYPE, RETRY_AFTER};
+use hyper::server::conn::AddrIncoming;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode, Version};
+use log::{error, info, warn};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::convert::Infallible;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr, SocketAddrV6};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{oneshot, Semaphore};
+
+/// Serves the files of a `Config` with the behavior of the `httpserve` binary.
+///
+/// ```no_run
+/// # async fn example() {
+/// let config = httpserve::Config {
+///     dir: Some(String::from("./public")),
+///     ..Default::default()
+/// };
+/// httpserve::server::ServerBuilder::new(config)
+///     .with_graceful_shutdown(async {
+///         tokio::signal::ctrl_c().await.ok();
+///     })
+///     .serve()
+///     .await
+///     .unwrap_or_else(|e| eprintln!("{}", e));
+/// # }
+/// ```
+pub struct ServerBuilder {
+    config: Config,
+    shutdown: Pin<Box<dyn Future<Output = ()> + Send>>,
+    on_bound: Option<Box<dyn FnOnce(SocketAddr) + Send>>,
+}
+
+impl ServerBuilder {
+    /// A server for `config`, which keeps running until the process exits.
+    pub fn new(config: Config) -> ServerBuilder {
+        ServerBuilder {
+            config,
+            shutdown: Box::pin(std::future::pending()),
+            on_bound: None,
+        }
+    }
+
+    /// Stop accepting connections once `signal` completes, and finish `serve` once the open
+    /// connections are closed.
+    pub fn with_graceful_shutdown(
+        mut self,
+        signal: impl Future<Output = ()> + Send + 'static,
+    ) -> ServerBuilder {
+        self.shutdown = Box::pin(signal);
+        self
+    }
+
+    /// Call `callback` with the address once it is bound, which tells the port the system chose
+    /// when `config.port` is 0.
+    pub fn on_bound(mut self, callback: impl FnOnce(SocketAddr) + Send + 'static) -> ServerBuilder {
+        self.on_bound = Some(Box::new(callback));
+        self
+    }
+
+    /// Load the files and serve them on the configured address and port, until the shutdown
+    /// signal completes. Fails if options conflict (see `Config::check_conflicts`), the files can't be
+    /// loaded, the address can't be bound or the server fails while serving; with
+    /// `background_load`, a failure to load the files stops the server once it happens. It must be
+    /// run on a Tokio runtime with IO and timers enabled.
+    pub async fn serve(self) -> Result<(), Error> {
+        let ServerBuilder {
+            config,
+            shutdown,
+            on_bound,
+        } = self;
+        config.check_conflicts()?;
+        for problem in config.validate() {
+            if let Problem::NoEffect(message) = problem {
+                warn!("{}", message);
+            }
+        }
+        let addr = match (config.address, config.scope_id) {
+            (IpAddr::V6(ip), Some(scope_id)) => {
+                SocketAddrV6::new(ip, config.port, 0, scope_id).into()
+            }
+            (ip, _) => SocketAddr::from((ip, config.port)),
+        };
+        info!(
+            "Starting httpserve on {} with {} worker threads",
+            addr, config.threads
+        );
+
+        // Only sent to when loading in the background fails
+        let (load_failed_tx, load_failed) = oneshot::channel();
+        let file_server = if config.background_load {
+            let file_server = Arc::new(FileServer::unloaded(&config)?);
+            let loader = Arc::clone(&file_server);
+            let loader_config = config.clone();
+            let loading = tokio::task::spawn_blocking(move || loader.load(&loader_config));
+            let delayed = Arc::clone(&file_server);
+            let ready_delay = config.ready_delay;
+            tokio::spawn(async move {
+                let error = match loading.await {
+                    Ok(Ok(())) => None,
+                    Ok(Err(e)) => Some(e),
+                    Err(e) => Some(Error::Io {
+                        context: String::from("Unable to load the files"),
+                        source: io::Error::other(e),
+                    }),
+                };
+                if let Some(error) = error {
+                    let _ = load_failed_tx.send(error);
+                    return;
+                }
+                if let Some(delay) = ready_delay {
+                    mark_ready_after(delayed, delay).await;
+                }
+            });
+            file_server
+        } else {
+            let file_server = Arc::new(FileServer::new(&config)?);
+            if let Some(delay) = config.ready_delay {
+                tokio::spawn(mark_ready_after(Arc::clone(&file_server), delay));
+            }
+            file_server
+        };
+
+        if let Some(debounce) = config.watch {
+            let dir = match &config.dir {
+                Some(dir) if Path::new(dir).is_dir() && !config.archive => dir,
+                _ => {
+                    return Err(Error::Config(String::from(
+                        "--watch can only watch a directory",
+                    )))
+                }
+            };
+            if !config.fallback_dirs.is_empty() {
+                return Err(Error::Config(String::from(
+                    "--watch can only watch a single directory",
+                )));
+            }
+            let virtual_paths = config.virtual_files.iter().map(|(path, _)| path.clone());
+            watch::spawn(
+                Arc::clone(&file_server),
+                Path::new(dir),
+                debounce,
+                virtual_paths.collect(),
+            )?;
+        }
+
+        if config.maintenance_page.is_some() {
+            tokio::spawn(toggle_maintenance_on_signal(Arc::clone(&file_server)));
+        }
+
+        let stats = Arc::new(Stats::default());
+        if let Some(interval) = config.stats_interval {
+            tokio::spawn(Arc::clone(&stats).report(interval));
+        }
+
+        let vhosts = Arc::new(VirtualHosts::new(&config, file_server)?);
+
+        // Dropped when `serve` returns, which shuts the admin API down as well
+        let (admin_shutdown, admin_shutdown_rx) = oneshot::channel::<()>();
+        let admin = match config.admin_port {
+            Some(port) => {
+                let admin = Admin::new(Arc::clone(&vhosts), &config);
+                let addr = SocketAddr::from((config.admin_address, port));
+                let listener = bind("admin API", addr)?;
+                let shutdown = async {
+                    let _ = admin_shutdown_rx.await;
+                };
+                Some(tokio::spawn(admin.serve(listener, shutdown)))
+            }
+            None => None,
+        };
+        let connections = config
+            .max_connections
+            .map(|max| Arc::new(Semaphore::new(max)));
+        let per_ip = config
+            .max_concurrent_per_ip
+            .map(|max| Arc::new(PerIpLimit::new(max)));
+        let keep_alive_max_requests = config.keep_alive_max_requests;
+        let proxy_headers = config.proxy_headers;
+
+        let make_svc = make_service_fn(move |conn: &Connection| {
+            let vhosts = Arc::clone(&vhosts);
+            let stats = Arc::clone(&stats);
+            let remote_addr = conn.remote_addr();
+            let connection_span = ConnectionSpan::new(remote_addr);
+            let activity = conn.activity();
+            let per_ip = per_ip.clone();
+            // Held for as long as the connection is open
+            let permit = connections
+                .as_ref()
+                .map(|connections| Arc::clone(connections).try_acquire_owned());
+            let mut requests = 0;
+            async move {
+                Ok::<_, Infallible>(service_fn(move |mut req: Request<Body>| {
+                    activity.request_received();
+                    let client = proxy_headers.client(req.headers()).unwrap_or(remote_addr);
+                    req.extensions_mut().insert(RemoteAddr(client));
+                    let file_server = Arc::clone(vhosts.select(&req));
+                    let stats = Arc::clone(&stats);
+                    let connection_span = connection_span.clone();
+                    let overloaded = matches!(permit, Some(Err(_)));
+                    // Held until the response is ready
+                    let in_flight = per_ip
+                        .as_ref()
+                        .map(|per_ip| per_ip.try_acquire(client.ip()));
+                    // HTTP/2 multiplexes requests over one connection, which can't be closed this way
+                    requests += 1;
+                    let last_request = keep_alive_max_requests.is_some_and(|max| requests >= max)
+                        && req.version() <= Version::HTTP_11;
+                    async move {
+                        let method = req.method().clone();
+                        let path = req.uri().path().to_string();
+                        let mut response = if overloaded {
+                            warn!("{} {} 503 (too many connections)", method, path);
+                            overloaded_response()
+                        } else if matches!(in_flight, Some(None)) {
+                            warn!(
+                                "{} {} 429 (too many concurrent requests from {})",
+                                method,
+                                path,
+                                client.ip()
+                            );
+                            too_many_requests_response()
+                        } else {
+                            let description = format!("{} {}", method, path);
+                            let handled = file_server.handle(req);
+                            panics::with_request(
+                                description,
+                                connection_span.instrument(&method, &path, handled),
+                            )
+                            .await?
+                        };
+                        if last_request {
+                            response
+                                .headers_mut()
+                                .insert(CONNECTION, HeaderValue::from_static("close"));
+                        }
+                        stats.record(&method, response.status());
+                        drop(in_flight);
+                        Ok::<_, Infallible>(response)
+                    }
+                }))
+            }
+        });
+
+        let listener = if config.reuse_port || config.listen_backlog.is_some() {
+            let backlog = config.listen_backlog.unwrap_or(1024);
+            listener(addr, config.reuse_port, backlog)?
+        } else {
+            bind("files", addr)?
+        };
+        let listener = tokio::net::TcpListener::from_std(listener)
+            .map_err(Error::io("Unable to listen on socket"))?;
+        let incoming = AddrIncoming::from_listener(listener).map_err(|e| Error::Io {
+            context: String::from("Unable to listen on socket"),
+            source: io::Error::other(e),
+        })?;
+        if let Some(on_bound) = on_bound {
+            on_bound(incoming.local_addr());
+        }
+        let builder = Server::builder(Incoming::new(incoming, config.connection_idle_timeout));
+        // Only written once the port is bound, so supervisors don't see a PID before we're serving
+        if let Some(pid_file) = &config.pid_file {
+            std::fs::write(pid_file, format!("{}\n", std::process::id())).map_err(Error::io(
+                format!("Unable to write PID file {}", pid_file.display()),
+            ))?;
+        }
+        let server = builder.serve(make_svc).with_graceful_shutdown(shutdown);
+
+        let result = tokio::select! {
+            result = server => result.map_err(Error::Server),
+            // Once the sender is dropped, the files were loaded (or aren't loaded in the background)
+            Ok(error) = load_failed => Err(error),
+        };
+        drop(admin_shutdown);
+        if let Some(admin) = admin {
+            // So that the port is free once `serve` returns
+            let _ = admin.await;
+        }
+        if let Some(pid_file) = &config.pid_file {
+            if let Err(e) = std::fs::remove_file(pid_file) {
+                error!("Unable to remove PID file {}: {}", pid_file.display(), e);
+            }
+        }
+        result
+    }
+}
+
+/// Report ready once `delay` has passed, for `--ready-delay`. The task is simply dropped when
+/// shutting down in the meantime.
+async fn mark_ready_after(file_server: Arc<FileServer>, delay: Duration) {
+    info!("Reporting ready in {:?}", delay);
+    tokio::time::sleep(delay).await;
+    file_server.mark_ready();
+    info!("Ready");
+}
+
+/// The response to requests beyond `--max-concurrent-per-ip`. Unlike with too many connections,
+/// the connection stays open, as the client's other requests will complete soon.
+fn too_many_requests_response() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header(RETRY_AFTER, "1")
+        .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(Body::from("Too many concurrent requests, try again later"))
+        .expect("Unable to create `http::Response`")
+}
+
+/// The response on connections beyond `--max-connections`. Unlike the `503` while files are being
+/// loaded, the connection is closed afterwards, to make room for others.
+fn overloaded_response() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header(RETRY_AFTER, "1")
+        .header(CONNECTION, "close")
+        .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(Body::from("Too many connections, try again later"))
+        .expect("Unable to create `http::Response`")
+}
+
+/// Toggle maintenance mode whenever the process receives `SIGUSR1`.
+#[cfg(unix)]
+async fn toggle_maintenance_on_signal(file_server: Arc<FileServer>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut signals = match signal(SignalKind::user_defined1()) {
+        Ok(signals) => signals,
+        Err(e) => {
+            error!(
+                "Unable to handle SIGUSR1, maintenance mode can't be toggled: {}",
+                e
+            );
+            return;
+        }
+    };
+    while signals.recv().await.is_some() {
+        if file_server.toggle_maintenance() {
+            info!("Entering maintenance mode");
+        } else {
+            info!("Leaving maintenance mode");
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn toggle_maintenance_on_signal(_file_server: Arc<FileServer>) {
+    warn!("Maintenance mode can only be toggled with SIGUSR1 on Unix");
+}
+
+/// Create a listener with a queue of `backlog` pending connections, optionally with
+/// `SO_REUSEPORT` set, which lets the kernel balance connections between all processes bound to
+/// the same address.
+fn listener(
+    addr: SocketAddr,
+    reuse_port: bool,
+    backlog: i32,
+) -> Result<std::net::TcpListener, Error> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))
+        .map_err(Error::io("Unable to create socket"))?;
+    socket
+        .set_reuse_address(true)
+        .map_err(Error::io("Unable to set SO_REUSEADDR"))?;
+    if reuse_port {
+        set_reuse_port(&socket)?;
+    }
+    socket.bind(&addr.into()).map_err(|source| Error::Bind {
+        what: "files",
+        addr,
+        source,
+    })?;
+    socket
+        .listen(backlog)
+        .map_err(Error::io("Unable to listen on socket"))?;
+    socket
+        .set_nonblocking(true)
+        .map_err(Error::io("Unable to make socket non-blocking"))?;
+    Ok(socket.into())
+}
+
+/// Bind `addr` to serve `what`, in non-blocking mode for Tokio.
+fn bind(what: &'static str, addr: SocketAddr) -> Result<std::net::TcpListener, Error> {
+    let listener =
+        std::net::TcpListener::bind(addr).map_err(|source| Error::Bind { what, addr, source })?;
+    listener
+        .set_nonblocking(true)
+        .map_err(Error::io("Unable to make socket non-blocking"))?;
+    Ok(listener)
+}
+
+#[cfg(unix)]
+fn set_reuse_port(socket: &Socket) -> Result<(), Error> {
+    socket
+        .set_reuse_port(true)
+        .map_err(Error::io("Unable to set SO_REUSEPORT"))
+}
+
+#[cfg(not(unix))]
+fn set_reuse_port(_socket: &Socket) -> Result<(), Error> {
+    Err(Error::Config(String::from(
+        "--reuse-port is only supported on Unix",
+    )))
+}
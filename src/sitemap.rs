@@ -0,0 +1,48 @@
+//! Generate `/sitemap.xml` and `/robots.txt` for `--generate-sitemap`.
+
+use crate::path;
+
+/// A sitemap listing the HTML files among `paths`, as absolute URLs below `site_url`. With the
+/// `index_fallback`, an `index.html` is listed as its directory, which is where it is served as
+/// well.
+pub fn sitemap<'a>(
+    site_url: &str,
+    paths: impl Iterator<Item = &'a str>,
+    index_fallback: bool,
+) -> String {
+    let mut pages: Vec<&str> = paths
+        .filter(|path| path.ends_with(".html") || path.ends_with(".htm"))
+        .map(|path| match path.strip_suffix("index.html") {
+            Some(dir) if index_fallback => dir,
+            _ => path,
+        })
+        .collect();
+    pages.sort_unstable();
+    let mut sitemap = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for page in pages {
+        let url = format!("{}{}", site_url, path::percent_encode(page));
+        sitemap.push_str(&format!("  <url><loc>{}</loc></url>\n", escape_xml(&url)));
+    }
+    sitemap.push_str("</urlset>\n");
+    sitemap
+}
+
+/// A `robots.txt` which allows everything and points crawlers to the sitemap.
+pub fn robots(site_url: &str) -> String {
+    format!(
+        "User-agent: *\nAllow: /\nSitemap: {}/sitemap.xml\n",
+        site_url
+    )
+}
+
+pub(crate) fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
@@ -0,0 +1,78 @@
+//! Counts of requests by method and status, logged every `--stats-interval` seconds.
+
+use hyper::{Method, StatusCode};
+use log::info;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Request counters which are periodically logged and reset by `report`.
+#[derive(Default)]
+pub struct Stats {
+    get: AtomicU64,
+    head: AtomicU64,
+    other_method: AtomicU64,
+    status_2xx: AtomicU64,
+    status_3xx: AtomicU64,
+    status_4xx: AtomicU64,
+    status_5xx: AtomicU64,
+}
+
+impl Stats {
+    pub fn record(&self, method: &Method, status: StatusCode) {
+        let method_counter = match *method {
+            Method::GET => &self.get,
+            Method::HEAD => &self.head,
+            _ => &self.other_method,
+        };
+        method_counter.fetch_add(1, Ordering::Relaxed);
+
+        let status_counter = match status.as_u16() {
+            200..=299 => &self.status_2xx,
+            300..=399 => &self.status_3xx,
+            400..=499 => &self.status_4xx,
+            500..=599 => &self.status_5xx,
+            _ => return,
+        };
+        status_counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Log a summary of the counters every `interval`, resetting them after each summary.
+    pub async fn report(self: Arc<Self>, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        // The first tick completes immediately, skip it so that the first summary covers a full
+        // interval.
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            info!(
+                "stats: {} in last {}s",
+                self.take_summary(),
+                interval.as_secs()
+            );
+        }
+    }
+
+    fn take_summary(&self) -> String {
+        let counters = [
+            (&self.get, "GET"),
+            (&self.head, "HEAD"),
+            (&self.other_method, "other"),
+            (&self.status_2xx, "2xx"),
+            (&self.status_3xx, "3xx"),
+            (&self.status_4xx, "4xx"),
+            (&self.status_5xx, "5xx"),
+        ];
+        let parts: Vec<String> = counters
+            .iter()
+            .map(|(counter, label)| (counter.swap(0, Ordering::Relaxed), label))
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, label)| format!("{} {}", count, label))
+            .collect();
+        if parts.is_empty() {
+            String::from("no requests")
+        } else {
+            parts.join(", ")
+        }
+    }
+}
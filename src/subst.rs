@@ -0,0 +1,61 @@
+//! Substitution of placeholders like `{{VERSION}}` in text files, for `--subst`.
+
+use crate::error::Error;
+
+/// The placeholders to replace in text files, along with their values.
+pub struct Substitutions {
+    replacements: Vec<(String, String)>,
+}
+
+impl Substitutions {
+    /// Create the substitutions from pairs of name and value. The placeholder for a name is
+    /// `pattern` with `NAME` replaced by that name, e.g. `{{NAME}}`.
+    pub fn new(substitutions: &[(String, String)], pattern: &str) -> Result<Substitutions, Error> {
+        if !pattern.contains("NAME") {
+            return Err(Error::Config(format!(
+                "The substitution pattern {} must contain NAME",
+                pattern
+            )));
+        }
+        Ok(Substitutions {
+            replacements: substitutions
+                .iter()
+                .map(|(name, value)| (pattern.replace("NAME", name), value.clone()))
+                .collect(),
+        })
+    }
+
+    /// Whether files of `content_type` are subject to substitution.
+    pub fn applies_to(&self, content_type: &str) -> bool {
+        !self.replacements.is_empty() && content_type.starts_with("text/")
+    }
+
+    /// The `content` with all placeholders replaced, if it is text. Other content, and text which
+    /// is not valid UTF-8, is returned as is.
+    pub fn apply(&self, content_type: &str, content: Vec<u8>) -> Vec<u8> {
+        if !self.applies_to(content_type) {
+            return content;
+        }
+        let mut text = match String::from_utf8(content) {
+            Ok(text) => text,
+            Err(e) => return e.into_bytes(),
+        };
+        for (placeholder, value) in &self.replacements {
+            if text.contains(placeholder.as_str()) {
+                text = text.replace(placeholder.as_str(), value);
+            }
+        }
+        text.into_bytes()
+    }
+}
+
+/// Parse a substitution of the form `NAME=VALUE`.
+pub fn parse_substitution(value: &str) -> Result<(String, String), Error> {
+    match value.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err(Error::Config(format!(
+            "Invalid --subst {}, expected the form NAME=VALUE",
+            value
+        ))),
+    }
+}
@@ -0,0 +1,94 @@
+//! Optional `tracing` instrumentation, enabled with the `tracing` feature. Without the feature
+//! all of this compiles down to nothing and logging goes through `simplelog` as usual.
+
+use hyper::{Body, Method, Response};
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+
+/// Install a plain-text `tracing` subscriber, which also receives everything logged via `log`.
+/// Returns false if the `tracing` feature is disabled.
+#[cfg(feature = "tracing")]
+pub fn init_subscriber() -> bool {
+    use tracing_subscriber::filter::Targets;
+    use tracing_subscriber::prelude::*;
+
+    // Hyper's own events are very chatty at debug level, keep the output comparable to the
+    // `simplelog` configuration
+    let filter = Targets::new()
+        .with_default(tracing::Level::DEBUG)
+        .with_target("hyper", tracing::Level::INFO);
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .with(filter)
+        .init();
+    true
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn init_subscriber() -> bool {
+    false
+}
+
+/// The span covering all requests made on a single connection.
+#[derive(Clone)]
+pub struct ConnectionSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl ConnectionSpan {
+    #[cfg(feature = "tracing")]
+    pub fn new(remote_addr: SocketAddr) -> ConnectionSpan {
+        ConnectionSpan {
+            span: tracing::info_span!("connection", remote = %remote_addr),
+        }
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub fn new(_remote_addr: SocketAddr) -> ConnectionSpan {
+        ConnectionSpan {}
+    }
+
+    /// Run `handler` inside a request span, recording the response status and latency on it.
+    #[cfg(feature = "tracing")]
+    pub async fn instrument<F>(
+        &self,
+        method: &Method,
+        path: &str,
+        handler: F,
+    ) -> Result<Response<Body>, Infallible>
+    where
+        F: Future<Output = Result<Response<Body>, Infallible>>,
+    {
+        use tracing::field::Empty;
+        use tracing::Instrument;
+
+        let span = tracing::info_span!(
+            parent: &self.span,
+            "request",
+            method = %method,
+            path = %path,
+            status = Empty,
+            latency_us = Empty,
+        );
+        let start = std::time::Instant::now();
+        let response = handler.instrument(span.clone()).await?;
+        span.record("status", response.status().as_u16());
+        span.record("latency_us", start.elapsed().as_micros() as u64);
+        Ok(response)
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub async fn instrument<F>(
+        &self,
+        _method: &Method,
+        _path: &str,
+        handler: F,
+    ) -> Result<Response<Body>, Infallible>
+    where
+        F: Future<Output = Result<Response<Body>, Infallible>>,
+    {
+        handler.await
+    }
+}
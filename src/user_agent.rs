@@ -0,0 +1,83 @@
+//! `--ua-rule`, which serves files from another directory to the user agents matching a pattern,
+//! e.g. a legacy bundle to old browsers.
+
+use crate::error::Error;
+use regex::Regex;
+
+/// The rules of `--ua-rule`, of which the first matching one applies.
+pub struct UserAgentRules {
+    rules: Vec<(Pattern, String)>,
+}
+
+enum Pattern {
+    Substring(String),
+    Regex(Regex),
+}
+
+impl UserAgentRules {
+    /// Rules from pairs of pattern and path prefix. Patterns starting with `~` are regular
+    /// expressions, others substrings of the `User-Agent`.
+    pub fn new(rules: &[(String, String)]) -> Result<UserAgentRules, Error> {
+        Ok(UserAgentRules {
+            rules: rules
+                .iter()
+                .map(|(pattern, prefix)| {
+                    let pattern = match pattern.strip_prefix('~') {
+                        Some(regex) => Pattern::Regex(compile(regex)?),
+                        None => Pattern::Substring(pattern.clone()),
+                    };
+                    Ok((pattern, prefix.clone()))
+                })
+                .collect::<Result<_, Error>>()?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The prefix of the first rule matching `user_agent`, if any.
+    pub fn prefix(&self, user_agent: &str) -> Option<&str> {
+        self.rules
+            .iter()
+            .find(|(pattern, _)| match pattern {
+                Pattern::Substring(substring) => user_agent.contains(substring.as_str()),
+                Pattern::Regex(regex) => regex.is_match(user_agent),
+            })
+            .map(|(_, prefix)| prefix.as_str())
+    }
+}
+
+/// Parse a rule of the form `PATTERN=>PREFIX`, e.g. `MSIE=>/legacy`. The prefix is a request
+/// path, which is returned without a trailing `/`.
+pub fn parse_ua_rule(value: &str) -> Result<(String, String), Error> {
+    let (pattern, prefix) = match value.rsplit_once("=>") {
+        Some((pattern, prefix)) if !pattern.is_empty() => (pattern, prefix),
+        _ => {
+            return Err(Error::Config(format!(
+                "Invalid --ua-rule {}, expected the form PATTERN=>PREFIX",
+                value
+            )))
+        }
+    };
+    if let Some(regex) = pattern.strip_prefix('~') {
+        compile(regex)?;
+    }
+    let prefix = prefix.trim_end_matches('/');
+    if !prefix.starts_with('/') {
+        return Err(Error::Config(format!(
+            "The prefix in --ua-rule {} must be a path starting with /",
+            value
+        )));
+    }
+    Ok((pattern.to_string(), prefix.to_string()))
+}
+
+fn compile(regex: &str) -> Result<Regex, Error> {
+    Regex::new(regex).map_err(|e| {
+        Error::Config(format!(
+            "Invalid regular expression {} in --ua-rule: {}",
+            regex, e
+        ))
+    })
+}
@@ -0,0 +1,208 @@
+//! Checks for combinations of options which contradict each other, or which make an option
+//! pointless, so they can be reported at startup rather than surprise at runtime.
+
+use crate::error::Error;
+use crate::{archive, Config};
+use hyper::StatusCode;
+use std::path::Path;
+
+/// A problem with the combination of options in a `Config`.
+pub enum Problem {
+    /// The options can't be used together, and the server shouldn't start
+    Conflict(String),
+    /// An option has no effect with the others
+    NoEffect(String),
+}
+
+impl Config {
+    /// Find every problem with the combination of options, in no particular order of importance.
+    pub fn validate(&self) -> Vec<Problem> {
+        let mut problems = Vec::new();
+        let mut conflict = |message: String| problems.push(Problem::Conflict(message));
+
+        let is_archive = self
+            .dir
+            .as_deref()
+            .is_some_and(|dir| self.archive || archive::has_archive_extension(Path::new(dir)));
+        for (flag, enabled) in [
+            ("--no-cache", self.no_cache),
+            ("--watch", self.watch.is_some()),
+            ("--writable", self.writable),
+        ] {
+            if !enabled {
+                continue;
+            }
+            if self.dir.is_none() {
+                conflict(format!("{} requires a directory to serve", flag));
+            } else if is_archive {
+                conflict(format!("{} can't be used with an archive", flag));
+            } else if flag != "--writable" && !self.fallback_dirs.is_empty() {
+                conflict(format!(
+                    "{} only supports a single directory, but --dir was given {} times",
+                    flag,
+                    self.fallback_dirs.len() + 1
+                ));
+            }
+        }
+        if self.stdin_tar && self.dir.is_some() {
+            conflict(String::from(
+                "--stdin-tar serves the archive on standard input instead of a directory",
+            ));
+        }
+        if self.strip_prefix_on_disk.is_some() || self.add_url_prefix.is_some() {
+            for (flag, enabled) in [
+                ("--no-cache", self.no_cache),
+                ("--watch", self.watch.is_some()),
+                ("--writable", self.writable),
+                ("--dir-per-request", self.dir_per_request),
+            ] {
+                if enabled {
+                    conflict(format!(
+                        "--strip-prefix-on-disk and --add-url-prefix apply to files loaded at \
+                         startup, so they can't be combined with {}",
+                        flag
+                    ));
+                }
+            }
+        }
+        if self.dir_per_request {
+            if self.dir.is_none() {
+                conflict(String::from(
+                    "--dir-per-request requires a directory to serve",
+                ));
+            }
+            if self.writable {
+                conflict(String::from(
+                    "--dir-per-request can't be combined with --writable, which isn't confined to \
+                     namespaces",
+                ));
+            }
+            if self.watch.is_some() {
+                conflict(String::from(
+                    "--dir-per-request can't be combined with --watch, which isn't confined to \
+                     namespaces",
+                ));
+            }
+        }
+        if let (Some(health), Some(status)) = (&self.health_path, &self.status_path) {
+            if health == status {
+                conflict(format!(
+                    "--health-path and --status-path are both {}, so the status would never be \
+                     served",
+                    health
+                ));
+            }
+        }
+        if self.canonical_host.is_some() && !self.vhosts.is_empty() {
+            conflict(String::from(
+                "--canonical-host redirects every other host, including those of --vhost",
+            ));
+        }
+        if self.local_only {
+            let admin_address = self.admin_port.map(|_| self.admin_address);
+            for (flag, address) in [
+                ("--address", Some(self.address)),
+                ("--admin-address", admin_address),
+            ] {
+                match address {
+                    Some(address) if !address.is_loopback() => conflict(format!(
+                        "--local-only refuses to listen on {} (from {}), which isn't a loopback \
+                         address",
+                        address, flag
+                    )),
+                    _ => {}
+                }
+            }
+        }
+        if self.admin_port == Some(self.port) {
+            conflict(format!(
+                "--admin-port {} is the port the files are served on",
+                self.port
+            ));
+        }
+        if let Some(root_document) = &self.root_document {
+            if !root_document.starts_with('/') {
+                conflict(format!(
+                    "--root-document {} must be a request path starting with /",
+                    root_document
+                ));
+            }
+        }
+
+        let mut no_effect = |message: &str| problems.push(Problem::NoEffect(message.to_string()));
+        let redirects = self.redirect_http || self.canonical_host.is_some();
+        if !redirects && self.redirect_status != StatusCode::MOVED_PERMANENTLY {
+            no_effect("--redirect-status only applies to --redirect-http and --canonical-host");
+        }
+        if !redirects && self.no_redirect_body {
+            no_effect("--no-redirect-body only applies to --redirect-http and --canonical-host");
+        }
+        if !redirects && self.proto_header != Config::default().proto_header {
+            no_effect("--proto-header only applies to --redirect-http and --canonical-host");
+        }
+        if self.no_cache && self.default_language.is_some() {
+            no_effect("--negotiate-language only finds variants in the cache, not with --no-cache");
+        }
+        if self.no_cache && self.source_maps {
+            no_effect("--source-maps only finds source maps in the cache, not with --no-cache");
+        }
+        if self.no_index_fallback
+            && (self.index_files != Config::default().index_files
+                || !self.dir_index_files.is_empty())
+        {
+            no_effect("--index and --dir-index don't apply with --no-index-fallback");
+        }
+        if self.cors_allow_origins.is_empty()
+            && (!self.cors_allow_methods.is_empty()
+                || !self.cors_allow_headers.is_empty()
+                || self.cors_reflect_headers
+                || self.cors_max_age.is_some())
+        {
+            no_effect("The other --cors-* options only apply to --cors-allow-origin");
+        }
+        if !self.no_cache && !self.preload_paths.is_empty() {
+            no_effect("--preload-path only makes a difference with --no-cache");
+        }
+        if self.admin_port.is_none()
+            && (self.admin_auth.is_some() || self.admin_address != Config::default().admin_address)
+        {
+            no_effect("--admin-address and --admin-auth only apply to --admin-port");
+        }
+        if !self.precompressed_zstd && self.encoding_order != Config::default().encoding_order {
+            no_effect("--encoding-order only makes a difference with --precompressed-zstd");
+        }
+        if self.substitutions.is_empty() && self.subst_pattern != Config::default().subst_pattern {
+            no_effect("--subst-pattern only applies to --subst");
+        }
+        let compression = &self.compression;
+        let default_compression = Config::default().compression;
+        if !compression.enabled
+            && (compression.min_size != default_compression.min_size
+                || compression.gzip_level != default_compression.gzip_level)
+        {
+            no_effect("--compress-min-size and --gzip-level only apply to --compress");
+        }
+        problems
+    }
+
+    /// Refuse options which contradict each other, naming every conflict. Options without effect
+    /// aren't an error.
+    pub fn check_conflicts(&self) -> Result<(), Error> {
+        let conflicts: Vec<String> = self
+            .validate()
+            .into_iter()
+            .filter_map(|problem| match problem {
+                Problem::Conflict(message) => Some(message),
+                Problem::NoEffect(_) => None,
+            })
+            .collect();
+        if conflicts.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Config(format!(
+                "Refusing to start with conflicting options: {}",
+                conflicts.join("; ")
+            )))
+        }
+    }
+}
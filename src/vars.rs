@@ -0,0 +1,55 @@
+//! Expansion of environment variables in option values and redirect rules, such as
+//! `${PUBLIC_HOST}` or `${PORT:-3000}`, so one command line or rules file works across
+//! environments.
+
+use std::env;
+
+/// Replace every `${NAME}` in `value` with the environment variable `NAME`, and every
+/// `${NAME:-DEFAULT}` with `DEFAULT` if `NAME` is unset or empty. `$${` stands for a literal
+/// `${`. A `$` which isn't followed by `{` is kept as it is. Returns an error for variables which
+/// are unset without a default, or a `${` without its `}`.
+pub fn expand(value: &str) -> Result<String, String> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(escaped) = rest.strip_prefix("$${") {
+            expanded.push_str("${");
+            rest = escaped;
+            continue;
+        }
+        let reference = match rest.strip_prefix("${") {
+            Some(reference) => reference,
+            None => {
+                expanded.push('$');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        let end = reference
+            .find('}')
+            .ok_or_else(|| format!("missing }} after ${{{}", reference))?;
+        let (name, default) = match reference[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&reference[..end], None),
+        };
+        if name.is_empty() || !name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') {
+            return Err(format!(
+                "invalid variable name in ${{{}}}",
+                &reference[..end]
+            ));
+        }
+        match (
+            env::var(name).ok().filter(|value| !value.is_empty()),
+            default,
+        ) {
+            (Some(value), _) => expanded.push_str(&value),
+            (None, Some(default)) => expanded.push_str(default),
+            (None, None) => return Err(format!("environment variable {} is not set", name)),
+        }
+        rest = &reference[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
@@ -0,0 +1,206 @@
+//! Name-based virtual hosts, which serve a different directory depending on the `Host` of a
+//! request.
+
+use crate::error::Error;
+use crate::{Config, FileServer};
+use hyper::header::HOST;
+use hyper::{Body, Request};
+use log::info;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub struct VirtualHosts {
+    exact: HashMap<String, Arc<FileServer>>,
+    /// Hosts like `*.example.com` by their suffix (`.example.com`), longest first
+    wildcards: Vec<(String, Arc<FileServer>)>,
+    /// The server for requests which match none of the hosts
+    default: Arc<FileServer>,
+    /// Every server, the default one first, with the configuration it was created from
+    servers: Vec<(Config, Arc<FileServer>)>,
+}
+
+impl VirtualHosts {
+    /// Create and load a file server for each of the configured virtual hosts, which serves that
+    /// host's directory with otherwise the same configuration.
+    pub fn new(config: &Config, default: Arc<FileServer>) -> Result<VirtualHosts, Error> {
+        let mut exact = HashMap::new();
+        let mut wildcards = Vec::new();
+        let mut servers = vec![(config.clone(), Arc::clone(&default))];
+        for (host, dir) in &config.vhosts {
+            info!("Serving {} for host {}", dir, host);
+            let vhost_config = Config {
+                dir: Some(dir.clone()),
+                fallback_dirs: Vec::new(),
+                ready_delay: None,
+                ..config.clone()
+            };
+            let host = host.to_lowercase();
+            if host.starts_with('*') && !host.starts_with("*.") {
+                return Err(Error::Config(format!(
+                    "Invalid virtual host {}, wildcard hosts must be of the form *.example.com",
+                    host
+                )));
+            }
+            let file_server = Arc::new(FileServer::new(&vhost_config)?);
+            servers.push((vhost_config, Arc::clone(&file_server)));
+            match host.strip_prefix('*') {
+                Some(suffix) => wildcards.push((suffix.to_string(), file_server)),
+                None => {
+                    exact.insert(host, file_server);
+                }
+            }
+        }
+        wildcards.sort_by_key(|(suffix, _)| std::cmp::Reverse(suffix.len()));
+        Ok(VirtualHosts {
+            exact,
+            wildcards,
+            default,
+            servers,
+        })
+    }
+
+    /// Every file server, the default one first, along with the configuration to load its files
+    /// again with.
+    pub fn servers(&self) -> &[(Config, Arc<FileServer>)] {
+        &self.servers
+    }
+
+    /// The file server responsible for `req`: the one for its exact host if there is one, else
+    /// the one for the most specific matching wildcard, else the default.
+    pub fn select(&self, req: &Request<Body>) -> &Arc<FileServer> {
+        if self.exact.is_empty() && self.wildcards.is_empty() {
+            return &self.default;
+        }
+        let host = match request_host(req) {
+            Some(host) => host,
+            None => return &self.default,
+        };
+        if let Some(file_server) = self.exact.get(&host) {
+            return file_server;
+        }
+        self.wildcards
+            .iter()
+            .find(|(suffix, _)| host.ends_with(suffix.as_str()) && host.len() > suffix.len())
+            .map_or(&self.default, |(_, file_server)| file_server)
+    }
+}
+
+/// The lowercased host of a request, without the port. As for the https redirect, this comes
+/// from the `Host` header, or the authority in HTTP/2.
+pub(crate) fn request_host(req: &Request<Body>) -> Option<String> {
+    let host = match req.headers().get(HOST) {
+        Some(host) => host.to_str().ok()?,
+        None => req.uri().host()?,
+    };
+    Some(normalize_host(host))
+}
+
+/// A host without the port or a trailing dot, lowercased.
+pub(crate) fn normalize_host(host: &str) -> String {
+    let host = if host.starts_with('[') {
+        // An IPv6 address, which contains colons itself
+        host.split_inclusive(']').next().unwrap_or(host)
+    } else {
+        host.split(':').next().unwrap_or(host)
+    };
+    host.trim_end_matches('.').to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Virtual hosts for `hosts`, each serving an empty directory named after its host.
+    fn vhosts(name: &str, hosts: &[&str]) -> (VirtualHosts, PathBuf) {
+        let root =
+            std::env::temp_dir().join(format!("httpserve-vhosts-{}-{}", std::process::id(), name));
+        let mut config = Config::default();
+        for (i, host) in hosts.iter().enumerate() {
+            let dir = root.join(i.to_string());
+            std::fs::create_dir_all(&dir).unwrap();
+            config
+                .vhosts
+                .push((host.to_string(), dir.to_string_lossy().into_owned()));
+        }
+        let default = Arc::new(FileServer::new(&Config::default()).unwrap());
+        (VirtualHosts::new(&config, default).unwrap(), root)
+    }
+
+    /// The configured host chosen for a request with the `host` header (or authority), or `None`
+    /// for the default server.
+    fn select<'a>(vhosts: &VirtualHosts, hosts: &[&'a str], host: &str) -> Option<&'a str> {
+        let req = if host.contains('/') {
+            Request::get(host).body(Body::empty()).unwrap()
+        } else {
+            Request::get("/")
+                .header(HOST, host)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let selected = vhosts.select(&req);
+        let index = vhosts
+            .servers()
+            .iter()
+            .position(|(_, file_server)| Arc::ptr_eq(file_server, selected))
+            .unwrap();
+        index.checked_sub(1).map(|i| hosts[i])
+    }
+
+    #[test]
+    fn normalizes_hosts() {
+        assert_eq!(normalize_host("Example.COM"), "example.com");
+        assert_eq!(normalize_host("example.com:8080"), "example.com");
+        assert_eq!(normalize_host("example.com."), "example.com");
+        assert_eq!(normalize_host("example.com.:8080"), "example.com");
+        assert_eq!(normalize_host("[2001:DB8::1]:443"), "[2001:db8::1]");
+        assert_eq!(normalize_host("[::1]"), "[::1]");
+    }
+
+    #[test]
+    fn prefers_exact_hosts_and_then_the_longest_wildcard() {
+        let hosts = [
+            "*.example.com",
+            "www.example.com",
+            "*.api.example.com",
+            "[::1]",
+            "Example.org",
+        ];
+        let (vhosts, root) = vhosts("precedence", &hosts);
+        let select = |host| select(&vhosts, &hosts, host);
+        assert_eq!(select("www.example.com"), Some("www.example.com"));
+        assert_eq!(select("WWW.Example.com.:3000"), Some("www.example.com"));
+        assert_eq!(select("blog.example.com"), Some("*.example.com"));
+        assert_eq!(select("v1.api.example.com"), Some("*.api.example.com"));
+        assert_eq!(select("api.example.com"), Some("*.example.com"));
+        assert_eq!(select("[::1]:3000"), Some("[::1]"));
+        assert_eq!(select("example.org"), Some("Example.org"));
+        // From the authority, as in HTTP/2
+        assert_eq!(select("http://a.example.com:3000/"), Some("*.example.com"));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn falls_back_to_the_default() {
+        let hosts = ["*.example.com", "example.org"];
+        let (vhosts, root) = vhosts("default", &hosts);
+        let select = |host| select(&vhosts, &hosts, host);
+        assert_eq!(select("example.com"), None);
+        assert_eq!(select(".example.com"), None);
+        assert_eq!(select("www.example.org"), None);
+        assert_eq!(select("other.net"), None);
+        let req = Request::get("/").body(Body::empty()).unwrap();
+        assert!(Arc::ptr_eq(vhosts.select(&req), &vhosts.servers()[0].1));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn rejects_wildcards_without_a_dot() {
+        let config = Config {
+            vhosts: vec![(String::from("*example.com"), String::from("."))],
+            ..Config::default()
+        };
+        let default = Arc::new(FileServer::new(&Config::default()).unwrap());
+        assert!(VirtualHosts::new(&config, default).is_err());
+    }
+}
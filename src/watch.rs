@@ -0,0 +1,141 @@
+//! Keep the cache up to date with the served directory, enabled with `--watch`.
+
+use crate::error::Error;
+use crate::{cache_key, FileServer, SourceFile};
+use log::{debug, warn};
+use notify::event::{AccessKind, AccessMode, EventKind};
+use notify::{Event, RecursiveMode, Watcher};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Watch `dir` on a separate thread and update the cache of `file_server` when files change.
+/// Events are coalesced until none have arrived for `debounce`, so that a build writing many
+/// files results in a single cache update. Request paths in `ignored` (the virtual files) are
+/// never touched.
+pub fn spawn(
+    file_server: Arc<FileServer>,
+    dir: &Path,
+    debounce: Duration,
+    ignored: HashSet<String>,
+) -> Result<(), Error> {
+    let root = fs::canonicalize(dir).map_err(Error::io(format!(
+        "Unable to resolve the served directory {}",
+        dir.display()
+    )))?;
+    let (sender, receiver) = mpsc::channel();
+    let watch_error = |e: notify::Error| Error::Io {
+        context: format!("Unable to watch {}", root.display()),
+        source: io::Error::other(e),
+    };
+    let mut watcher = notify::recommended_watcher(sender).map_err(watch_error)?;
+    watcher
+        .watch(&root, RecursiveMode::Recursive)
+        .map_err(watch_error)?;
+    thread::spawn(move || {
+        // The watcher stops when it is dropped
+        let _watcher = watcher;
+        // Paths which changed, or which were still being written to when they were last read
+        let mut changed = HashSet::new();
+        loop {
+            if changed.is_empty() {
+                match receiver.recv() {
+                    Ok(event) => collect(event, &mut changed),
+                    Err(_) => return,
+                }
+            }
+            loop {
+                match receiver.recv_timeout(debounce) {
+                    Ok(event) => collect(event, &mut changed),
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+            let changes = read_changes(&root, &mut changed, &ignored);
+            if !changes.is_empty() {
+                file_server.update(changes);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn collect(event: notify::Result<Event>, changed: &mut HashSet<PathBuf>) {
+    match event {
+        // Opening and reading files (including our own reads) doesn't change them
+        Ok(Event {
+            kind: EventKind::Access(access),
+            ..
+        }) if access != AccessKind::Close(AccessMode::Write) => {}
+        Ok(event) => changed.extend(event.paths),
+        Err(e) => warn!("File watcher error: {}", e),
+    }
+}
+
+/// Read the current state of the changed paths below `root`. A changed directory is read as a
+/// whole, and paths which no longer exist map to `None`. Files which are still being written
+/// to are left in `changed` to be read again later, so that the previous content keeps being
+/// served instead of a partial file.
+fn read_changes(
+    root: &Path,
+    changed: &mut HashSet<PathBuf>,
+    ignored: &HashSet<String>,
+) -> HashMap<String, Option<SourceFile>> {
+    let mut changes = HashMap::new();
+    let mut to_visit: VecDeque<PathBuf> = changed.drain().collect();
+    while let Some(path) = to_visit.pop_front() {
+        if !path.starts_with(root) || path == root {
+            continue;
+        }
+        let key = match cache_key(root, &path) {
+            Ok(key) => key,
+            Err(e) => {
+                warn!("Ignoring change: {}", e);
+                continue;
+            }
+        };
+        if ignored.contains(&key) {
+            continue;
+        }
+        if path.is_dir() {
+            match fs::read_dir(&path) {
+                Ok(children) => to_visit.extend(children.filter_map(|c| c.ok()).map(|c| c.path())),
+                Err(e) => warn!("Failed to read directory {}: {}", key, e),
+            }
+            continue;
+        }
+        match read_file(&path) {
+            Ok(Some(file)) => {
+                debug!("Reloaded {}", key);
+                changes.insert(key, Some(file));
+            }
+            Ok(None) => {
+                debug!("{} is still being written, reading it again later", key);
+                changed.insert(path);
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                debug!("Removed {}", key);
+                changes.insert(key, None);
+            }
+            Err(e) => warn!("Failed to reload {}: {}", key, e),
+        }
+    }
+    changes
+}
+
+/// Read a file, or return `None` if it changed while reading it.
+fn read_file(path: &Path) -> io::Result<Option<SourceFile>> {
+    let before = fs::metadata(path)?;
+    let content = fs::read(path)?;
+    let after = fs::metadata(path)?;
+    let modified = after.modified().ok();
+    if content.len() as u64 != after.len() || before.modified().ok() != modified {
+        return Ok(None);
+    }
+    Ok(Some(SourceFile { content, modified }))
+}
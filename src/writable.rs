@@ -0,0 +1,179 @@
+//! `PUT` and `DELETE` requests for `--writable`, which store and remove files in the served
+//! directory.
+
+use crate::{is_not_found, path, FileServer, SourceFile};
+use hyper::body::HttpBody;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use log::{error, info, warn};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+
+impl FileServer {
+    /// Handle a `PUT` or `DELETE` request, if the server is writable.
+    pub(crate) async fn modify(&self, req: Request<Body>) -> Response<Body> {
+        let dir = match &self.writable_dir {
+            Some(dir) => dir,
+            None => return status(StatusCode::METHOD_NOT_ALLOWED),
+        };
+        // Rather than resolve `..`, which the client may expect to lead out of the directory,
+        // reject it
+        if path::has_parent_segment(req.uri().path()) {
+            warn!(
+                "Refusing {} {} with a parent segment",
+                req.method(),
+                req.uri().path()
+            );
+            return status(StatusCode::FORBIDDEN);
+        }
+        let path = match self.request_path(req.uri().path()) {
+            Some(path) if !path.ends_with('/') => path,
+            _ => return status(StatusCode::BAD_REQUEST),
+        };
+        let file_path = dir.join(&path[1..]);
+        let result = if req.method() == Method::PUT {
+            self.put(dir, &path, &file_path, req.into_body()).await
+        } else {
+            self.delete(dir, &path, &file_path).await
+        };
+        match result {
+            Ok(code) => status(code),
+            Err(e) => {
+                error!("Failed to modify {}: {}", path, e);
+                status(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+
+    async fn put(
+        &self,
+        dir: &Path,
+        path: &str,
+        file_path: &Path,
+        mut body: Body,
+    ) -> io::Result<StatusCode> {
+        let parent = file_path.parent().unwrap_or(dir);
+        if !is_within(dir, parent).await? {
+            warn!("Refusing to write {} outside of the served directory", path);
+            return Ok(StatusCode::FORBIDDEN);
+        }
+        // The `Content-Length`, if the client sent one
+        if body.size_hint().lower() > self.max_upload_size {
+            warn!("Refusing to store {}, which is too large", path);
+            return Ok(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+        tokio::fs::create_dir_all(parent).await?;
+        let existed = tokio::fs::metadata(file_path).await.is_ok();
+
+        // Write to a temporary file first, so that a failed upload doesn't leave a partial file
+        let mut temp_name = file_path.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(".httpserve-upload");
+        let temp_path = file_path.with_file_name(temp_name);
+        // Also removes the temporary file if the request is cancelled, e.g. by a timeout
+        let mut temp_guard = TempFile(Some(temp_path.clone()));
+        let mut size = 0;
+        let written = async {
+            let mut temp = tokio::fs::File::create(&temp_path).await?;
+            while let Some(chunk) = body.data().await {
+                let chunk = chunk.map_err(io::Error::other)?;
+                size += chunk.len() as u64;
+                if size > self.max_upload_size {
+                    return Ok(false);
+                }
+                temp.write_all(&chunk).await?;
+            }
+            temp.flush().await?;
+            Ok::<_, io::Error>(true)
+        };
+        if !written.await? {
+            warn!("Refusing to store {}, which is too large", path);
+            return Ok(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+        tokio::fs::rename(&temp_path, file_path).await?;
+        temp_guard.0 = None;
+        info!("Stored {} bytes at {}", size, path);
+
+        self.update_written(path, Some(file_path)).await?;
+        Ok(if existed {
+            StatusCode::NO_CONTENT
+        } else {
+            StatusCode::CREATED
+        })
+    }
+
+    async fn delete(&self, dir: &Path, path: &str, file_path: &Path) -> io::Result<StatusCode> {
+        let metadata = match tokio::fs::symlink_metadata(file_path).await {
+            Ok(metadata) => metadata,
+            Err(e) if is_not_found(&e) => return Ok(StatusCode::NOT_FOUND),
+            Err(e) => return Err(e),
+        };
+        if metadata.is_dir() {
+            return Ok(StatusCode::NOT_FOUND);
+        }
+        let parent = file_path.parent().unwrap_or(dir);
+        if !is_within(dir, parent).await? {
+            warn!(
+                "Refusing to delete {} outside of the served directory",
+                path
+            );
+            return Ok(StatusCode::FORBIDDEN);
+        }
+        tokio::fs::remove_file(file_path).await?;
+        info!("Deleted {}", path);
+        self.update_written(path, None).await?;
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    /// Apply a change to the cache: the file stored at `file_path`, or none for a deleted one.
+    /// Without a cache, the next request reads the file anyway. The file is read back rather than
+    /// kept while receiving it, so that uploads aren't held in memory twice.
+    async fn update_written(&self, path: &str, file_path: Option<&Path>) -> io::Result<()> {
+        if self.uncached_dir.is_none() {
+            let file = match file_path {
+                Some(file_path) => Some(SourceFile {
+                    content: tokio::fs::read(file_path).await?,
+                    modified: tokio::fs::metadata(file_path).await?.modified().ok(),
+                }),
+                None => None,
+            };
+            let mut changes = HashMap::new();
+            changes.insert(path.to_string(), file);
+            self.update(changes);
+        }
+        Ok(())
+    }
+}
+
+/// Whether `path` (or, if it doesn't exist yet, its closest existing ancestor) is within `dir`
+/// once symlinks are resolved. Request paths are normalized, so they can only lead outside of
+/// the served directory through a symlink.
+async fn is_within(dir: &Path, path: &Path) -> io::Result<bool> {
+    let mut existing = path;
+    while tokio::fs::metadata(existing).await.is_err() {
+        match existing.parent() {
+            Some(parent) => existing = parent,
+            None => break,
+        }
+    }
+    let existing: PathBuf = tokio::fs::canonicalize(existing).await?;
+    Ok(existing.starts_with(dir))
+}
+
+/// Removes the temporary file of an upload when dropped, unless it has been renamed.
+struct TempFile(Option<PathBuf>);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Some(path) = &self.0 {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+fn status(code: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(code)
+        .body(Body::empty())
+        .expect("Unable to create `http::Response`")
+}
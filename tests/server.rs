@@ -0,0 +1,1134 @@
+//! Tests against a server bound to an ephemeral port, serving a temporary directory, with real
+//! HTTP requests over TCP.
+
+use bytes::Bytes;
+use httpserve::error::Error;
+use httpserve::server::ServerBuilder;
+use httpserve::{Config, EtagMode};
+use hyper::header::{
+    HeaderName, ACCEPT_ENCODING, ACCEPT_RANGES, ACCESS_CONTROL_ALLOW_HEADERS,
+    ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE,
+    ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD, ALLOW, AUTHORIZATION,
+    CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, HOST, IF_NONE_MATCH,
+    LOCATION, ORIGIN, RANGE, USER_AGENT, VARY,
+};
+use hyper::{Body, Client, HeaderMap, Method, Request, StatusCode};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::oneshot;
+
+/// Large enough to be compressed with `--compress`.
+const STYLE: &str = "body { color: red; }\n";
+
+const FILES: &[(&str, &str)] = &[
+    ("index.html", "<h1>Home</h1>"),
+    ("about.txt", "About us"),
+    ("docs/index.html", "<h1>Docs</h1>"),
+    ("guide/README.html", "<h1>Guide</h1>"),
+    ("guide/v1/index.html", "<h1>Guide v1</h1>"),
+    ("legacy/about.txt", "About us, for old browsers"),
+    ("Readme.txt", "Read me"),
+    ("tenant-a/page.txt", "Tenant A"),
+    ("tenant-b/secret.txt", "Tenant B"),
+];
+
+/// A server which stops when dropped, along with a copy of `FILES` (and `style.css`).
+struct TestServer {
+    addr: SocketAddr,
+    root: PathBuf,
+    _shutdown: oneshot::Sender<()>,
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Start a server for a fresh copy of the files, with the config changed by `configure`.
+async fn start(configure: impl FnOnce(&mut Config)) -> TestServer {
+    static SERVERS: AtomicUsize = AtomicUsize::new(0);
+    let root = std::env::temp_dir().join(format!(
+        "httpserve-test-{}-{}",
+        std::process::id(),
+        SERVERS.fetch_add(1, Ordering::Relaxed)
+    ));
+    for (path, content) in FILES {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+    fs::write(root.join("style.css"), STYLE.repeat(200)).unwrap();
+
+    let mut config = Config {
+        dir: Some(root.to_string_lossy().into_owned()),
+        port: 0,
+        ..Config::default()
+    };
+    configure(&mut config);
+    let (bound_tx, bound_rx) = oneshot::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let server = ServerBuilder::new(config)
+        .with_graceful_shutdown(async {
+            let _ = shutdown_rx.await;
+        })
+        .on_bound(|addr| {
+            let _ = bound_tx.send(addr);
+        });
+    tokio::spawn(server.serve());
+    TestServer {
+        addr: bound_rx.await.expect("The server didn't start"),
+        root,
+        _shutdown: shutdown_tx,
+    }
+}
+
+struct Reply {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl Reply {
+    fn header(&self, name: HeaderName) -> &str {
+        self.headers
+            .get(&name)
+            .unwrap_or_else(|| panic!("No {} header", name))
+            .to_str()
+            .unwrap()
+    }
+}
+
+async fn request(
+    server: &TestServer,
+    method: Method,
+    path: &str,
+    headers: &[(HeaderName, &str)],
+) -> Reply {
+    send(server, method, path, headers, Body::empty()).await
+}
+
+async fn send(
+    server: &TestServer,
+    method: Method,
+    path: &str,
+    headers: &[(HeaderName, &str)],
+    body: Body,
+) -> Reply {
+    let mut req = Request::builder()
+        .method(method)
+        .uri(format!("http://{}{}", server.addr, path));
+    for (name, value) in headers {
+        req = req.header(name, *value);
+    }
+    let response = Client::new()
+        .request(req.body(body).unwrap())
+        .await
+        .unwrap();
+    let (parts, body) = response.into_parts();
+    Reply {
+        status: parts.status,
+        headers: parts.headers,
+        body: hyper::body::to_bytes(body).await.unwrap(),
+    }
+}
+
+async fn get(server: &TestServer, path: &str) -> Reply {
+    request(server, Method::GET, path, &[]).await
+}
+
+/// Send `request` as it is and read the response until the server closes the connection.
+async fn raw(server: &TestServer, request: &[u8]) -> String {
+    let mut stream = TcpStream::connect(server.addr).await.unwrap();
+    stream.write_all(request).await.unwrap();
+    let mut response = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut response))
+        .await
+        .expect("The server didn't close the connection")
+        .unwrap();
+    String::from_utf8_lossy(&response).into_owned()
+}
+
+#[tokio::test]
+async fn serves_files() {
+    let server = start(|_| {}).await;
+    let reply = get(&server, "/about.txt").await;
+    assert_eq!(reply.status, StatusCode::OK);
+    assert_eq!(reply.header(CONTENT_TYPE), "text/plain; charset=utf-8");
+    assert_eq!(reply.header(CONTENT_LENGTH), "8");
+    assert_eq!(reply.body, "About us");
+}
+
+#[tokio::test]
+async fn serves_the_same_files_whether_dir_ends_in_a_slash_or_not() {
+    for trailing_slash in [false, true] {
+        let server = start(|config| {
+            if trailing_slash {
+                config.dir.as_mut().unwrap().push('/');
+            }
+        })
+        .await;
+        let reply = get(&server, "/docs/index.html").await;
+        assert_eq!(reply.status, StatusCode::OK, "{}", trailing_slash);
+        assert_eq!(reply.body, "<h1>Docs</h1>");
+    }
+}
+
+#[tokio::test]
+async fn serves_index_files_for_directories() {
+    let server = start(|_| {}).await;
+    assert_eq!(get(&server, "/").await.body, "<h1>Home</h1>");
+    assert_eq!(get(&server, "/docs/").await.body, "<h1>Docs</h1>");
+    // Resolved before the index fallback
+    assert_eq!(get(&server, "/docs/./").await.body, "<h1>Docs</h1>");
+    assert_eq!(get(&server, "/docs//").await.body, "<h1>Docs</h1>");
+}
+
+#[tokio::test]
+async fn serves_the_root_index_over_http_2() {
+    let server = start(|_| {}).await;
+    let client = Client::builder().http2_only(true).build_http::<Body>();
+    for uri in [
+        format!("http://{}/", server.addr),
+        format!("http://{}", server.addr),
+        // Normalized to the root
+        format!("http://{}/.//", server.addr),
+    ] {
+        let response = client.get(uri.parse().unwrap()).await.unwrap();
+        assert_eq!(response.version(), hyper::Version::HTTP_2);
+        assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, "<h1>Home</h1>");
+    }
+}
+
+#[tokio::test]
+async fn serves_the_index_files_of_the_longest_matching_directory() {
+    let server = start(|config| {
+        config.index_files = vec![String::from("about.txt"), String::from("index.html")];
+        config.dir_index_files = vec![(
+            String::from("/guide/"),
+            vec![String::from("README.html"), String::from("index.html")],
+        )];
+    })
+    .await;
+    assert_eq!(get(&server, "/").await.body, "About us");
+    assert_eq!(get(&server, "/docs/").await.body, "<h1>Docs</h1>");
+    assert_eq!(get(&server, "/guide/").await.body, "<h1>Guide</h1>");
+    assert_eq!(get(&server, "/guide/v1/").await.body, "<h1>Guide v1</h1>");
+}
+
+#[tokio::test]
+async fn protects_files_served_for_other_paths() {
+    let server = start(|config| {
+        config.root_document = Some(String::from("/docs/index.html"));
+        config.basic_auth = vec![(
+            String::from("/docs/index.html"),
+            String::from("admin:secret"),
+        )];
+    })
+    .await;
+    // The root document, and the index file of its directory
+    for path in ["/", "/docs/", "/docs/index.html"] {
+        let reply = get(&server, path).await;
+        assert_eq!(reply.status, StatusCode::UNAUTHORIZED, "{}", path);
+        assert!(reply.body.is_empty());
+    }
+    let credentials = [(AUTHORIZATION, "Basic YWRtaW46c2VjcmV0")];
+    let reply = request(&server, Method::GET, "/", &credentials).await;
+    assert_eq!(reply.body, "<h1>Docs</h1>");
+    assert_eq!(get(&server, "/about.txt").await.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn answers_missing_files_with_404() {
+    let server = start(|_| {}).await;
+    assert_eq!(
+        get(&server, "/missing.txt").await.status,
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        get(&server, "/readme.txt").await.status,
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        get(&server, "/about.txt.").await.status,
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn matches_case_and_trailing_dots_when_asked_to() {
+    let server = start(|config| {
+        config.case_insensitive = true;
+        config.strip_trailing_dot = true;
+    })
+    .await;
+    assert_eq!(get(&server, "/readme.txt").await.body, "Read me");
+    assert_eq!(get(&server, "/README.TXT").await.body, "Read me");
+    assert_eq!(get(&server, "/about.txt.").await.body, "About us");
+}
+
+#[tokio::test]
+async fn matches_nested_paths_ignoring_case() {
+    let server = start(|config| config.case_insensitive = true).await;
+    assert_eq!(get(&server, "/DOCS/Index.HTML").await.body, "<h1>Docs</h1>");
+    assert_eq!(
+        get(&server, "/Guide/V1/index.html").await.body,
+        "<h1>Guide v1</h1>"
+    );
+    // Without --strip-trailing-dot, the dot is still part of the name
+    assert_eq!(
+        get(&server, "/ABOUT.TXT.").await.status,
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn strips_trailing_dots_without_ignoring_case() {
+    let server = start(|config| config.strip_trailing_dot = true).await;
+    assert_eq!(get(&server, "/Readme.txt.").await.body, "Read me");
+    assert_eq!(
+        get(&server, "/readme.txt.").await.status,
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn answers_other_methods_with_405() {
+    let server = start(|_| {}).await;
+    let reply = request(&server, Method::POST, "/about.txt", &[]).await;
+    assert_eq!(reply.status, StatusCode::METHOD_NOT_ALLOWED);
+    assert!(reply.header(ALLOW).contains("GET"));
+    assert!(reply.body.is_empty());
+}
+
+#[tokio::test]
+async fn answers_cors_preflight_requests_from_allowed_origins() {
+    let server = start(|config| {
+        config.cors_allow_origins = vec![String::from("https://app.example")];
+        config.cors_allow_headers = vec![String::from("Content-Type"), String::from("X-Token")];
+        config.cors_max_age = Some(600);
+    })
+    .await;
+    let preflight = [
+        (ORIGIN, "https://app.example"),
+        (ACCESS_CONTROL_REQUEST_METHOD, "GET"),
+        (ACCESS_CONTROL_REQUEST_HEADERS, "x-token"),
+    ];
+    let reply = request(&server, Method::OPTIONS, "/about.txt", &preflight).await;
+    assert_eq!(reply.status, StatusCode::NO_CONTENT);
+    assert_eq!(
+        reply.header(ACCESS_CONTROL_ALLOW_ORIGIN),
+        "https://app.example"
+    );
+    assert_eq!(reply.header(ACCESS_CONTROL_ALLOW_METHODS), "GET, HEAD");
+    assert_eq!(
+        reply.header(ACCESS_CONTROL_ALLOW_HEADERS),
+        "Content-Type, X-Token"
+    );
+    assert_eq!(reply.header(ACCESS_CONTROL_MAX_AGE), "600");
+    let vary: Vec<_> = reply.headers.get_all(VARY).iter().collect();
+    assert_eq!(vary, ["Origin"]);
+    assert_eq!(
+        reply
+            .headers
+            .get_all(ACCESS_CONTROL_ALLOW_ORIGIN)
+            .iter()
+            .count(),
+        1
+    );
+    let reply = request(
+        &server,
+        Method::GET,
+        "/about.txt",
+        &[(ORIGIN, "https://app.example")],
+    )
+    .await;
+    assert_eq!(
+        reply.header(ACCESS_CONTROL_ALLOW_ORIGIN),
+        "https://app.example"
+    );
+    // Other origins get neither
+    let preflight = [
+        (ORIGIN, "https://evil.example"),
+        (ACCESS_CONTROL_REQUEST_METHOD, "GET"),
+    ];
+    let reply = request(&server, Method::OPTIONS, "/about.txt", &preflight).await;
+    assert_eq!(reply.status, StatusCode::METHOD_NOT_ALLOWED);
+    assert!(!reply.headers.contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+    let reply = request(
+        &server,
+        Method::GET,
+        "/about.txt",
+        &[(ORIGIN, "https://evil.example")],
+    )
+    .await;
+    assert!(!reply.headers.contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+    assert_eq!(reply.header(VARY), "Origin");
+}
+
+#[tokio::test]
+async fn reflects_requested_cors_headers_when_asked_to() {
+    let server = start(|config| {
+        config.cors_allow_origins = vec![String::from("*")];
+        config.cors_allow_methods = vec![String::from("GET"), String::from("POST")];
+        config.cors_reflect_headers = true;
+    })
+    .await;
+    let preflight = [
+        (ORIGIN, "https://app.example"),
+        (ACCESS_CONTROL_REQUEST_METHOD, "POST"),
+        (ACCESS_CONTROL_REQUEST_HEADERS, "x-token, x-trace"),
+    ];
+    let reply = request(&server, Method::OPTIONS, "/about.txt", &preflight).await;
+    assert_eq!(reply.header(ACCESS_CONTROL_ALLOW_ORIGIN), "*");
+    assert_eq!(reply.header(ACCESS_CONTROL_ALLOW_METHODS), "GET, POST");
+    assert_eq!(
+        reply.header(ACCESS_CONTROL_ALLOW_HEADERS),
+        "x-token, x-trace"
+    );
+    assert_eq!(reply.header(VARY), "Access-Control-Request-Headers");
+}
+
+#[tokio::test]
+async fn rejects_long_uris_with_414() {
+    let server = start(|config| config.max_uri_length = 64).await;
+    let reply = get(&server, &format!("/about.txt?q={}", "x".repeat(60))).await;
+    assert_eq!(reply.status, StatusCode::URI_TOO_LONG);
+    let reply = get(&server, &format!("/about.txt?q={}", "x".repeat(40))).await;
+    assert_eq!(reply.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn serves_other_files_to_matching_user_agents() {
+    let server = start(|config| {
+        config.ua_rules = vec![
+            (String::from("~MSIE [5-9]"), String::from("/legacy")),
+            (String::from("Trident/"), String::from("/legacy")),
+        ]
+    })
+    .await;
+    let old = [(
+        USER_AGENT,
+        "Mozilla/4.0 (compatible; MSIE 6.0; Windows NT 5.1)",
+    )];
+    let reply = request(&server, Method::GET, "/about.txt", &old).await;
+    assert_eq!(reply.body, "About us, for old browsers");
+    assert_eq!(reply.header(VARY), "User-Agent");
+    // Files which aren't under the prefix are served as to everyone else
+    let reply = request(&server, Method::GET, "/Readme.txt", &old).await;
+    assert_eq!(reply.body, "Read me");
+    let new = [(
+        USER_AGENT,
+        "Mozilla/5.0 (X11; Linux x86_64; rv:130.0) Firefox/130.0",
+    )];
+    let reply = request(&server, Method::GET, "/about.txt", &new).await;
+    assert_eq!(reply.body, "About us");
+    assert_eq!(reply.header(VARY), "User-Agent");
+}
+
+#[tokio::test]
+async fn keeps_the_query_string_in_canonical_redirects() {
+    let server = start(|config| config.canonical_host = Some(String::from("example.com"))).await;
+    let response = raw(
+        &server,
+        b"GET /about.txt?lang=en&page=2 HTTP/1.1\r\nHost: www.example.com\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 301"), "{}", response);
+    assert!(
+        response.contains("location: http://example.com/about.txt?lang=en&page=2\r\n"),
+        "{}",
+        response
+    );
+}
+
+#[tokio::test]
+async fn redirects_below_splat_rules() {
+    let server = start(|config| {
+        let rules = PathBuf::from(config.dir.as_ref().unwrap()).join("_redirects");
+        fs::write(&rules, "/blog/* /news/:splat 302\n/old /new#top\n").unwrap();
+        config.redirects_file = Some(rules);
+    })
+    .await;
+    let reply = get(&server, "/blog/2020/post").await;
+    assert_eq!(reply.status, StatusCode::FOUND);
+    assert_eq!(reply.header(LOCATION), "/news/2020/post");
+    let reply = get(&server, "/old").await;
+    assert_eq!(reply.status, StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(reply.header(LOCATION), "/new#top");
+    assert_eq!(get(&server, "/blogs").await.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn answers_options_for_the_server_without_redirecting() {
+    let server = start(|config| {
+        config.redirect_http = true;
+        config.redirect_status = StatusCode::PERMANENT_REDIRECT;
+    })
+    .await;
+    let response = raw(
+        &server,
+        b"OPTIONS * HTTP/1.1\r\nHost: localhost\r\nX-Forwarded-Proto: http\r\n\
+          Connection: close\r\n\r\n",
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 204"), "{}", response);
+    assert!(response.contains("allow: "), "{}", response);
+}
+
+#[tokio::test]
+async fn answers_expect_100_continue_with_the_final_response() {
+    let server = start(|_| {}).await;
+    let response = raw(
+        &server,
+        b"POST /about.txt HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\n\
+          Content-Length: 10\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 405"), "{}", response);
+    assert!(!response.contains("100 Continue"), "{}", response);
+}
+
+#[tokio::test]
+async fn answers_expect_100_continue_for_allowed_methods_without_the_body() {
+    let server = start(|config| {
+        config
+            .allowed_methods
+            .insert(Method::POST, StatusCode::NO_CONTENT);
+    })
+    .await;
+    let response = raw(
+        &server,
+        b"POST /about.txt HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\n\
+          Content-Length: 10\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 204"), "{}", response);
+    assert!(!response.contains("100 Continue"), "{}", response);
+}
+
+#[tokio::test]
+async fn serves_http_1_0() {
+    let server = start(|_| {}).await;
+    let response = raw(&server, b"GET /about.txt HTTP/1.0\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.0 200 OK\r\n"), "{}", response);
+    assert!(response.contains("content-length: 8\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nAbout us"), "{}", response);
+}
+
+#[tokio::test]
+async fn keeps_http_1_0_connections_open_when_asked_to() {
+    let server = start(|_| {}).await;
+    // The second request doesn't ask to keep the connection open, so it's closed after that one
+    let response = raw(
+        &server,
+        b"GET /about.txt HTTP/1.0\r\nConnection: keep-alive\r\n\r\n\
+          GET /Readme.txt HTTP/1.0\r\n\r\n",
+    )
+    .await;
+    assert_eq!(
+        response.matches("HTTP/1.0 200 OK\r\n").count(),
+        2,
+        "{}",
+        response
+    );
+    assert!(
+        response.contains("connection: keep-alive\r\n"),
+        "{}",
+        response
+    );
+    assert!(response.ends_with("\r\n\r\nRead me"), "{}", response);
+}
+
+#[tokio::test]
+async fn rejects_unreadable_hosts_with_400() {
+    let server = start(|_| {}).await;
+    let response = raw(
+        &server,
+        b"GET /about.txt HTTP/1.1\r\nHost: caf\xe9.example\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 400"), "{}", response);
+}
+
+#[tokio::test]
+async fn rejects_other_unreadable_headers_with_400() {
+    let server = start(|config| config.redirect_http = true).await;
+    for header in [
+        &b"Accept-Encoding: gzip\xff"[..],
+        b"If-None-Match: \"caf\xe9\"",
+        b"X-Forwarded-Proto: htt\xf0",
+    ] {
+        let mut request = b"GET /about.txt HTTP/1.1\r\nHost: localhost\r\n".to_vec();
+        request.extend_from_slice(header);
+        request.extend_from_slice(b"\r\nConnection: close\r\n\r\n");
+        let response = raw(&server, &request).await;
+        assert!(
+            response.starts_with("HTTP/1.1 400"),
+            "{}: {}",
+            String::from_utf8_lossy(header),
+            response
+        );
+    }
+}
+
+#[tokio::test]
+async fn rejects_unsupported_versions_with_505() {
+    let server = start(|_| {}).await;
+    let response = raw(
+        &server,
+        b"GET /about.txt HTTP/3.0\r\nHost: localhost\r\n\r\n",
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 505"), "{}", response);
+}
+
+#[tokio::test]
+async fn answers_only_unknown_versions_with_505() {
+    let server = start(|_| {}).await;
+    let response = raw(
+        &server,
+        b"GET /about.txt HTTP/2.0\r\nHost: localhost\r\n\r\n",
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 505"), "{}", response);
+    let response = raw(
+        &server,
+        b"GET /about.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    // Not a version at all
+    let response = raw(&server, b"GET /about.txt HTTX\r\nHost: localhost\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 400"), "{}", response);
+}
+
+#[tokio::test]
+async fn lets_refused_encodings_override_the_wildcard() {
+    let server = start(|config| config.compression.enabled = true).await;
+    let refused = [(ACCEPT_ENCODING, "gzip;q=0, *")];
+    let reply = request(&server, Method::GET, "/style.css", &refused).await;
+    assert_eq!(reply.status, StatusCode::OK);
+    assert!(!reply.headers.contains_key(CONTENT_ENCODING));
+    assert_eq!(reply.body, STYLE.repeat(200));
+    let wildcard = [(ACCEPT_ENCODING, "br, *;q=0.5")];
+    let reply = request(&server, Method::GET, "/style.css", &wildcard).await;
+    assert_eq!(reply.header(CONTENT_ENCODING), "gzip");
+}
+
+#[tokio::test]
+async fn gives_each_encoding_its_own_etag() {
+    let server = start(|config| config.compression.enabled = true).await;
+    let gzip = [(ACCEPT_ENCODING, "gzip")];
+    let identity = get(&server, "/style.css").await;
+    let compressed = request(&server, Method::GET, "/style.css", &gzip).await;
+    assert_eq!(compressed.header(CONTENT_ENCODING), "gzip");
+    assert_ne!(identity.header(ETAG), compressed.header(ETAG));
+
+    let etag = compressed.header(ETAG);
+    let revalidated = [(ACCEPT_ENCODING, "gzip"), (IF_NONE_MATCH, etag)];
+    let reply = request(&server, Method::GET, "/style.css", &revalidated).await;
+    assert_eq!(reply.status, StatusCode::NOT_MODIFIED);
+    // The uncompressed content is a different representation
+    let reply = request(&server, Method::GET, "/style.css", &[(IF_NONE_MATCH, etag)]).await;
+    assert_eq!(reply.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn compares_conditional_requests_against_the_encoding_served() {
+    let server = start(|config| {
+        config.compression.enabled = true;
+        config.etag_mode = EtagMode::Weak;
+    })
+    .await;
+    let gzip = [(ACCEPT_ENCODING, "gzip")];
+    let identity = get(&server, "/style.css").await;
+    let compressed = request(&server, Method::GET, "/style.css", &gzip).await;
+    assert!(compressed.header(ETAG).starts_with("W/"));
+    assert_ne!(identity.header(ETAG), compressed.header(ETAG));
+
+    // A client with the uncompressed copy which now accepts gzip needs the gzip one
+    let revalidated = [
+        (ACCEPT_ENCODING, "gzip"),
+        (IF_NONE_MATCH, identity.header(ETAG)),
+    ];
+    let reply = request(&server, Method::GET, "/style.css", &revalidated).await;
+    assert_eq!(reply.status, StatusCode::OK);
+    assert_eq!(reply.header(CONTENT_ENCODING), "gzip");
+
+    let revalidated = [
+        (ACCEPT_ENCODING, "gzip"),
+        (IF_NONE_MATCH, compressed.header(ETAG)),
+    ];
+    let reply = request(&server, Method::GET, "/style.css", &revalidated).await;
+    assert_eq!(reply.status, StatusCode::NOT_MODIFIED);
+    assert_eq!(reply.header(ETAG), compressed.header(ETAG));
+    assert!(reply.header(VARY).contains("Accept-Encoding"));
+}
+
+#[tokio::test]
+async fn matches_if_none_match_lists() {
+    let server = start(|_| {}).await;
+    let etag = get(&server, "/about.txt").await.header(ETAG).to_string();
+    let status = |value: String| {
+        let server = &server;
+        async move {
+            request(
+                server,
+                Method::GET,
+                "/about.txt",
+                &[(IF_NONE_MATCH, &value)],
+            )
+            .await
+            .status
+        }
+    };
+    assert_eq!(
+        status(format!("\"other\", {}", etag)).await,
+        StatusCode::NOT_MODIFIED
+    );
+    assert_eq!(
+        status(format!("W/{}", etag)).await,
+        StatusCode::NOT_MODIFIED
+    );
+    assert_eq!(status(String::from("*")).await, StatusCode::NOT_MODIFIED);
+    assert_eq!(status(String::from("\"a,b\", \"c\"")).await, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn matches_etags_across_several_if_none_match_headers() {
+    let server = start(|_| {}).await;
+    let etag = get(&server, "/about.txt").await.header(ETAG).to_string();
+    let weak = format!("W/{}", etag);
+    let headers = [(IF_NONE_MATCH, "\"other\""), (IF_NONE_MATCH, weak.as_str())];
+    let reply = request(&server, Method::GET, "/about.txt", &headers).await;
+    assert_eq!(reply.status, StatusCode::NOT_MODIFIED);
+    assert_eq!(reply.header(ETAG), etag);
+    assert!(reply.body.is_empty());
+    let reply = request(&server, Method::HEAD, "/about.txt", &headers).await;
+    assert_eq!(reply.status, StatusCode::NOT_MODIFIED);
+}
+
+#[tokio::test]
+async fn serves_ranges() {
+    let server = start(|_| {}).await;
+    let reply = request(&server, Method::GET, "/about.txt", &[(RANGE, "bytes=0-4")]).await;
+    assert_eq!(reply.status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(reply.header(ACCEPT_RANGES), "bytes");
+    assert_eq!(reply.header(CONTENT_RANGE), "bytes 0-4/8");
+    assert_eq!(reply.body, "About");
+
+    let reply = request(&server, Method::GET, "/about.txt", &[(RANGE, "bytes=-2")]).await;
+    assert_eq!(reply.body, "us");
+
+    let reply = request(&server, Method::GET, "/about.txt", &[(RANGE, "bytes=100-")]).await;
+    assert_eq!(reply.status, StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(reply.header(CONTENT_RANGE), "bytes */8");
+
+    let reply = request(
+        &server,
+        Method::GET,
+        "/about.txt",
+        &[(RANGE, "bytes=0-0,6-7")],
+    )
+    .await;
+    assert_eq!(reply.status, StatusCode::PARTIAL_CONTENT);
+    assert!(reply
+        .header(CONTENT_TYPE)
+        .starts_with("multipart/byteranges; boundary="));
+    let body = String::from_utf8_lossy(&reply.body);
+    assert!(
+        body.contains("Content-Range: bytes 0-0/8\r\n\r\nA\r\n"),
+        "{}",
+        body
+    );
+    assert!(
+        body.contains("Content-Range: bytes 6-7/8\r\n\r\nus\r\n"),
+        "{}",
+        body
+    );
+}
+
+#[tokio::test]
+async fn combines_overlapping_ranges_and_ignores_too_many() {
+    let server = start(|_| {}).await;
+    let reply = request(
+        &server,
+        Method::GET,
+        "/about.txt",
+        &[(RANGE, "bytes=0-2,1-4")],
+    )
+    .await;
+    assert_eq!(reply.status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(reply.header(CONTENT_RANGE), "bytes 0-4/8");
+    assert_eq!(reply.body, "About");
+
+    let many = (0..17)
+        .map(|i| format!("{}-{}", i * 2, i * 2))
+        .collect::<Vec<_>>()
+        .join(",");
+    let reply = request(
+        &server,
+        Method::GET,
+        "/style.css",
+        &[(RANGE, &format!("bytes={}", many))],
+    )
+    .await;
+    assert_eq!(reply.status, StatusCode::OK);
+    assert_eq!(reply.body.len(), STYLE.len() * 200);
+}
+
+#[tokio::test]
+async fn confines_requests_to_their_directory() {
+    let server = start(|config| config.dir_per_request = true).await;
+    assert_eq!(get(&server, "/tenant-a/page.txt").await.body, "Tenant A");
+    for escape in [
+        "/tenant-a/../tenant-b/secret.txt",
+        "/tenant-a/%2e%2e/tenant-b/secret.txt",
+        "/tenant-a/..%2ftenant-b/secret.txt",
+    ] {
+        let response = raw(
+            &server,
+            format!(
+                "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                escape
+            )
+            .as_bytes(),
+        )
+        .await;
+        assert!(
+            response.starts_with("HTTP/1.1 400"),
+            "{}: {}",
+            escape,
+            response
+        );
+    }
+}
+
+#[tokio::test]
+async fn serves_only_files_inside_existing_namespaces() {
+    let server = start(|config| {
+        config.dir_per_request = true;
+        #[cfg(unix)]
+        {
+            let root = PathBuf::from(config.dir.as_ref().unwrap());
+            std::os::unix::fs::symlink(
+                root.join("tenant-b/secret.txt"),
+                root.join("tenant-a/secret.txt"),
+            )
+            .unwrap();
+        }
+    })
+    .await;
+    for path in [
+        "/tenant-c/page.txt",
+        "/about.txt",
+        "/index.html",
+        "/tenant-a/secret.txt",
+    ] {
+        assert_eq!(
+            get(&server, path).await.status,
+            StatusCode::NOT_FOUND,
+            "{}",
+            path
+        );
+    }
+    assert_eq!(get(&server, "/tenant-b/secret.txt").await.body, "Tenant B");
+}
+
+#[tokio::test]
+async fn answers_head_with_the_length_of_the_get_body() {
+    let server = start(|config| config.compression.enabled = true).await;
+    for accept_encoding in ["gzip", "identity"] {
+        let headers = [(ACCEPT_ENCODING, accept_encoding)];
+        let get = request(&server, Method::GET, "/style.css", &headers).await;
+        let head = request(&server, Method::HEAD, "/style.css", &headers).await;
+        assert_eq!(head.status, StatusCode::OK);
+        assert!(head.body.is_empty());
+        assert_eq!(
+            head.header(CONTENT_LENGTH),
+            get.body.len().to_string(),
+            "{}",
+            accept_encoding
+        );
+        assert_eq!(
+            head.headers.get(CONTENT_ENCODING),
+            get.headers.get(CONTENT_ENCODING)
+        );
+    }
+}
+
+#[tokio::test]
+async fn answers_head_with_the_length_of_precompressed_files() {
+    let server = start(|config| {
+        config.precompressed_gzip = true;
+        let root = PathBuf::from(config.dir.as_ref().unwrap());
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, b"{\"about\": \"us\"}").unwrap();
+        fs::write(root.join("about.json.gz"), encoder.finish().unwrap()).unwrap();
+    })
+    .await;
+    for accept_encoding in ["gzip", "identity"] {
+        let headers = [(ACCEPT_ENCODING, accept_encoding)];
+        let get = request(&server, Method::GET, "/about.json", &headers).await;
+        let head = request(&server, Method::HEAD, "/about.json", &headers).await;
+        if accept_encoding == "gzip" {
+            assert_eq!(get.header(CONTENT_ENCODING), "gzip");
+        }
+        assert!(head.body.is_empty());
+        assert_eq!(
+            head.header(CONTENT_LENGTH),
+            get.body.len().to_string(),
+            "{}",
+            accept_encoding
+        );
+        assert_eq!(
+            head.headers.get(CONTENT_ENCODING),
+            get.headers.get(CONTENT_ENCODING)
+        );
+    }
+}
+
+#[tokio::test]
+async fn reports_startup_failures_as_errors() {
+    let missing = std::env::temp_dir().join("httpserve-test-missing");
+    let config = Config {
+        dir: Some(missing.to_string_lossy().into_owned()),
+        port: 0,
+        ..Config::default()
+    };
+    let error = ServerBuilder::new(config)
+        .serve()
+        .await
+        .expect_err("Served a missing directory");
+    assert!(
+        error.to_string().contains("httpserve-test-missing"),
+        "{}",
+        error
+    );
+
+    let config = Config {
+        port: 0,
+        subst_pattern: String::from("{{KEY}}"),
+        ..Config::default()
+    };
+    let error = ServerBuilder::new(config)
+        .serve()
+        .await
+        .expect_err("Accepted a substitution pattern without NAME");
+    assert!(matches!(error, Error::Config(_)), "{}", error);
+}
+
+#[tokio::test]
+async fn refuses_to_serve_conflicting_options() {
+    let config = Config {
+        port: 0,
+        health_path: Some(String::from("/up")),
+        status_path: Some(String::from("/up")),
+        ..Config::default()
+    };
+    let error = ServerBuilder::new(config)
+        .serve()
+        .await
+        .expect_err("Served conflicting options");
+    assert!(matches!(error, Error::Config(_)), "{}", error);
+    assert!(
+        error.to_string().starts_with(
+            "Refusing to start with conflicting options: --health-path and --status-path"
+        ),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn stops_when_loading_in_the_background_fails() {
+    let missing = std::env::temp_dir().join("httpserve-test-missing-background");
+    let config = Config {
+        dir: Some(missing.to_string_lossy().into_owned()),
+        port: 0,
+        background_load: true,
+        ..Config::default()
+    };
+    let served = tokio::time::timeout(Duration::from_secs(5), ServerBuilder::new(config).serve())
+        .await
+        .expect("The server kept running");
+    let error = served.expect_err("Served a missing directory");
+    assert!(
+        error
+            .to_string()
+            .contains("httpserve-test-missing-background"),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn stores_and_removes_files_when_writable() {
+    let server = start(|config| config.writable = true).await;
+    let put = |path, body: &'static str| send(&server, Method::PUT, path, &[], Body::from(body));
+
+    assert_eq!(
+        put("/new/file.txt", "First").await.status,
+        StatusCode::CREATED
+    );
+    assert_eq!(&get(&server, "/new/file.txt").await.body[..], b"First");
+    assert_eq!(
+        put("/new/file.txt", "Second").await.status,
+        StatusCode::NO_CONTENT
+    );
+    assert_eq!(&get(&server, "/new/file.txt").await.body[..], b"Second");
+    assert_eq!(
+        fs::read_to_string(server.root.join("new/file.txt")).unwrap(),
+        "Second"
+    );
+
+    let delete = |path| request(&server, Method::DELETE, path, &[]);
+    assert_eq!(delete("/new/file.txt").await.status, StatusCode::NO_CONTENT);
+    assert_eq!(
+        get(&server, "/new/file.txt").await.status,
+        StatusCode::NOT_FOUND
+    );
+    assert!(!server.root.join("new/file.txt").exists());
+    assert_eq!(delete("/new/file.txt").await.status, StatusCode::NOT_FOUND);
+    assert_eq!(delete("/missing.txt").await.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn refuses_to_write_outside_of_the_directory() {
+    let server = start(|config| config.writable = true).await;
+    for path in [
+        "/docs/../escape.txt",
+        "/docs/%2e%2e/escape.txt",
+        "/%2E%2E/escape.txt",
+    ] {
+        let reply = send(&server, Method::PUT, path, &[], Body::from("Escaped")).await;
+        assert_eq!(reply.status, StatusCode::FORBIDDEN, "{}", path);
+        let reply = request(&server, Method::DELETE, path, &[]).await;
+        assert_eq!(reply.status, StatusCode::FORBIDDEN, "{}", path);
+    }
+    assert!(!server.root.join("escape.txt").exists());
+    assert!(!server.root.parent().unwrap().join("escape.txt").exists());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn refuses_to_write_through_symlinks_out_of_the_directory() {
+    let server = start(|config| config.writable = true).await;
+    let outside = server.root.with_extension("outside");
+    fs::create_dir_all(&outside).unwrap();
+    fs::write(outside.join("kept.txt"), "Kept").unwrap();
+    std::os::unix::fs::symlink(&outside, server.root.join("link")).unwrap();
+
+    let reply = send(&server, Method::PUT, "/link/new.txt", &[], Body::from("x")).await;
+    assert_eq!(reply.status, StatusCode::FORBIDDEN);
+    let reply = send(
+        &server,
+        Method::PUT,
+        "/link/deeper/new.txt",
+        &[],
+        Body::from("x"),
+    )
+    .await;
+    assert_eq!(reply.status, StatusCode::FORBIDDEN);
+    let reply = request(&server, Method::DELETE, "/link/kept.txt", &[]).await;
+    assert_eq!(reply.status, StatusCode::FORBIDDEN);
+    let mut stored: Vec<_> = fs::read_dir(&outside)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    stored.sort();
+    assert_eq!(stored, ["kept.txt"]);
+    fs::remove_dir_all(&outside).unwrap();
+}
+
+#[tokio::test]
+async fn refuses_uploads_larger_than_the_maximum() {
+    let server = start(|config| {
+        config.writable = true;
+        config.max_upload_size = 4;
+    })
+    .await;
+    let reply = send(&server, Method::PUT, "/big.txt", &[], Body::from("12345")).await;
+    assert_eq!(reply.status, StatusCode::PAYLOAD_TOO_LARGE);
+
+    // Without a `Content-Length`, the size is only known while receiving the body
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        for chunk in ["123", "45"] {
+            if sender.send_data(Bytes::from(chunk)).await.is_err() {
+                break;
+            }
+        }
+    });
+    let reply = send(&server, Method::PUT, "/big.txt", &[], body).await;
+    assert_eq!(reply.status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(get(&server, "/big.txt").await.status, StatusCode::NOT_FOUND);
+    let stored: Vec<_> = fs::read_dir(&server.root)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.starts_with("big.txt"))
+        .collect();
+    assert!(stored.is_empty(), "{:?}", stored);
+
+    let reply = send(&server, Method::PUT, "/big.txt", &[], Body::from("1234")).await;
+    assert_eq!(reply.status, StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn reloads_every_host_through_the_admin_api_and_stops_with_the_server() {
+    let admin_port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut vhost_dir = PathBuf::new();
+    let server = start(|config| {
+        vhost_dir = PathBuf::from(config.dir.as_ref().unwrap()).with_extension("vhost");
+        fs::create_dir_all(&vhost_dir).unwrap();
+        fs::write(vhost_dir.join("page.txt"), "Old page").unwrap();
+        config.vhosts = vec![(
+            String::from("other.example"),
+            vhost_dir.to_string_lossy().into_owned(),
+        )];
+        config.admin_port = Some(admin_port);
+    })
+    .await;
+    let page = || {
+        request(
+            &server,
+            Method::GET,
+            "/page.txt",
+            &[(HOST, "other.example")],
+        )
+    };
+    assert_eq!(&page().await.body[..], b"Old page");
+    fs::write(server.root.join("about.txt"), "New about").unwrap();
+    fs::write(vhost_dir.join("page.txt"), "New page").unwrap();
+
+    let clear = Request::post(format!("http://127.0.0.1:{}/cache/clear", admin_port))
+        .body(Body::empty())
+        .unwrap();
+    let response = Client::new().request(clear).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(&get(&server, "/about.txt").await.body[..], b"New about");
+    assert_eq!(&page().await.body[..], b"New page");
+
+    drop(server);
+    let stopped = async {
+        while std::net::TcpListener::bind(("127.0.0.1", admin_port)).is_err() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), stopped)
+        .await
+        .expect("The admin API kept listening");
+    fs::remove_dir_all(&vhost_dir).unwrap();
+}